@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use glam::Vec3;
+
+use crate::pattern::{Gauge, Node, Pattern};
+
+/// How much yarn is used by each stitch type, as a multiple of the stitch width.
+/// These are typical amounts; tighter or looser crocheters will use less or more.
+const DEFAULT_LENGTHS: [(&str, f32); 9] = [
+    ("ch", 3.0),
+    ("sl", 3.0),
+    ("sc", 5.0),
+    ("hdc", 7.0),
+    ("dc", 8.0),
+    ("tr", 11.0),
+    ("dtr", 14.0),
+    ("dec", 8.0),
+    ("magic_ring", 6.0),
+];
+
+/// Options for estimating how much yarn a pattern uses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct YarnOptions {
+    /// How much yarn each stitch type uses, as a multiple of the stitch width.
+    /// Stitch types that aren't listed don't use any yarn, e.g. chain spaces, which are made of other stitches.
+    pub lengths: BTreeMap<&'static str, f32>,
+    /// The gauge to size stitches by, or `None` to use the pattern's own gauge.
+    pub gauge: Option<Gauge>,
+    /// The length of yarn in 100g, as given on the yarn label, to estimate the weight of yarn used.
+    pub metres_per_100g: Option<f32>,
+}
+
+impl Default for YarnOptions {
+    fn default() -> Self {
+        Self {
+            lengths: DEFAULT_LENGTHS.into_iter().collect(),
+            gauge: None,
+            metres_per_100g: None,
+        }
+    }
+}
+
+/// The yarn used in a single colour.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorUsage {
+    pub color: Vec3,
+    /// The number of stitches of each type worked in this colour.
+    pub stitches: BTreeMap<&'static str, usize>,
+    /// The estimated length of yarn, in metres.
+    pub length: f32,
+    /// The estimated weight of yarn in grams, if the yarn's length per 100g was given.
+    pub weight: Option<f32>,
+}
+
+/// The yarn used by a pattern, and how often the colour changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct YarnUsage {
+    /// The yarn used in each colour, in the order the colours first appear in the pattern.
+    pub colors: Vec<ColorUsage>,
+    /// The number of times the colour changes within each row of each part.
+    pub color_changes: Vec<Vec<usize>>,
+}
+
+impl YarnUsage {
+    /// The estimated length of yarn used in all colours, in metres.
+    pub fn total_length(&self) -> f32 {
+        self.colors.iter().map(|usage| usage.length).sum()
+    }
+
+    /// The estimated weight of yarn used in all colours in grams, if the yarn's length per 100g was given.
+    pub fn total_weight(&self) -> Option<f32> {
+        self.colors.iter().map(|usage| usage.weight).sum()
+    }
+}
+
+/// Format a colour as a hex code, e.g. `#ff8000`.
+pub fn color_hex(color: Vec3) -> String {
+    let [r, g, b] = color
+        .clamp(Vec3::ZERO, Vec3::ONE)
+        .to_array()
+        .map(|c| (c * 255.0).round() as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// The colour a node is worked in. Chain spaces and magic rings don't have a colour of their own.
+fn node_color(node: &Node) -> Option<Vec3> {
+    match node {
+        Node::Stitch { color, .. } => Some(*color),
+        _ => None,
+    }
+}
+
+impl Pattern {
+    /// Estimate the yarn used in each colour, and count the colour changes in each row.
+    pub fn yarn_usage(&self, options: &YarnOptions) -> YarnUsage {
+        let gauge = options.gauge.clone().unwrap_or_else(|| self.gauge());
+        let metres_per_width = gauge.stitch_width() / 1000.0;
+        let graph = self.graph();
+
+        let mut colors: Vec<ColorUsage> = vec![];
+        // a magic ring is made with the yarn of the first stitches worked into it
+        let mut magic_rings = 0;
+        for node in graph.node_weights() {
+            let Some(color) = node_color(node) else {
+                if *node == Node::MagicRing {
+                    magic_rings += 1;
+                }
+                continue;
+            };
+
+            let usage = match colors.iter().position(|usage| usage.color == color) {
+                Some(i) => &mut colors[i],
+                None => {
+                    colors.push(ColorUsage {
+                        color,
+                        stitches: BTreeMap::new(),
+                        length: 0.0,
+                        weight: None,
+                    });
+                    colors.last_mut().unwrap()
+                }
+            };
+            let mut add = |ty: &'static str, count: usize| {
+                *usage.stitches.entry(ty).or_insert(0) += count;
+                usage.length += options.lengths.get(ty).copied().unwrap_or(0.0)
+                    * count as f32
+                    * metres_per_width;
+            };
+            add(node.stitch_type(), 1);
+            if magic_rings > 0 {
+                add("magic_ring", magic_rings);
+                magic_rings = 0;
+            }
+        }
+        for usage in &mut colors {
+            usage.weight = options
+                .metres_per_100g
+                .map(|metres| usage.length / metres * 100.0);
+        }
+
+        let color_changes = self
+            .parts()
+            .iter()
+            .map(|rows| {
+                rows.iter()
+                    .map(|row| {
+                        let colors = row
+                            .iter()
+                            .filter_map(|node| node_color(&graph[*node]))
+                            .collect::<Vec<_>>();
+                        colors.windows(2).filter(|w| w[0] != w[1]).count()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        YarnUsage {
+            colors,
+            color_changes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::test_pattern_flat;
+
+    #[test]
+    fn test_yarn_usage() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        for _ in 0..4 {
+            part.chain().unwrap();
+        }
+        part.turn().unwrap();
+        part.sc().unwrap();
+        part.change_color(Vec3::new(1.0, 0.0, 0.0));
+        part.sc().unwrap();
+        part.sc().unwrap();
+        part.change_color(Vec3::ONE);
+        part.sc().unwrap();
+        drop(part);
+        pattern.set_gauge(Gauge::new(20.0, 20.0));
+
+        let options = YarnOptions {
+            metres_per_100g: Some(200.0),
+            ..Default::default()
+        };
+        let usage = pattern.yarn_usage(&options);
+        assert_eq!(usage.colors.len(), 2);
+        let (white, red) = (&usage.colors[0], &usage.colors[1]);
+        assert_eq!(color_hex(red.color), "#ff0000");
+        assert_eq!(red.stitches, BTreeMap::from([("sc", 2)]));
+        // the start, four chains and a turning chain, and two sc
+        assert_eq!(white.stitches, BTreeMap::from([("ch", 6), ("sc", 2)]));
+
+        // sc use 5 stitch widths of 5mm each
+        assert!((red.length - 2.0 * 5.0 * 0.005).abs() < 1e-6);
+        assert!((red.weight.unwrap() - red.length / 2.0).abs() < 1e-6);
+        assert!((usage.total_length() - (white.length + red.length)).abs() < 1e-6);
+
+        assert_eq!(usage.color_changes, vec![vec![0, 2]]);
+    }
+
+    #[test]
+    fn test_yarn_gauge() {
+        // a looser gauge uses more yarn for the same stitches
+        let pattern = test_pattern_flat(5).unwrap();
+        let tight = pattern.yarn_usage(&YarnOptions {
+            gauge: Some(Gauge::new(25.0, 12.0)),
+            ..Default::default()
+        });
+        let loose = pattern.yarn_usage(&YarnOptions {
+            gauge: Some(Gauge::new(12.5, 6.0)),
+            ..Default::default()
+        });
+        assert!((loose.total_length() - 2.0 * tight.total_length()).abs() < 1e-4);
+        assert_eq!(tight.total_weight(), None);
+    }
+}
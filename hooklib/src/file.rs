@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+
+use glam::Vec3;
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::pattern::{EdgeType, Gauge, InsertMode, Node, PartRows, Pattern, WORKED_STITCHES};
+
+/// The version of the file format written by [`Pattern::to_json`].
+/// This should be increased whenever the format changes in a way older readers can't understand.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Stitch types that aren't worked stitches, but can still appear in a pattern.
+const OTHER_STITCHES: [&str; 2] = ["ch", "dec"];
+
+/// A whole pattern as stored in a file.
+/// Nodes and edges are stored in index order, so node indices are preserved when read back.
+#[derive(Serialize, Deserialize)]
+struct PatternFile {
+    version: u32,
+    nodes: Vec<NodeRecord>,
+    edges: Vec<EdgeRecord>,
+    parts: Vec<Vec<Vec<usize>>>,
+    /// How firmly each part is stuffed, by part index. Left out when no part sets it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stuffing: Vec<Option<f32>>,
+    /// The gauge of the pattern. Left out when it's the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gauge: Option<GaugeRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GaugeRecord {
+    stitches: f32,
+    rows: f32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    heights: BTreeMap<String, f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum NodeRecord {
+    Stitch {
+        ty: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        turn: bool,
+        color: [f32; 3],
+    },
+    ChainSpace,
+    MagicRing,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EdgeRecord {
+    from: usize,
+    to: usize,
+    #[serde(flatten)]
+    ty: EdgeRecordType,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum EdgeRecordType {
+    Previous,
+    Insert {
+        #[serde(default)]
+        mode: InsertModeRecord,
+    },
+    Slip,
+    Neighbour,
+    Sew,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum InsertModeRecord {
+    #[default]
+    BothLoops,
+    FrontLoop,
+    BackLoop,
+    FrontPost,
+    BackPost,
+}
+
+impl From<InsertMode> for InsertModeRecord {
+    fn from(mode: InsertMode) -> Self {
+        match mode {
+            InsertMode::BothLoops => Self::BothLoops,
+            InsertMode::FrontLoop => Self::FrontLoop,
+            InsertMode::BackLoop => Self::BackLoop,
+            InsertMode::FrontPost => Self::FrontPost,
+            InsertMode::BackPost => Self::BackPost,
+        }
+    }
+}
+
+impl From<InsertModeRecord> for InsertMode {
+    fn from(mode: InsertModeRecord) -> Self {
+        match mode {
+            InsertModeRecord::BothLoops => Self::BothLoops,
+            InsertModeRecord::FrontLoop => Self::FrontLoop,
+            InsertModeRecord::BackLoop => Self::BackLoop,
+            InsertModeRecord::FrontPost => Self::FrontPost,
+            InsertModeRecord::BackPost => Self::BackPost,
+        }
+    }
+}
+
+impl From<&Node> for NodeRecord {
+    fn from(node: &Node) -> Self {
+        match *node {
+            Node::Stitch { ty, turn, color } => Self::Stitch {
+                ty: ty.to_string(),
+                turn,
+                color: color.to_array(),
+            },
+            Node::ChainSpace => Self::ChainSpace,
+            Node::MagicRing => Self::MagicRing,
+        }
+    }
+}
+
+impl From<&EdgeType> for EdgeRecordType {
+    fn from(edge: &EdgeType) -> Self {
+        match *edge {
+            EdgeType::Previous => Self::Previous,
+            EdgeType::Insert(mode) => Self::Insert { mode: mode.into() },
+            EdgeType::Slip => Self::Slip,
+            EdgeType::Neighbour => Self::Neighbour,
+            EdgeType::Sew => Self::Sew,
+        }
+    }
+}
+
+impl From<EdgeRecordType> for EdgeType {
+    fn from(edge: EdgeRecordType) -> Self {
+        match edge {
+            EdgeRecordType::Previous => Self::Previous,
+            EdgeRecordType::Insert { mode } => Self::Insert(mode.into()),
+            EdgeRecordType::Slip => Self::Slip,
+            EdgeRecordType::Neighbour => Self::Neighbour,
+            EdgeRecordType::Sew => Self::Sew,
+        }
+    }
+}
+
+/// An error reading a pattern file.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file isn't valid JSON, or doesn't have the expected structure.
+    Json(serde_json::Error),
+    /// The file was written by a newer version of the format.
+    UnsupportedVersion(u32),
+    /// A stitch has a type that isn't known.
+    UnknownStitchType(String),
+    /// An edge or row refers to a node that isn't in the file.
+    InvalidNode(usize),
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(err) => write!(f, "Invalid pattern file: {err}"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Pattern file is version {version}, but only versions up to {FORMAT_VERSION} are supported."
+            ),
+            Self::UnknownStitchType(ty) => write!(f, "Unknown stitch type \"{ty}\" in pattern file."),
+            Self::InvalidNode(index) => write!(f, "Pattern file refers to node {index}, which doesn't exist."),
+        }
+    }
+}
+
+impl Error for FileError {}
+
+impl From<serde_json::Error> for FileError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Find the static name of a stitch type read from a file.
+fn stitch_type(name: &str) -> Result<&'static str, FileError> {
+    WORKED_STITCHES
+        .into_iter()
+        .chain(OTHER_STITCHES)
+        .find(|ty| *ty == name)
+        .ok_or_else(|| FileError::UnknownStitchType(name.to_string()))
+}
+
+impl Pattern {
+    /// Serialize the crochet graph, including the rows of each part, to JSON.
+    pub fn to_json(&self) -> String {
+        let graph = self.graph();
+        let mut file = PatternFile {
+            version: FORMAT_VERSION,
+            nodes: graph.node_weights().map(NodeRecord::from).collect(),
+            edges: graph
+                .raw_edges()
+                .iter()
+                .map(|edge| EdgeRecord {
+                    from: edge.source().index(),
+                    to: edge.target().index(),
+                    ty: (&edge.weight).into(),
+                })
+                .collect(),
+            parts: self
+                .parts()
+                .iter()
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| row.iter().map(|node| node.index()).collect())
+                        .collect()
+                })
+                .collect(),
+            stuffing: (0..self.parts().len())
+                .map(|part| self.stuffing(part))
+                .collect(),
+            gauge: Some(self.gauge())
+                .filter(|gauge| *gauge != Gauge::default())
+                .map(|gauge| GaugeRecord {
+                    stitches: gauge.stitches,
+                    rows: gauge.rows,
+                    heights: gauge
+                        .heights
+                        .into_iter()
+                        .map(|(ty, height)| (ty.to_string(), height))
+                        .collect(),
+                }),
+        };
+        if file.stuffing.iter().all(Option::is_none) {
+            file.stuffing.clear();
+        }
+        serde_json::to_string(&file).expect("Pattern files should always serialize.")
+    }
+
+    /// Read a pattern from JSON written by [`Pattern::to_json`].
+    pub fn from_json(json: &str) -> Result<Pattern, FileError> {
+        let file: PatternFile = serde_json::from_str(json)?;
+        if file.version > FORMAT_VERSION {
+            return Err(FileError::UnsupportedVersion(file.version));
+        }
+
+        let mut graph = DiGraph::with_capacity(file.nodes.len(), file.edges.len());
+        for node in file.nodes {
+            graph.add_node(match node {
+                NodeRecord::Stitch { ty, turn, color } => Node::Stitch {
+                    ty: stitch_type(&ty)?,
+                    turn,
+                    color: Vec3::from_array(color),
+                },
+                NodeRecord::ChainSpace => Node::ChainSpace,
+                NodeRecord::MagicRing => Node::MagicRing,
+            });
+        }
+
+        let node_count = graph.node_count();
+        let node = |index: usize| {
+            if index < node_count {
+                Ok(NodeIndex::new(index))
+            } else {
+                Err(FileError::InvalidNode(index))
+            }
+        };
+
+        for edge in file.edges {
+            graph.add_edge(node(edge.from)?, node(edge.to)?, edge.ty.into());
+        }
+
+        let parts = file
+            .parts
+            .into_iter()
+            .map(|rows| {
+                rows.into_iter()
+                    .map(|row| row.into_iter().map(node).collect())
+                    .collect()
+            })
+            .collect::<Result<Vec<PartRows>, _>>()?;
+
+        let mut stuffing = file.stuffing;
+        stuffing.resize(parts.len(), None);
+
+        let gauge = match file.gauge {
+            Some(gauge) => Gauge {
+                stitches: gauge.stitches,
+                rows: gauge.rows,
+                heights: gauge
+                    .heights
+                    .into_iter()
+                    .map(|(ty, height)| Ok((stitch_type(&ty)?, height)))
+                    .collect::<Result<_, FileError>>()?,
+            },
+            None => Gauge::default(),
+        };
+
+        Ok(Pattern::from_graph(graph, parts, stuffing, gauge))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::test_pattern_sphere;
+
+    #[test]
+    fn test_round_trip() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.chain().unwrap();
+        part.chain().unwrap();
+        part.chain().unwrap();
+        part.turn().unwrap();
+        part.change_color(Vec3::new(0.2, 0.4, 0.6));
+        part.set_insert_mode(InsertMode::BackLoop);
+        part.sc().unwrap();
+        part.set_insert_mode(InsertMode::FrontPost);
+        part.dc().unwrap();
+        part.set_stuffing(0.5);
+        drop(part);
+        let mut gauge = Gauge::new(20.0, 12.0);
+        gauge.heights.insert("sc", 4.5);
+        pattern.set_gauge(gauge.clone());
+        let pattern = pattern.into_inner();
+
+        let read = Pattern::from_json(&pattern.to_json()).unwrap();
+        assert_eq!(read, pattern);
+        assert_eq!(*read.parts(), *pattern.parts());
+        assert_eq!(read.stuffing(0), Some(0.5));
+        assert_eq!(read.gauge(), gauge);
+        assert_eq!(read.graph().edge_count(), pattern.graph().edge_count());
+
+        let sphere = test_pattern_sphere().unwrap();
+        assert_eq!(Pattern::from_json(&sphere.to_json()).unwrap(), sphere);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let newer = format!(
+            r#"{{"version":{},"nodes":[],"edges":[],"parts":[]}}"#,
+            FORMAT_VERSION + 1
+        );
+        assert!(matches!(
+            Pattern::from_json(&newer),
+            Err(FileError::UnsupportedVersion(_))
+        ));
+
+        let unknown = r#"{"version":1,"nodes":[{"kind":"stitch","ty":"puff","color":[0,0,0]}],"edges":[],"parts":[]}"#;
+        assert!(matches!(
+            Pattern::from_json(unknown),
+            Err(FileError::UnknownStitchType(ty)) if ty == "puff"
+        ));
+
+        let dangling = r#"{"version":1,"nodes":[{"kind":"magic_ring"}],"edges":[{"from":0,"to":3,"kind":"previous"}],"parts":[]}"#;
+        assert!(matches!(
+            Pattern::from_json(dangling),
+            Err(FileError::InvalidNode(3))
+        ));
+    }
+}
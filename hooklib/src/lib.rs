@@ -2,11 +2,19 @@
 
 //! Contains all library components for representing and generating crochet patterns.
 
+/// Estimating the yarn used by a pattern
+pub mod analysis;
 /// Example patterns used in testing
 pub mod examples;
+/// The native file format for evaluated patterns
+pub mod file;
 /// The visual scripting component
 pub mod parametric;
 /// The pattern representation and building as a crochet graph
 pub mod pattern;
+/// Named sets of parameter values stored next to a script
+pub mod presets;
 /// The textual scripting component using Rhai
 pub mod script;
+/// Converting patterns to written instructions
+pub mod written;
@@ -7,6 +7,9 @@ use std::{
 
 use elsa::FrozenVec;
 use itertools::Itertools;
+use rhai::{Expr, OptimizationLevel, ScriptFuncDef, Stmt};
+
+use crate::script::PatternScript;
 
 /// A newtype around a shared string to be used for identifier types.
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -35,11 +38,25 @@ pub struct ParametricPattern {
 }
 
 impl ParametricPattern {
-    /// Get a list of all identifiers defined in the script
+    /// Get a list of all identifiers defined in the script, including loop variables and function parameters
     pub fn defined_identifiers(&self) -> Vec<Identifier> {
+        let mut ids = vec![];
+        self.walk(&mut |op| match op {
+            Operation::Define(identifier, _) | Operation::For(identifier, ..) => {
+                ids.push(identifier.clone())
+            }
+            Operation::Function(_, params, _) => ids.extend(params.iter().cloned()),
+            _ => {}
+        });
+
+        ids.into_iter().unique().collect::<Vec<_>>()
+    }
+
+    /// Get a list of all functions defined in the script
+    pub fn defined_functions(&self) -> Vec<Identifier> {
         let mut ids = vec![];
         self.walk(&mut |op| {
-            if let Operation::Define(identifier, _) = op {
+            if let Operation::Function(identifier, ..) = op {
                 ids.push(identifier.clone())
             }
         });
@@ -100,9 +117,25 @@ impl ParametricPattern {
     pub fn variable(&self, name: impl Into<Identifier>) -> OperationRef {
         self.add_node(Operation::Variable(name.into()))
     }
+    /// Create instruction: a literal decimal number.
+    pub fn float(&self, value: f64) -> OperationRef {
+        self.add_node(Operation::Float(value))
+    }
     /// Create instruction: a function call.
-    pub fn call(&self, name: impl Into<Identifier>) -> OperationRef {
-        self.add_node(Operation::Call(name.into()))
+    pub fn call(
+        &self,
+        name: impl Into<Identifier>,
+        args: impl IntoIterator<Item = OperationRef>,
+    ) -> OperationRef {
+        self.add_node(Operation::Call(name.into(), args.into_iter().collect()))
+    }
+    /// Create instruction: an operation on two values.
+    pub fn binary(&self, op: BinaryOp, lhs: OperationRef, rhs: OperationRef) -> OperationRef {
+        self.add_node(Operation::Binary(op, lhs, rhs))
+    }
+    /// Create instruction: a list of values.
+    pub fn array(&self, items: impl IntoIterator<Item = OperationRef>) -> OperationRef {
+        self.add_node(Operation::Array(items.into_iter().collect()))
     }
     /// Create instruction: a group of instructions in sequence.
     pub fn seq(&self, ops: impl IntoIterator<Item = OperationRef>) -> OperationRef {
@@ -112,27 +145,209 @@ impl ParametricPattern {
     pub fn repeat(&self, n: OperationRef, op: OperationRef) -> OperationRef {
         self.add_node(Operation::Repeat(n, op))
     }
+    /// Create instruction: a function definition.
+    pub fn function(
+        &self,
+        name: impl Into<Identifier>,
+        params: impl IntoIterator<Item = Identifier>,
+        body: OperationRef,
+    ) -> OperationRef {
+        self.add_node(Operation::Function(
+            name.into(),
+            params.into_iter().collect(),
+            body,
+        ))
+    }
+    /// Create instruction: a conditional statement.
+    pub fn if_then(
+        &self,
+        condition: OperationRef,
+        then: OperationRef,
+        otherwise: Option<OperationRef>,
+    ) -> OperationRef {
+        self.add_node(Operation::If(condition, then, otherwise))
+    }
+    /// Create instruction: a loop over a range or list.
+    pub fn for_in(
+        &self,
+        name: impl Into<Identifier>,
+        values: OperationRef,
+        body: OperationRef,
+    ) -> OperationRef {
+        self.add_node(Operation::For(name.into(), values, body))
+    }
+    /// Create instruction: code that can't be edited visually, kept as it's written.
+    pub fn raw(&self, code: impl Into<String>) -> OperationRef {
+        self.add_node(Operation::Raw(code.into()))
+    }
 
-    /// Transforms an operation and its children into a textual script.
-    fn op_to_script(&self, op: OperationRef) -> String {
+    /// Transforms an operation used as a statement, and its children, into a textual script,
+    /// indented to the given depth.
+    fn op_to_script(&self, op: OperationRef, depth: usize) -> String {
+        let indent = "    ".repeat(depth);
+        // blocks after a keyword start on the same line as it
+        let block = |op: OperationRef| self.op_to_script(op, depth).trim_start().to_string();
         match &*self.nodes[op.0].borrow() {
-            Operation::Define(name, op) => format!("let {name} = {}", self.op_to_script(*op)),
-            Operation::Literal(value) => format!("{value}"),
-            Operation::Variable(name) => format!("{name}"),
-            Operation::Call(name) => format!("{name}()"),
+            Operation::Define(name, op) => {
+                format!("{indent}let {name} = {};", self.expr_to_script(*op))
+            }
             Operation::Seq(v) => format!(
-                "{{\n{}\n}}",
-                v.iter().map(|op| self.op_to_script(*op)).join(";\n")
+                "{indent}{{\n{}\n{indent}}}",
+                self.seq_to_script(v, depth + 1)
             ),
             Operation::Repeat(n, op) => {
-                format!("rep {} {}", self.op_to_script(*n), self.op_to_script(*op))
+                let n = match &*self.nodes[n.0].borrow() {
+                    Operation::Binary(..) => format!("({})", self.expr_to_script(*n)),
+                    _ => self.expr_to_script(*n),
+                };
+                format!("{indent}rep {n} {};", block(*op))
+            }
+            Operation::Function(name, params, body) => {
+                format!(
+                    "{indent}fn {name}({}) {}",
+                    params.iter().join(", "),
+                    block(*body)
+                )
+            }
+            Operation::If(condition, then, otherwise) => {
+                let mut script = format!(
+                    "{indent}if {} {}",
+                    self.expr_to_script(*condition),
+                    block(*then)
+                );
+                if let Some(otherwise) = otherwise {
+                    // a branch that's only another conditional is written as `else if`
+                    let chained = match &*self.nodes[otherwise.0].borrow() {
+                        Operation::Seq(ops) if ops.len() == 1 => {
+                            matches!(*self.nodes[ops[0].0].borrow(), Operation::If(..))
+                                .then_some(ops[0])
+                        }
+                        _ => None,
+                    };
+                    script += &format!(" else {}", block(chained.unwrap_or(*otherwise)));
+                }
+                script
+            }
+            Operation::For(name, values, body) => format!(
+                "{indent}for {name} in {} {}",
+                self.expr_to_script(*values),
+                block(*body)
+            ),
+            // raw code keeps its own indentation, and is terminated in case it was the last statement of a block
+            Operation::Raw(code) => match without_comment(code) {
+                "" => code.clone(),
+                end if end.ends_with(';') => code.clone(),
+                end if end.ends_with('}') && end.len() == code.len() => format!("{code};"),
+                _ => format!("{code}\n{indent};"),
+            },
+            // values used as statements, e.g. calls
+            _ => format!("{indent}{};", self.expr_to_script(op)),
+        }
+    }
+
+    /// Transforms an operation used as a value, and its children, into a textual script.
+    fn expr_to_script(&self, op: OperationRef) -> String {
+        match &*self.nodes[op.0].borrow() {
+            Operation::Literal(value) => format!("{value}"),
+            Operation::Float(value) => format!("{value:?}"),
+            Operation::Variable(name) => format!("{name}"),
+            Operation::Call(name, args) => format!(
+                "{name}({})",
+                args.iter().map(|arg| self.expr_to_script(*arg)).join(", ")
+            ),
+            Operation::Binary(op, lhs, rhs) => {
+                let lhs = self.operand_to_script(*op, *lhs, false);
+                let rhs = self.operand_to_script(*op, *rhs, true);
+                match op {
+                    BinaryOp::Range | BinaryOp::RangeInclusive => format!("{lhs}{op}{rhs}"),
+                    _ => format!("{lhs} {op} {rhs}"),
+                }
+            }
+            Operation::Array(items) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|item| self.expr_to_script(*item))
+                    .join(", ")
+            ),
+            Operation::Raw(code) => code.trim().to_string(),
+            // statements used as values, e.g. blocks
+            _ => self.op_to_script(op, 0),
+        }
+    }
+
+    /// Transforms an operand of a binary operation into a textual script, in brackets if it needs them.
+    fn operand_to_script(&self, parent: BinaryOp, op: OperationRef, right: bool) -> String {
+        let script = self.expr_to_script(op);
+        let needs_brackets = match &*self.nodes[op.0].borrow() {
+            Operation::Binary(child, ..) => {
+                child.precedence() < parent.precedence()
+                    || (child.precedence() == parent.precedence()
+                        && (right || !parent.is_chainable()))
             }
+            _ => false,
+        };
+        if needs_brackets {
+            format!("({script})")
+        } else {
+            script
         }
     }
 
+    /// Transforms a sequence of operations into lines of a textual script, indented to the given depth.
+    fn seq_to_script(&self, ops: &[OperationRef], depth: usize) -> String {
+        ops.iter()
+            .map(|op| self.op_to_script(*op, depth))
+            .join("\n")
+    }
+
     /// Transforms the whole tree into a textual script.
     pub fn to_script(&self) -> String {
-        self.op_to_script(self.root.expect("No root node"))
+        let root = self.root.expect("No root node");
+        // the top level isn't wrapped in a block, so exports and functions stay global
+        if let Operation::Seq(ops) = &*self.nodes[root.0].borrow() {
+            return self.seq_to_script(ops, 0);
+        }
+        self.op_to_script(root, 0)
+    }
+
+    /// Convert a textual script into operations that can be edited visually.
+    /// Variable definitions, calls, `rep`, `if`, `for`, function definitions, blocks and the values they use are
+    /// converted, and any other code is kept as raw code, along with its comments, so converting the pattern back
+    /// to a script doesn't lose anything.
+    pub fn from_script(source: &str) -> ParametricPattern {
+        let mut pattern = ParametricPattern::default();
+        let mut engine = PatternScript::create_compile_engine();
+        // optimizing would fold expressions, so they'd no longer match the code as it's written
+        engine.set_optimization_level(OptimizationLevel::None);
+
+        let lines = source.lines().collect::<Vec<_>>();
+        let ops = match engine.compile(source) {
+            Ok(ast) => {
+                // functions are kept apart from the statements once parsed, so they're put back in their place
+                let mut items = ast
+                    .statements()
+                    .iter()
+                    .map(Item::Stmt)
+                    .chain(
+                        ast.iter_fn_def()
+                            .filter(|def| !def.name.starts_with(ANONYMOUS_FUNCTION))
+                            .map(|def| Item::Function(def)),
+                    )
+                    .collect::<Vec<_>>();
+                items.sort_by_key(|item| item.first_line());
+                ScriptConverter {
+                    pattern: &pattern,
+                    lines: &lines,
+                }
+                .convert_block(&items, 1, lines.len() + 1)
+            }
+            Err(_) => None,
+        };
+        let ops = ops.unwrap_or_else(|| vec![pattern.raw(source.trim_end())]);
+        let root = pattern.seq(ops);
+        pattern.build(root);
+        pattern
     }
 
     /// Walk an operation and its children, performing the function `f` at each iteration.
@@ -150,6 +365,27 @@ impl ParametricPattern {
                 self.op_walk(*n, f);
                 self.op_walk(*op, f);
             }
+            Operation::Call(_, ops) | Operation::Array(ops) => {
+                for op in ops.iter() {
+                    self.op_walk(*op, f);
+                }
+            }
+            Operation::Binary(_, lhs, rhs) => {
+                self.op_walk(*lhs, f);
+                self.op_walk(*rhs, f);
+            }
+            Operation::Function(.., body) => self.op_walk(*body, f),
+            Operation::If(condition, then, otherwise) => {
+                self.op_walk(*condition, f);
+                self.op_walk(*then, f);
+                if let Some(otherwise) = otherwise {
+                    self.op_walk(*otherwise, f);
+                }
+            }
+            Operation::For(_, values, body) => {
+                self.op_walk(*values, f);
+                self.op_walk(*body, f);
+            }
             _ => {}
         }
     }
@@ -174,6 +410,27 @@ impl ParametricPattern {
                 self.op_walk_mut(*n, f);
                 self.op_walk_mut(*op, f);
             }
+            Operation::Call(_, ops) | Operation::Array(ops) => {
+                for op in ops.iter() {
+                    self.op_walk_mut(*op, f);
+                }
+            }
+            Operation::Binary(_, lhs, rhs) => {
+                self.op_walk_mut(*lhs, f);
+                self.op_walk_mut(*rhs, f);
+            }
+            Operation::Function(.., body) => self.op_walk_mut(*body, f),
+            Operation::If(condition, then, otherwise) => {
+                self.op_walk_mut(*condition, f);
+                self.op_walk_mut(*then, f);
+                if let Some(otherwise) = otherwise {
+                    self.op_walk_mut(*otherwise, f);
+                }
+            }
+            Operation::For(_, values, body) => {
+                self.op_walk_mut(*values, f);
+                self.op_walk_mut(*body, f);
+            }
             _ => {}
         }
     }
@@ -196,12 +453,416 @@ pub enum Operation {
     Literal(u32),
     /// A literal reference
     Variable(Identifier),
-    /// A function call
-    Call(Identifier),
+    /// A literal decimal number
+    Float(f64),
+    /// A function call, with its arguments
+    Call(Identifier, Vec<OperationRef>),
+    /// An arithmetic, comparison or logical operation on two values
+    Binary(BinaryOp, OperationRef, OperationRef),
+    /// A list of values
+    Array(Vec<OperationRef>),
     /// A group of sequential instructions
     Seq(Vec<OperationRef>),
     /// A repetition statement
     Repeat(OperationRef, OperationRef),
+    /// A function definition, with the names of its parameters and its body
+    Function(Identifier, Vec<Identifier>, OperationRef),
+    /// A conditional statement, with a body for when the condition holds and optionally one for when it doesn't
+    If(OperationRef, OperationRef, Option<OperationRef>),
+    /// A loop over the values of a range or list, each given in turn to a variable
+    For(Identifier, OperationRef, OperationRef),
+    /// Code that can't be edited visually, kept as it's written
+    Raw(String),
+}
+
+/// An operator of a binary operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    /// A range of whole numbers, excluding the end
+    Range,
+    /// A range of whole numbers, including the end
+    RangeInclusive,
+}
+
+impl BinaryOp {
+    /// Every operator, in the order they're offered to choose from.
+    pub const ALL: &[BinaryOp] = &[
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::Rem,
+        Self::Eq,
+        Self::Ne,
+        Self::Lt,
+        Self::Le,
+        Self::Gt,
+        Self::Ge,
+        Self::And,
+        Self::Or,
+        Self::Range,
+        Self::RangeInclusive,
+    ];
+
+    /// The operator as it's written in a script.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::And => "&&",
+            Self::Or => "||",
+            Self::Range => "..",
+            Self::RangeInclusive => "..=",
+        }
+    }
+
+    /// The operator written as the given symbol, if there is one.
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|op| op.symbol() == symbol)
+    }
+
+    /// How tightly the operator binds its operands, as in Rhai.
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 30,
+            Self::And => 60,
+            Self::Eq | Self::Ne => 90,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 130,
+            Self::Range | Self::RangeInclusive => 140,
+            Self::Add | Self::Sub => 150,
+            Self::Mul | Self::Div | Self::Rem => 180,
+        }
+    }
+
+    /// Whether operations can be chained without brackets, e.g. `a - b - c`.
+    fn is_chainable(self) -> bool {
+        !matches!(
+            self,
+            Self::Eq
+                | Self::Ne
+                | Self::Lt
+                | Self::Le
+                | Self::Gt
+                | Self::Ge
+                | Self::Range
+                | Self::RangeInclusive
+        )
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// Converts the statements of a parsed script into operations, keeping any it can't convert as raw code.
+struct ScriptConverter<'a> {
+    pattern: &'a ParametricPattern,
+    lines: &'a [&'a str],
+}
+
+/// Whether a line of a script is blank or only a comment.
+fn is_comment_line(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with("//")
+}
+
+/// The code up to the end of its last statement, without the comments after it.
+fn without_comment(code: &str) -> &str {
+    let (mut start, mut end) = (0, 0);
+    for line in code.split_inclusive('\n') {
+        if !is_comment_line(line) {
+            end = start + line.split("//").next().unwrap_or_default().trim_end().len();
+        }
+        start += line.len();
+    }
+    &code[..end]
+}
+
+/// Strip a script of the spacing, separators and brackets that converting it can change,
+/// to check converting it hasn't lost anything like comments.
+fn normalize_script(script: &str) -> String {
+    script
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, ';' | '{' | '}' | '(' | ')'))
+        .collect()
+}
+
+/// The prefix of the names Rhai gives closures, which are parsed as functions.
+const ANONYMOUS_FUNCTION: &str = "anon$";
+
+/// A statement of a block, or a function definition at the top level of a script.
+enum Item<'a> {
+    Stmt(&'a Stmt),
+    Function(&'a ScriptFuncDef),
+}
+
+impl Item<'_> {
+    /// The first line the item is on, which isn't always the line of its position, e.g. for method calls.
+    fn first_line(&self) -> Option<usize> {
+        let stmt = match self {
+            Self::Stmt(stmt) => stmt,
+            Self::Function(def) => return def.body.position().line(),
+        };
+        let mut line = stmt.position().line();
+        stmt.walk(&mut vec![], &mut |path| {
+            if let Some(node_line) = path.last().and_then(|node| node.position().line()) {
+                line = Some(line.map_or(node_line, |line| line.min(node_line)));
+            }
+            true
+        });
+        line
+    }
+}
+
+impl ScriptConverter<'_> {
+    /// Convert the statements of a block, whose code is within the lines `first..end`, starting from 1.
+    /// Statements on lines of their own are kept as raw code if they can't be converted, but if they share lines
+    /// with the code around them, they all have to be converted, or `None` is returned.
+    fn convert_block(&self, items: &[Item], first: usize, end: usize) -> Option<Vec<OperationRef>> {
+        let starts = items
+            .iter()
+            .map(Item::first_line)
+            .collect::<Option<Vec<_>>>();
+        let Some(starts) =
+            starts.filter(|starts| starts.iter().all(|line| (first..end).contains(line)))
+        else {
+            return items
+                .iter()
+                .map(|item| self.convert_item(item, false))
+                .collect();
+        };
+
+        // statements starting on the same line are kept together
+        let mut ops = vec![];
+        let chunks = items.iter().zip(&starts).chunk_by(|(_, line)| **line);
+        let chunks = chunks
+            .into_iter()
+            .map(|(line, chunk)| (line, chunk.map(|(item, _)| item).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        for (i, (line, chunk)) in chunks.iter().enumerate() {
+            let from = if i == 0 { first } else { *line };
+            let to = chunks.get(i + 1).map_or(end, |(next, _)| *next);
+            let lines = &self.lines[from - 1..to - 1];
+            // comments on lines of their own are kept apart from the code, so it can still be converted
+            let code_start = lines
+                .iter()
+                .position(|line| !is_comment_line(line))
+                .unwrap_or(lines.len());
+            let code_end = lines
+                .iter()
+                .rposition(|line| !is_comment_line(line))
+                .map_or(code_start, |i| i + 1);
+            self.push_comments(&mut ops, &lines[..code_start]);
+            let code = lines[code_start..code_end].join("\n");
+            let code = code.as_str();
+
+            let converted = chunk
+                .iter()
+                .map(|item| self.convert_item(item, true))
+                .collect::<Option<Vec<_>>>()
+                .filter(|converted| {
+                    let script = converted
+                        .iter()
+                        .map(|op| self.pattern.op_to_script(*op, 0))
+                        .join("\n");
+                    normalize_script(&script) == normalize_script(code)
+                });
+            match converted {
+                Some(converted) => ops.extend(converted),
+                None => ops.push(self.pattern.raw(code)),
+            }
+            self.push_comments(&mut ops, &lines[code_end..]);
+        }
+        Some(ops)
+    }
+
+    /// Keep lines of comments as raw code, unless they're all blank.
+    fn push_comments(&self, ops: &mut Vec<OperationRef>, lines: &[&str]) {
+        let comments = lines.join("\n");
+        if !comments.trim().is_empty() {
+            ops.push(
+                self.pattern
+                    .raw(comments.trim_end().trim_start_matches('\n')),
+            );
+        }
+    }
+
+    /// Convert a statement or function definition.
+    /// If `allow_raw` is set, the statements of any blocks within it can be kept as raw code.
+    fn convert_item(&self, item: &Item, allow_raw: bool) -> Option<OperationRef> {
+        match item {
+            Item::Stmt(stmt) => self.convert_stmt(stmt, allow_raw),
+            Item::Function(def) if def.access.is_public() && def.this_type.is_none() => {
+                Some(self.pattern.function(
+                    def.name.as_str(),
+                    def.params.iter().map(|param| param.as_str().into()),
+                    self.pattern.seq(self.convert_nested(&def.body, allow_raw)?),
+                ))
+            }
+            Item::Function(_) => None,
+        }
+    }
+
+    /// Convert a statement. If `allow_raw` is set, the statements of any blocks within it can be kept as raw code.
+    fn convert_stmt(&self, stmt: &Stmt, allow_raw: bool) -> Option<OperationRef> {
+        match stmt {
+            Stmt::Var(body, flags, _) if flags.is_empty() => Some(
+                self.pattern
+                    .define(body.0.name.as_str(), self.convert_value(&body.1)?),
+            ),
+            Stmt::FnCall(call, _) => {
+                self.convert_expr(&Expr::FnCall(call.clone(), stmt.position()), allow_raw)
+            }
+            Stmt::Expr(expr) => self.convert_expr(expr, allow_raw),
+            Stmt::Block(block) => Some(self.pattern.seq(self.convert_nested(block, allow_raw)?)),
+            Stmt::If(flow, _) => {
+                let condition = self.convert_value(&flow.expr)?;
+                let then = self
+                    .pattern
+                    .seq(self.convert_nested(&flow.body, allow_raw)?);
+                let otherwise = match flow.branch.statements() {
+                    [] => None,
+                    // `else if` has no braces of its own around the conditional
+                    [stmt @ Stmt::If(..)] if flow.branch.end_position().is_none() => {
+                        Some(self.pattern.seq([self.convert_stmt(stmt, allow_raw)?]))
+                    }
+                    _ => Some(
+                        self.pattern
+                            .seq(self.convert_nested(&flow.branch, allow_raw)?),
+                    ),
+                };
+                Some(self.pattern.if_then(condition, then, otherwise))
+            }
+            Stmt::For(x, _) if x.1.is_none() => {
+                let (name, _, flow) = &**x;
+                Some(
+                    self.pattern.for_in(
+                        name.name.as_str(),
+                        self.convert_value(&flow.expr)?,
+                        self.pattern
+                            .seq(self.convert_nested(&flow.body, allow_raw)?),
+                    ),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert an expression used as a statement, i.e. a call or `rep`.
+    fn convert_expr(&self, expr: &Expr, allow_raw: bool) -> Option<OperationRef> {
+        match expr {
+            Expr::FnCall(call, _) if call.op_token.is_none() => self.convert_value(expr),
+            Expr::Custom(custom, _)
+                if custom.tokens.first().is_some_and(|token| token == "rep") =>
+            {
+                let n = self.convert_value(&custom.inputs[0])?;
+                let body = match &custom.inputs[1] {
+                    Expr::Stmt(block) => self.convert_nested(block, allow_raw)?,
+                    body => vec![self.convert_expr(body, allow_raw)?],
+                };
+                Some(self.pattern.repeat(n, self.pattern.seq(body)))
+            }
+            Expr::Stmt(block) => Some(self.pattern.seq(self.convert_nested(block, allow_raw)?)),
+            _ => None,
+        }
+    }
+
+    /// Convert the statements of a block within a statement, which are between its braces.
+    fn convert_nested(
+        &self,
+        block: &rhai::StmtBlock,
+        allow_raw: bool,
+    ) -> Option<Vec<OperationRef>> {
+        let (Some(open), Some(close)) = (block.position().line(), block.end_position().line())
+        else {
+            return None;
+        };
+        if allow_raw {
+            let items = block
+                .statements()
+                .iter()
+                .map(Item::Stmt)
+                .collect::<Vec<_>>();
+            self.convert_block(&items, open + 1, close)
+        } else {
+            block
+                .statements()
+                .iter()
+                .map(|stmt| self.convert_stmt(stmt, false))
+                .collect()
+        }
+    }
+
+    /// Convert an expression used as a value, i.e. a number, variable, call, binary operation or list.
+    fn convert_value(&self, expr: &Expr) -> Option<OperationRef> {
+        match expr {
+            Expr::IntegerConstant(value, _) => {
+                Some(self.pattern.literal((*value).try_into().ok()?))
+            }
+            Expr::FloatConstant(value, _) => Some(self.pattern.float(**value)),
+            Expr::Variable(x, ..) if x.2.is_empty() => Some(self.pattern.variable(x.1.as_str())),
+            Expr::FnCall(call, _) if call.is_qualified() => None,
+            Expr::FnCall(call, _) if call.op_token.is_some() => match &call.args[..] {
+                [lhs, rhs] => Some(self.pattern.binary(
+                    BinaryOp::from_symbol(call.name.as_str())?,
+                    self.convert_value(lhs)?,
+                    self.convert_value(rhs)?,
+                )),
+                _ => None,
+            },
+            Expr::FnCall(call, _) => Some(
+                self.pattern.call(
+                    call.name.as_str(),
+                    call.args
+                        .iter()
+                        .map(|arg| self.convert_value(arg))
+                        .collect::<Option<Vec<_>>>()?,
+                ),
+            ),
+            Expr::And(x, _) | Expr::Or(x, _) => Some(self.pattern.binary(
+                if matches!(expr, Expr::And(..)) {
+                    BinaryOp::And
+                } else {
+                    BinaryOp::Or
+                },
+                self.convert_value(&x.lhs)?,
+                self.convert_value(&x.rhs)?,
+            )),
+            Expr::Array(items, _) => Some(
+                self.pattern.array(
+                    items
+                        .iter()
+                        .map(|item| self.convert_value(item))
+                        .collect::<Option<Vec<_>>>()?,
+                ),
+            ),
+            _ => None,
+        }
+    }
 }
 
 /// Returns an example parametric pattern representing a flat sheet of crochet.
@@ -212,12 +873,12 @@ pub fn example_flat() -> ParametricPattern {
         p.define("stitches", p.literal(15)),
         p.define("x", p.literal(5)),
         p.define("y", p.literal(6)),
-        p.repeat(p.variable("stitches"), p.seq([p.call("chain")])),
+        p.repeat(p.variable("stitches"), p.seq([p.call("chain", [])])),
         p.repeat(
             p.variable("stitches"),
             p.seq([
-                p.call("turn"),
-                p.repeat(p.variable("stitches"), p.seq([p.call("dc")])),
+                p.call("turn", []),
+                p.repeat(p.variable("stitches"), p.seq([p.call("dc", [])])),
             ]),
         ),
     ]);
@@ -230,9 +891,109 @@ pub fn example_flat() -> ParametricPattern {
 #[cfg(test)]
 mod tests {
     use crate::parametric::*;
+    use crate::script::PatternScript;
 
     #[test]
     fn test_example_flat() {
         println!("{}", example_flat().to_script())
     }
+
+    /// Count the raw code operations in a pattern.
+    fn raw_count(pattern: &ParametricPattern) -> usize {
+        let mut count = 0;
+        pattern.walk(&mut |op| count += matches!(op, Operation::Raw(_)) as usize);
+        count
+    }
+
+    /// Check a script evaluates to the same pattern after converting it to operations and back.
+    fn assert_round_trip(source: &str) -> ParametricPattern {
+        let pattern = ParametricPattern::from_script(source);
+        let script = pattern.to_script();
+        assert_eq!(
+            PatternScript::eval_script(&script.as_str().into()).unwrap(),
+            PatternScript::eval_script(&source.into()).unwrap(),
+            "{script}"
+        );
+        pattern
+    }
+
+    #[test]
+    fn test_from_script() {
+        let pattern = assert_round_trip(
+            "let n = 6;\nrep n chain();\nrep 4 {\n    turn();\n    rep n { dc(); }\n}\n",
+        );
+        assert_eq!(raw_count(&pattern), 0);
+        assert_eq!(pattern.defined_identifiers(), vec![Identifier::from("n")]);
+
+        // a syntax error keeps the whole script as it is
+        let pattern = ParametricPattern::from_script("rep 3 {\n chain();\n");
+        assert_eq!(raw_count(&pattern), 1);
+        assert_eq!(pattern.to_script(), "rep 3 {\n chain();");
+    }
+
+    #[test]
+    fn test_from_script_raw() {
+        let source = include_str!("../examples/spiral_rounds.ph");
+        let pattern = assert_round_trip(source);
+        let script = pattern.to_script();
+        assert!(script.contains("fn inc() {"));
+        assert!(script.contains("    j += 1;"));
+
+        // statements that can't be converted are kept on their own, along with comments
+        let source = "export let n = 4;\n// the base\nrep n chain();\nlet m = -1 + n;\nrep 2 {\n    turn(); // next row\n    rep m sc();\n}\n";
+        let pattern = assert_round_trip(source);
+        assert_eq!(raw_count(&pattern), 4);
+        let script = pattern.to_script();
+        assert!(script.starts_with("export let n = 4;\n// the base\nrep n {"));
+        assert!(script.contains("    turn(); // next row\n    rep m {"));
+    }
+
+    #[test]
+    fn test_from_script_operations() {
+        let source = r#"fn edge(n, color) {
+    change_color(color);
+    rep n sc();
+}
+
+let width = 6;
+into(mark());
+rep width * 2 chain();
+for i in 0..4 {
+    turn();
+    if i % 2 == 0 && width > 3 {
+        edge(width * 2, [1.0, 0.5, 0.0]);
+    } else if i == 1 {
+        edge(width + width, [0.0, 0.0, 1.0]);
+    } else {
+        rep (width - 1) * 2 dc();
+        dc();
+    }
+}
+"#;
+        let pattern = assert_round_trip(source);
+        assert_eq!(raw_count(&pattern), 0);
+        assert_eq!(pattern.defined_functions(), vec![Identifier::from("edge")]);
+        assert!(pattern
+            .defined_identifiers()
+            .contains(&Identifier::from("i")));
+        let script = pattern.to_script();
+        assert!(script.starts_with("fn edge(n, color) {\n    change_color(color);"));
+        assert!(script.contains("    if i % 2 == 0 && width > 3 {"));
+        assert!(script.contains("    } else if i == 1 {"));
+        assert!(script.contains("edge(width * 2, [1.0, 0.5, 0.0]);"));
+        assert!(script.contains("rep ((width - 1) * 2) {"));
+    }
+
+    #[test]
+    fn test_binary_brackets() {
+        let p = ParametricPattern::default();
+        let (a, b, c) = (p.variable("a"), p.variable("b"), p.variable("c"));
+        let sum = p.binary(BinaryOp::Add, a, b);
+        let product = p.binary(BinaryOp::Mul, sum, c);
+        assert_eq!(p.expr_to_script(product), "(a + b) * c");
+        let difference = p.binary(BinaryOp::Sub, c, sum);
+        assert_eq!(p.expr_to_script(difference), "c - (a + b)");
+        let range = p.binary(BinaryOp::Range, p.literal(0), difference);
+        assert_eq!(p.expr_to_script(range), "0..c - (a + b)");
+    }
 }
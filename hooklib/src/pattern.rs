@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
@@ -26,6 +27,16 @@ pub enum PatternError {
     NestedChainSpace,
     /// You tried to end a chain space while none was started.
     NoChainSpace,
+    /// The stitch type given isn't one of [`WORKED_STITCHES`].
+    UnknownStitchType,
+    /// You tried to work a group of zero stitches, e.g. with [`Part::dec_n`].
+    InvalidCount,
+    /// A row doesn't have the number of stitches given to [`Part::expect_count`].
+    WrongStitchCount {
+        row: usize,
+        expected: usize,
+        actual: usize,
+    },
 }
 
 impl Display for PatternError {
@@ -37,6 +48,9 @@ impl Display for PatternError {
             Self::SewInvalidLengths => write!(f, "Rows to sew are not the same length."),
             Self::NestedChainSpace => write!(f, "Tried to start a chain space when one was already started."),
             Self::NoChainSpace => write!(f, "Tried to end a chain space when none was started."),
+            Self::UnknownStitchType => write!(f, "Unknown stitch type. Use one of {}.", WORKED_STITCHES.join(", ")),
+            Self::InvalidCount => write!(f, "Tried to work a group of zero stitches."),
+            Self::WrongStitchCount { row, expected, actual } => write!(f, "Row {row} has {actual} stitches, expected {expected}."),
         }
     }
 }
@@ -77,10 +91,10 @@ impl Node {
         }
     }
 
-    /// Returns a double crochet stitch.
-    fn dc(color: Vec3) -> Self {
+    /// Returns a worked stitch of the given type, e.g. `"sc"` or `"dc"`.
+    fn stitch(ty: &'static str, color: Vec3) -> Self {
         Self::Stitch {
-            ty: "dc",
+            ty,
             turn: false,
             color,
         }
@@ -113,6 +127,32 @@ impl Node {
             Node::MagicRing => "magic_ring",
         }
     }
+
+    /// Look up one of the [`WORKED_STITCHES`] by name.
+    pub fn worked_stitch_type(name: &str) -> Result<&'static str, PatternError> {
+        WORKED_STITCHES
+            .into_iter()
+            .find(|ty| *ty == name)
+            .ok_or(PatternError::UnknownStitchType)
+    }
+
+    /// Returns whether this is a stitch worked into a previous row, rather than a chain.
+    pub fn is_worked(&self) -> bool {
+        WORKED_STITCHES.contains(&self.stitch_type())
+    }
+
+    /// Returns the height of this stitch, relative to a double crochet.
+    /// Decreases, and anything else that isn't a worked stitch, are as tall as a double crochet.
+    pub fn height(&self) -> f32 {
+        match self.stitch_type() {
+            "sl" => 0.25,
+            "sc" => 0.5,
+            "hdc" => 0.75,
+            "tr" => 1.5,
+            "dtr" => 2.0,
+            _ => 1.0,
+        }
+    }
 }
 
 impl Default for Node {
@@ -126,8 +166,8 @@ impl Default for Node {
 pub enum EdgeType {
     /// The stitch is the last one worked
     Previous,
-    /// The stitch inserts into this one
-    Insert,
+    /// The stitch inserts into this one, through the given loops or around the post
+    Insert(InsertMode),
     /// There is a slip stitch connecting these two stitches
     Slip,
     /// This stitch is a neighbour making up a chain space
@@ -136,6 +176,70 @@ pub enum EdgeType {
     Sew,
 }
 
+impl EdgeType {
+    /// Returns whether this is an [`EdgeType::Insert`] edge, regardless of the insertion mode.
+    pub fn is_insert(&self) -> bool {
+        matches!(self, EdgeType::Insert(_))
+    }
+}
+
+/// Where a stitch is inserted into the stitch it's worked into.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum InsertMode {
+    /// Under both loops, the usual way of working a stitch.
+    #[default]
+    BothLoops,
+    /// Under the front loop only (FLO).
+    FrontLoop,
+    /// Under the back loop only (BLO).
+    BackLoop,
+    /// Around the post from the front (e.g. FPdc).
+    FrontPost,
+    /// Around the post from the back (e.g. BPdc).
+    BackPost,
+}
+
+impl InsertMode {
+    /// How tall a stitch worked this way is, relative to one worked under both loops.
+    /// Post stitches wrap around the stitch below rather than sitting on top of it, so they overlap it.
+    pub fn length_factor(&self) -> f32 {
+        match self {
+            InsertMode::BothLoops | InsertMode::FrontLoop | InsertMode::BackLoop => 1.0,
+            InsertMode::FrontPost | InsertMode::BackPost => 0.6,
+        }
+    }
+
+    /// The angle (in radians) the fabric folds to along a row worked this way, if it folds at all.
+    /// Working into one loop leaves the other as a ridge that the fabric hinges on, e.g. the base of an amigurumi.
+    /// Positive angles fold towards the front (the side facing you as the row is worked), negative towards the back,
+    /// so the fabric folds away from the ridge: back loop rows fold backwards and front loop rows forwards.
+    pub fn fold_angle(&self) -> Option<f32> {
+        match self {
+            InsertMode::BackLoop => Some(-std::f32::consts::FRAC_PI_2),
+            InsertMode::FrontLoop => Some(3.0 * std::f32::consts::FRAC_PI_4),
+            _ => None,
+        }
+    }
+
+    /// How far a stitch worked this way sits in front of the fabric, in stitch widths (negative is behind it).
+    /// Front post stitches stand out on the front and back post stitches on the back, which is what makes ribbing.
+    pub fn post_offset(&self) -> f32 {
+        match self {
+            InsertMode::FrontPost => 0.5,
+            InsertMode::BackPost => -0.5,
+            _ => 0.0,
+        }
+    }
+
+    /// Which side of the fabric a stitch worked this way should end up on, if it's pushed to either side:
+    /// `1.0` for the front, `-1.0` for the back.
+    fn side(&self) -> Option<f32> {
+        self.fold_angle()
+            .map(f32::signum)
+            .or_else(|| (self.post_offset() != 0.0).then(|| self.post_offset().signum()))
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
 enum SkipDirection {
     #[default]
@@ -144,7 +248,61 @@ enum SkipDirection {
 }
 
 /// Gauge is the ratio of rows in a given length to stitches in a given length.
-const GAUGE: f32 = 15.0 / 18.5;
+/// This is measured for double crochet, other stitch heights are relative to it (see [`Node::height`]).
+const GAUGE: f32 = DEFAULT_STITCHES / DEFAULT_ROWS;
+
+/// The default number of stitches and rows of double crochet in 10cm, see [`Gauge`].
+const DEFAULT_STITCHES: f32 = 15.0;
+const DEFAULT_ROWS: f32 = 18.5;
+
+/// The size of a pattern's stitches, which sets the real size of the finished piece.
+/// Measured as the number of stitches and rows of double crochet in a 10cm square, as on a yarn label.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gauge {
+    /// The number of stitches in 10cm along a row.
+    pub stitches: f32,
+    /// The number of rows of double crochet in 10cm.
+    pub rows: f32,
+    /// The height in millimetres of particular stitch types, instead of their usual height relative to double crochet.
+    pub heights: BTreeMap<&'static str, f32>,
+}
+
+impl Default for Gauge {
+    fn default() -> Self {
+        Self {
+            stitches: DEFAULT_STITCHES,
+            rows: DEFAULT_ROWS,
+            heights: BTreeMap::new(),
+        }
+    }
+}
+
+impl Gauge {
+    /// Create a gauge from the number of stitches and rows of double crochet in 10cm.
+    pub fn new(stitches: f32, rows: f32) -> Self {
+        Self {
+            stitches,
+            rows,
+            heights: BTreeMap::new(),
+        }
+    }
+
+    /// The width of a stitch in millimetres.
+    pub fn stitch_width(&self) -> f32 {
+        100.0 / self.stitches
+    }
+
+    /// The height of a stitch in millimetres.
+    pub fn height(&self, node: &Node) -> f32 {
+        self.heights
+            .get(node.stitch_type())
+            .copied()
+            .unwrap_or_else(|| node.height() * 100.0 / self.rows)
+    }
+}
+
+/// All stitch types that can be worked into a previous row, from shortest to tallest.
+pub const WORKED_STITCHES: [&str; 6] = ["sl", "sc", "hdc", "dc", "tr", "dtr"];
 
 /// Epsilon is just a really small distance, used for stitches that should be really close together (e.g. slips and sews)
 const EPSILON: f32 = 0.001;
@@ -153,7 +311,7 @@ impl From<EdgeType> for f32 {
     fn from(edge_type: EdgeType) -> Self {
         match edge_type {
             EdgeType::Previous => 1.0,
-            EdgeType::Insert => GAUGE,
+            EdgeType::Insert(mode) => GAUGE * mode.length_factor(),
             EdgeType::Slip => EPSILON,
             EdgeType::Neighbour => 1.0,
             EdgeType::Sew => EPSILON,
@@ -161,10 +319,78 @@ impl From<EdgeType> for f32 {
     }
 }
 
+/// The rows of a single part, each a list of the stitches worked in that row.
+/// The first row holds the foundation, i.e. the starting chain or magic ring.
+pub type PartRows = Vec<Vec<graph::NodeIndex>>;
+
+/// The number of stitches in a row, and how many increases and decreases shaped it from the row before.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RowCount {
+    /// The number of stitches in the row, not counting turning chains.
+    pub stitches: usize,
+    /// Extra stitches worked into the same stitch of the row before.
+    pub increases: usize,
+    /// Stitches of the row before that were worked together into one.
+    pub decreases: usize,
+}
+
+impl RowCount {
+    /// Count the stitches in a row of the graph. Magic rings and chain spaces aren't stitches, so aren't counted.
+    /// Working several stitches into a magic ring or chain space doesn't count as increasing.
+    pub fn new(graph: &graph::DiGraph<Node, EdgeType>, row: &[NodeIndex]) -> Self {
+        let mut count = Self::default();
+        let mut inserts = BTreeMap::<NodeIndex, usize>::new();
+        for node in row
+            .iter()
+            .filter(|node| matches!(graph[**node], Node::Stitch { turn: false, .. }))
+        {
+            count.stitches += 1;
+            let targets = graph
+                .edges(*node)
+                .filter(|edge| edge.weight().is_insert())
+                .map(|edge| edge.target())
+                .collect::<Vec<_>>();
+            count.decreases += targets.len().saturating_sub(1);
+            for target in targets {
+                if matches!(graph[target], Node::Stitch { .. }) {
+                    *inserts.entry(target).or_insert(0) += 1;
+                }
+            }
+        }
+        count.increases = inserts.values().map(|n| n - 1).sum();
+        count
+    }
+}
+
+/// A line in a pattern script, and the file it's in if the script was loaded from one.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: Option<String>,
+    /// The line number, starting from 1.
+    pub line: usize,
+}
+
+/// Where a node in the crochet graph was made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeOrigin {
+    /// The script call that made the node, if it was made by a script.
+    pub location: Option<SourceLocation>,
+    /// The index of the part the node is in.
+    pub part: usize,
+    /// The index of the row the node was worked in, where row 0 is the foundation.
+    pub row: usize,
+}
+
 /// A whole pattern, represented as a crochet graph. Most operations will refer to the [`Part`] struct.
 #[derive(Default, Debug)]
 pub struct Pattern {
     graph: RwLock<graph::DiGraph<Node, EdgeType>>,
+    parts: RwLock<Vec<PartRows>>,
+    /// How firmly each part is stuffed, if the script set it.
+    stuffing: RwLock<Vec<Option<f32>>>,
+    gauge: RwLock<Gauge>,
+    /// Where each node was made, indexed by node.
+    origins: RwLock<Vec<Option<NodeOrigin>>>,
 }
 
 impl PartialEq for Pattern {
@@ -183,13 +409,37 @@ impl Pattern {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
             graph: Default::default(),
+            parts: Default::default(),
+            stuffing: Default::default(),
+            gauge: Default::default(),
+            origins: Default::default(),
         })
     }
 
+    /// Create a pattern directly from a crochet graph, the rows and stuffing of each of its parts, and its gauge.
+    pub(crate) fn from_graph(
+        graph: graph::DiGraph<Node, EdgeType>,
+        parts: Vec<PartRows>,
+        stuffing: Vec<Option<f32>>,
+        gauge: Gauge,
+    ) -> Self {
+        Self {
+            graph: graph.into(),
+            parts: parts.into(),
+            stuffing: stuffing.into(),
+            gauge: gauge.into(),
+            origins: Default::default(),
+        }
+    }
+
     /// Unwrap a pattern within an arc, or clone the underlying graph if the Arc is still being used.
     pub fn into_inner(self: Arc<Self>) -> Self {
         Arc::try_unwrap(self).unwrap_or_else(|s| Pattern {
             graph: s.graph.read().unwrap().clone().into(),
+            parts: s.parts.read().unwrap().clone().into(),
+            stuffing: s.stuffing.read().unwrap().clone().into(),
+            gauge: s.gauge.read().unwrap().clone().into(),
+            origins: s.origins.read().unwrap().clone().into(),
         })
     }
 
@@ -216,22 +466,150 @@ impl Pattern {
         }
     }
 
+    /// Return the rows of every part in the pattern, in the order the parts were added.
+    /// A part's rows are only recorded here once it's finished, i.e. when the [`Part`] is dropped.
+    pub fn parts(&self) -> impl Deref<Target = Vec<PartRows>> + use<'_> {
+        self.parts.read().unwrap()
+    }
+
+    /// Count the stitches, increases and decreases in each row of each finished part.
+    pub fn row_counts(&self) -> Vec<Vec<RowCount>> {
+        let graph = self.graph();
+        self.parts()
+            .iter()
+            .map(|rows| rows.iter().map(|row| RowCount::new(&graph, row)).collect())
+            .collect()
+    }
+
+    /// Where a node was made, if it was made by a [`Part`].
+    pub fn origin(&self, node: NodeIndex) -> Option<NodeOrigin> {
+        self.origins
+            .read()
+            .unwrap()
+            .get(node.index())
+            .cloned()
+            .flatten()
+    }
+
+    /// Find all nodes made by a script call on the given line.
+    pub fn nodes_at(&self, location: &SourceLocation) -> Vec<NodeIndex> {
+        self.origins
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, origin)| {
+                origin
+                    .as_ref()
+                    .is_some_and(|origin| origin.location.as_ref() == Some(location))
+            })
+            .map(|(i, _)| NodeIndex::new(i))
+            .collect()
+    }
+
+    /// Record where a node was made.
+    fn set_origin(&self, node: NodeIndex, origin: NodeOrigin) {
+        let mut origins = self.origins.write().unwrap();
+        if origins.len() <= node.index() {
+            origins.resize(node.index() + 1, None);
+        }
+        origins[node.index()] = Some(origin);
+    }
+
+    /// The gauge of the pattern, setting the real size of its stitches.
+    pub fn gauge(&self) -> Gauge {
+        self.gauge.read().unwrap().clone()
+    }
+
+    /// Change the gauge of the pattern.
+    pub fn set_gauge(&self, gauge: Gauge) {
+        *self.gauge.write().unwrap() = gauge;
+    }
+
+    /// How firmly a part is stuffed, as set by [`Part::set_stuffing`], or `None` if it wasn't set.
+    pub fn stuffing(&self, part: usize) -> Option<f32> {
+        self.stuffing.read().unwrap().get(part).copied().flatten()
+    }
+
+    /// Guess whether a part is closed, so it would be stuffed, like the body of an amigurumi toy.
+    /// A part is closed if its first and last rows are both much smaller than its widest row,
+    /// i.e. it's worked up from a ring or point, and decreased back down to close it off.
+    pub fn is_closed(&self, part: usize) -> bool {
+        let parts = self.parts();
+        let Some(rows) = parts.get(part) else {
+            return false;
+        };
+        let lengths = rows
+            .iter()
+            .map(Vec::len)
+            .filter(|len| *len > 0)
+            .collect::<Vec<_>>();
+        let widest = lengths.iter().copied().max().unwrap_or(0);
+
+        match (lengths.first(), lengths.last()) {
+            (Some(first), Some(last)) if lengths.len() >= 3 => {
+                *first * 2 <= widest && *last * 2 <= widest
+            }
+            _ => false,
+        }
+    }
+
+    /// Find the node in a previous version of this pattern at the same place as each node in this one,
+    /// i.e. in the same part and row, and at the same position in that row.
+    /// This lets work done on the previous version, like its layout, carry over to this one.
+    pub fn matching_nodes(&self, previous: &Pattern) -> Vec<Option<NodeIndex>> {
+        let mut matches = vec![None; self.graph().node_count()];
+        for (rows, previous_rows) in self.parts().iter().zip(previous.parts().iter()) {
+            for (row, previous_row) in rows.iter().zip(previous_rows) {
+                for (node, previous_node) in row.iter().zip(previous_row) {
+                    matches[node.index()] = Some(*previous_node);
+                }
+            }
+        }
+        matches
+    }
+
     /// Return the underlying graph of the pattern.
     pub fn graph(&self) -> impl Deref<Target = graph::DiGraph<Node, EdgeType>> + use<'_> {
         self.graph.read().unwrap()
     }
 
     /// Return a triangulated version of the crochet graph, where diagonal shortcuts are added.
+    /// Edges are weighted by their length in millimetres, using the pattern's [`Gauge`].
     pub fn triangulated_graph(&self) -> graph::DiGraph<(), f32> {
         let new_graph = self.graph.read().unwrap().clone();
-        let diag_length = (1.0 + (GAUGE * GAUGE)).sqrt();
+        let gauge = self.gauge();
+        let width = gauge.stitch_width();
+        let insert_length = |stitch: NodeIndex, mode: InsertMode| {
+            gauge.height(&new_graph[stitch]) * mode.length_factor()
+        };
+        // post stitches stand out from the fabric, so they're further from stitches that don't
+        let offsets = new_graph
+            .node_indices()
+            .map(|stitch| {
+                new_graph
+                    .edges_directed(stitch, Direction::Outgoing)
+                    .find_map(|e| match *e.weight() {
+                        EdgeType::Insert(mode) => Some(mode.post_offset() * width),
+                        _ => None,
+                    })
+                    .unwrap_or(0.0)
+            })
+            .collect::<Vec<_>>();
+        let offset_between = |a: NodeIndex, b: NodeIndex| offsets[a.index()] - offsets[b.index()];
+        let diag_length = |a: NodeIndex, b: NodeIndex, target: NodeIndex, mode: InsertMode| {
+            let height = (insert_length(a, mode) + insert_length(b, mode)) / 2.0;
+            let offset = offset_between(b, target);
+            (width * width + height * height + offset * offset).sqrt()
+        };
 
         let diagonals = new_graph
             .edge_references()
             .filter_map(|p| {
-                if *p.weight() == EdgeType::Insert
-                    && !new_graph.node_weight(p.target()).unwrap().is_turn()
-                {
+                let EdgeType::Insert(mode) = *p.weight() else {
+                    return None;
+                };
+                if !new_graph.node_weight(p.target()).unwrap().is_turn() {
                     if let Some(endpoint_1) = new_graph
                         .edges_directed(p.source(), Direction::Incoming)
                         .find(|e| *e.weight() == EdgeType::Previous)
@@ -239,14 +617,23 @@ impl Pattern {
                     {
                         if let Some(endpoint_2) = new_graph
                             .edges_directed(endpoint_1, Direction::Outgoing)
-                            .find(|e| *e.weight() == EdgeType::Insert)
+                            .find(|e| e.weight().is_insert())
                             .map(|e| e.target())
                         {
                             Some(vec![
-                                (endpoint_1, p.target(), diag_length),
-                                (endpoint_2, p.source(), diag_length),
+                                (
+                                    endpoint_1,
+                                    p.target(),
+                                    diag_length(p.source(), endpoint_1, p.target(), mode),
+                                ),
+                                (
+                                    endpoint_2,
+                                    p.source(),
+                                    diag_length(endpoint_1, p.source(), endpoint_2, mode),
+                                ),
                             ])
                         } else {
+                            let diag_length = diag_length(p.source(), endpoint_1, p.target(), mode);
                             Some(vec![(endpoint_1, p.target(), diag_length)])
                         }
                     } else {
@@ -259,31 +646,97 @@ impl Pattern {
             .flatten()
             .collect::<Vec<_>>();
 
+        // rows worked into a single loop fold along the unworked one,
+        // so shorten the distance from each stitch to the row below the one it's worked into.
+        // Distances are the same either way the fabric folds, see `Pattern::side_agreement` for picking the side.
+        let folds = new_graph
+            .edge_references()
+            .filter_map(|p| {
+                let EdgeType::Insert(mode) = *p.weight() else {
+                    return None;
+                };
+                let angle = mode.fold_angle()?;
+                let (below, below_mode) = new_graph
+                    .edges_directed(p.target(), Direction::Outgoing)
+                    .find_map(|e| match *e.weight() {
+                        EdgeType::Insert(below_mode) => Some((e.target(), below_mode)),
+                        _ => None,
+                    })?;
+
+                let a = insert_length(p.source(), mode);
+                let b = insert_length(p.target(), below_mode);
+                let length = (a * a + b * b - 2.0 * a * b * angle.cos()).sqrt();
+                Some((p.source(), below, length))
+            })
+            .collect::<Vec<_>>();
+
         let mut new_graph = new_graph.map(
             |_ix, _node| (),
             |ix, edge| {
-                let (start, end) = new_graph.edge_endpoints(ix).unwrap();
-                let start = *new_graph.node_weight(start).unwrap();
-                let end = *new_graph.node_weight(end).unwrap();
-                let edge_length_type = match edge {
-                    EdgeType::Previous => {
-                        if start.stitch_type() == "ch" && end.stitch_type() == "dc" {
-                            EdgeType::Insert
-                        } else {
-                            EdgeType::Previous
-                        }
+                let (start_ix, end_ix) = new_graph.edge_endpoints(ix).unwrap();
+                let start = *new_graph.node_weight(start_ix).unwrap();
+                let end = *new_graph.node_weight(end_ix).unwrap();
+                let offset = offset_between(start_ix, end_ix);
+                match edge {
+                    // stitches are as tall as the stitch they're worked from
+                    EdgeType::Insert(mode) => {
+                        (gauge.height(&start) * mode.length_factor()).hypot(offset)
                     }
-                    other => *other,
-                };
-                edge_length_type.into()
+                    EdgeType::Previous if start.stitch_type() == "ch" && end.is_worked() => {
+                        gauge.height(&end)
+                    }
+                    EdgeType::Previous => width.hypot(offset),
+                    other => f32::from(*other) * width,
+                }
             },
         );
 
         new_graph.extend_with_edges(diagonals);
+        new_graph.extend_with_edges(folds);
 
         new_graph
     }
 
+    /// How well a layout puts folds and post stitches on the side of the fabric they belong on
+    /// (see [`InsertMode::fold_angle`] and [`InsertMode::post_offset`]), given the position of each node by index.
+    /// Positive if most of them are on the right side, negative if the layout needs mirroring.
+    ///
+    /// The front of the fabric is where a stitch's row runs from right to left as it stands up from the row below,
+    /// so it swaps sides with every turn. A mirrored layout has all the same distances,
+    /// so the layout can't tell the sides apart by itself.
+    pub fn side_agreement(&self, positions: &[Vec3]) -> f32 {
+        let graph = self.graph();
+        let insert = |stitch: NodeIndex| {
+            graph
+                .edges_directed(stitch, Direction::Outgoing)
+                .find_map(|e| match *e.weight() {
+                    EdgeType::Insert(mode) => Some((e.target(), mode)),
+                    _ => None,
+                })
+        };
+        let previous = |stitch: NodeIndex| {
+            graph
+                .edges_directed(stitch, Direction::Outgoing)
+                .find(|e| *e.weight() == EdgeType::Previous)
+                .map(|e| e.target())
+        };
+
+        graph
+            .node_indices()
+            .filter_map(|stitch| {
+                let (worked_into, mode) = insert(stitch)?;
+                let side = mode.side()?;
+                let (below, _) = insert(worked_into)?;
+                let position = |node: NodeIndex| positions.get(node.index()).copied();
+
+                let up = position(worked_into)? - position(below)?;
+                let along = position(stitch)? - position(previous(stitch)?)?;
+                let front = up.cross(along).normalize_or_zero();
+                Some(side * front.dot(position(stitch)? - position(worked_into)?))
+            })
+            .sum()
+    }
+
     /// Convert the pattern graph to GraphViz DOT format using [`petgraph::dot::Dot`].
     pub fn to_graphviz(&self) -> String {
         use petgraph::dot::{Config, Dot};
@@ -294,6 +747,12 @@ impl Pattern {
                 Node::Stitch { ty: "dc", .. } => {
                     "shape = \"none\" label = \"+\" margin = \"0\" fontsize = 56.0"
                 }
+                n if n.is_worked() => {
+                    return format!(
+                        "shape = \"none\" label = \"{}\" margin = \"0\"",
+                        n.stitch_type()
+                    )
+                }
                 _ => "shape = \"point\" label = \"\"",
             };
 
@@ -312,7 +771,9 @@ impl Pattern {
                 let len: f32 = (*e.weight()).into();
                 match e.weight() {
                     EdgeType::Previous => format!("len = {len}"),
-                    EdgeType::Insert => format!(r#"len = {len} style = "dotted" arrowhead="vee""#),
+                    EdgeType::Insert(_) => {
+                        format!(r#"len = {len} style = "dotted" arrowhead="vee""#)
+                    }
                     EdgeType::Slip => format!("len = {len} style = \"dashed\""),
                     EdgeType::Neighbour => format!("len = {len} style = \"invis\""),
                     EdgeType::Sew => format!("len = {len} style = \"dashed\""),
@@ -329,6 +790,7 @@ impl Pattern {
 #[derive(Debug)]
 pub struct Part {
     parent: Arc<Pattern>,
+    index: usize,
 
     start: graph::NodeIndex,
     prev: graph::NodeIndex,
@@ -337,10 +799,27 @@ pub struct Part {
     rows: Vec<Vec<graph::NodeIndex>>,
     direction: SkipDirection,
     ignore_for_row: bool,
+    insert_mode: InsertMode,
     current_color: Vec3,
+    /// The script call currently working the part, recorded in the origin of each new node.
+    location: Option<SourceLocation>,
 }
 
 impl Part {
+    /// Add a node to the parent's crochet graph, recording where it was made.
+    fn add_node(&self, node: Node) -> NodeIndex {
+        let new_node = self.graph_mut().add_node(node);
+        self.parent.set_origin(
+            new_node,
+            NodeOrigin {
+                location: self.location.clone(),
+                part: self.index,
+                row: self.rows.len().saturating_sub(1),
+            },
+        );
+        new_node
+    }
+
     /// Get a reference to the parent's crochet graph.
     fn graph_mut(&self) -> impl DerefMut<Target = graph::DiGraph<Node, EdgeType>> + use<'_> {
         self.parent.graph.write().unwrap()
@@ -392,9 +871,24 @@ impl Part {
             .add_node(Node::chain(Vec3::ONE));
         let prev = start;
         let rows = vec![vec![start]];
+        let index = {
+            let mut parts = parent.parts.write().unwrap();
+            parts.push(vec![]);
+            parent.stuffing.write().unwrap().push(None);
+            parts.len() - 1
+        };
+        parent.set_origin(
+            start,
+            NodeOrigin {
+                location: None,
+                part: index,
+                row: 0,
+            },
+        );
 
         Self {
             parent,
+            index,
             start,
             prev,
             insert: None,
@@ -402,22 +896,43 @@ impl Part {
             rows,
             direction: Default::default(),
             ignore_for_row: false,
+            insert_mode: Default::default(),
             current_color: Vec3::ONE,
+            location: None,
         }
     }
 
     /// Replace the starting chain with a magic ring.
     pub fn magic_ring(&mut self) {
         self.graph_mut().remove_node(self.start);
-        let new_start = self.graph_mut().add_node(Node::MagicRing);
+        // the graph moves its last node into the removed node's index, so do the same with origins
+        {
+            let mut origins = self.parent.origins.write().unwrap();
+            if self.start.index() < origins.len() {
+                origins.swap_remove(self.start.index());
+            }
+        }
+        let new_start = self.add_node(Node::MagicRing);
         self.start = new_start;
         self.prev = new_start;
+        self.rows[0] = vec![new_start];
     }
 
     /// Start a new row.
     pub fn new_row(&mut self) -> Result<(), PatternError> {
         self.rows.push(vec![]);
-        self.set_insert(*self.previous_row()?.first().ok_or(PatternError::EndOfRow)?);
+        // a row started in a magic ring is worked into its stitches, not the ring
+        let first = {
+            let graph = self.parent.graph();
+            let previous_row = self.previous_row()?;
+            previous_row
+                .iter()
+                .find(|node| graph[**node] != Node::MagicRing)
+                .or(previous_row.first())
+                .copied()
+                .ok_or(PatternError::EndOfRow)?
+        };
+        self.set_insert(first);
 
         Ok(())
     }
@@ -436,7 +951,7 @@ impl Part {
         self.new_row()?;
         self.insert = Some(self.prev);
         self.direction = SkipDirection::Reverse;
-        let new_node = self.graph_mut().add_node(Node::turn(self.current_color));
+        let new_node = self.add_node(Node::turn(self.current_color));
         self.graph_mut()
             .add_edge(new_node, self.prev, EdgeType::Previous);
         self.current_row_mut()?.push(new_node);
@@ -468,7 +983,7 @@ impl Part {
 
     /// Create a new chain stitch.
     pub fn chain(&mut self) -> Result<NodeIndex, PatternError> {
-        let new_node = self.graph_mut().add_node(Node::chain(self.current_color));
+        let new_node = self.add_node(Node::chain(self.current_color));
         self.graph_mut()
             .add_edge(new_node, self.prev, EdgeType::Previous);
         self.prev = new_node;
@@ -484,23 +999,23 @@ impl Part {
         Ok(new_node)
     }
 
-    /// Create a new double crochet stitch in the current insertion point, then skip.
-    pub fn dc(&mut self) -> Result<NodeIndex, PatternError> {
-        let new_node = self.dc_noskip()?;
+    /// Create a new stitch of the given type in the current insertion point, then skip.
+    pub fn stitch(&mut self, ty: &'static str) -> Result<NodeIndex, PatternError> {
+        let new_node = self.stitch_noskip(ty)?;
         self.skip()?;
         Ok(new_node)
     }
 
-    /// Create a new double crochet stitch in the current insertion point.
+    /// Create a new stitch of the given type in the current insertion point.
     /// Don't skip to the next insertion point.
-    pub fn dc_noskip(&mut self) -> Result<NodeIndex, PatternError> {
-        let new_node = self.graph_mut().add_node(Node::dc(self.current_color));
+    pub fn stitch_noskip(&mut self, ty: &'static str) -> Result<NodeIndex, PatternError> {
+        let new_node = self.add_node(Node::stitch(ty, self.current_color));
         self.graph_mut()
             .add_edge(new_node, self.prev, EdgeType::Previous);
         self.graph_mut().add_edge(
             new_node,
             self.insert.ok_or(PatternError::NoInsert)?,
-            EdgeType::Insert,
+            EdgeType::Insert(self.insert_mode),
         );
 
         self.prev = new_node;
@@ -512,25 +1027,90 @@ impl Part {
         Ok(new_node)
     }
 
-    /// Create a new decrease stitch in the next two insertion points.
-    pub fn dec(&mut self) -> Result<NodeIndex, PatternError> {
-        let new_node = self
-            .graph_mut()
-            .add_node(Node::decrease(self.current_color));
+    /// Create a new slip stitch in the current insertion point, then skip.
+    /// Unlike [`Part::slip_stitch`], this is worked as part of the row.
+    pub fn sl(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("sl")
+    }
+
+    /// Create a new slip stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn sl_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("sl")
+    }
+
+    /// Create a new single crochet stitch in the current insertion point, then skip.
+    pub fn sc(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("sc")
+    }
+
+    /// Create a new single crochet stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn sc_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("sc")
+    }
+
+    /// Create a new half double crochet stitch in the current insertion point, then skip.
+    pub fn hdc(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("hdc")
+    }
+
+    /// Create a new half double crochet stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn hdc_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("hdc")
+    }
+
+    /// Create a new double crochet stitch in the current insertion point, then skip.
+    pub fn dc(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("dc")
+    }
+
+    /// Create a new double crochet stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn dc_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("dc")
+    }
+
+    /// Create a new treble crochet stitch in the current insertion point, then skip.
+    pub fn tr(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("tr")
+    }
+
+    /// Create a new treble crochet stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn tr_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("tr")
+    }
+
+    /// Create a new double treble crochet stitch in the current insertion point, then skip.
+    pub fn dtr(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch("dtr")
+    }
+
+    /// Create a new double treble crochet stitch in the current insertion point.
+    /// Don't skip to the next insertion point.
+    pub fn dtr_noskip(&mut self) -> Result<NodeIndex, PatternError> {
+        self.stitch_noskip("dtr")
+    }
+
+    /// Add a single node worked into each of the next `n` insertion points, skipping past all of them.
+    fn work_together(&mut self, node: Node, n: usize) -> Result<NodeIndex, PatternError> {
+        if n == 0 {
+            return Err(PatternError::InvalidCount);
+        }
+
+        let new_node = self.add_node(node);
         self.graph_mut()
             .add_edge(new_node, self.prev, EdgeType::Previous);
-        self.graph_mut().add_edge(
-            new_node,
-            self.insert.ok_or(PatternError::NoInsert)?,
-            EdgeType::Insert,
-        );
-        self.skip()?;
-        self.graph_mut().add_edge(
-            new_node,
-            self.insert.ok_or(PatternError::NoInsert)?,
-            EdgeType::Insert,
-        );
-        self.skip()?;
+        for _ in 0..n {
+            self.graph_mut().add_edge(
+                new_node,
+                self.insert.ok_or(PatternError::NoInsert)?,
+                EdgeType::Insert(self.insert_mode),
+            );
+            self.skip()?;
+        }
 
         self.prev = new_node;
 
@@ -541,6 +1121,17 @@ impl Part {
         Ok(new_node)
     }
 
+    /// Create a new decrease stitch in the next two insertion points.
+    pub fn dec(&mut self) -> Result<NodeIndex, PatternError> {
+        self.work_together(Node::decrease(self.current_color), 2)
+    }
+
+    /// Work `n` stitches of the given type together into one, in the next `n` insertion points (e.g. sc3tog).
+    pub fn dec_n(&mut self, n: usize, ty: &str) -> Result<NodeIndex, PatternError> {
+        let ty = Node::worked_stitch_type(ty)?;
+        self.work_together(Node::stitch(ty, self.current_color), n)
+    }
+
     /// Work an increase stitch, i.e. two double crochetes inserted into the same stitch.
     pub fn inc(&mut self) -> Result<(NodeIndex, NodeIndex), PatternError> {
         let s1 = self.dc_noskip()?;
@@ -548,6 +1139,21 @@ impl Part {
         Ok((s1, s2))
     }
 
+    /// Work `n` stitches of the given type into the same insertion point, then skip (e.g. a 3-into-1 increase).
+    pub fn inc_n(&mut self, n: usize, ty: &str) -> Result<Vec<NodeIndex>, PatternError> {
+        let ty = Node::worked_stitch_type(ty)?;
+        if n == 0 {
+            return Err(PatternError::InvalidCount);
+        }
+
+        let mut stitches = (1..n)
+            .map(|_| self.stitch_noskip(ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        stitches.push(self.stitch(ty)?);
+
+        Ok(stitches)
+    }
+
     /// Work a slip stitch into the given stitch.
     pub fn slip_stitch(&mut self, into: graph::NodeIndex) {
         self.graph_mut().add_edge(self.prev, into, EdgeType::Slip);
@@ -571,7 +1177,7 @@ impl Part {
             .take()
             .ok_or(PatternError::NoChainSpace)?;
 
-        let new_node = self.graph_mut().add_node(Node::ch_sp());
+        let new_node = self.add_node(Node::ch_sp());
         ch_sp.into_iter().for_each(|neighbour| {
             self.graph_mut()
                 .add_edge(new_node, neighbour, EdgeType::Neighbour);
@@ -580,15 +1186,60 @@ impl Part {
         Ok(new_node)
     }
 
+    /// Check that the current row has the expected number of stitches, not counting its turning chain.
+    pub fn expect_count(&self, expected: usize) -> Result<(), PatternError> {
+        let actual = RowCount::new(&self.parent.graph(), self.current_row()?).stitches;
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(PatternError::WrongStitchCount {
+                row: self.rows.len() - 1,
+                expected,
+                actual,
+            })
+        }
+    }
+
     /// Set whether to ignore the currently worked stitches, not adding them to the current row.
     pub fn set_ignore(&mut self, ignore: bool) {
         self.ignore_for_row = ignore;
     }
 
+    /// Set the script call currently working the part, to record where new nodes are made.
+    pub fn set_location(&mut self, location: Option<SourceLocation>) {
+        self.location = location;
+    }
+
+    /// Set which loops or post of the insertion point new stitches are worked into.
+    pub fn set_insert_mode(&mut self, mode: InsertMode) {
+        self.insert_mode = mode;
+    }
+
+    /// Get which loops or post of the insertion point new stitches are worked into.
+    pub fn insert_mode(&self) -> InsertMode {
+        self.insert_mode
+    }
+
     /// Change the color of the yarn.
     pub fn change_color(&mut self, color: Vec3) {
         self.current_color = color;
     }
+
+    /// Set how firmly the part is stuffed, which inflates it when laid out.
+    /// This overrides whether the part looks closed, see [`Pattern::is_closed`].
+    pub fn set_stuffing(&mut self, pressure: f32) {
+        self.parent.stuffing.write().unwrap()[self.index] = Some(pressure);
+    }
+}
+
+impl Drop for Part {
+    /// Record the finished part's rows in the parent [`Pattern`].
+    fn drop(&mut self) {
+        let rows = std::mem::take(&mut self.rows);
+        if let Ok(mut parts) = self.parent.parts.write() {
+            parts[self.index] = rows;
+        }
+    }
 }
 
 pub fn test_pattern_spiral_rounds() -> Result<Pattern, PatternError> {
@@ -626,6 +1277,7 @@ pub fn test_pattern_spiral_rounds() -> Result<Pattern, PatternError> {
         }
     }
 
+    drop(part);
     Ok(pattern.into_inner())
 }
 
@@ -680,6 +1332,7 @@ pub fn test_pattern_sphere() -> Result<Pattern, PatternError> {
     part.dec()?;
     part.dec()?;
 
+    drop(part);
     Ok(pattern.into_inner())
 }
 
@@ -717,6 +1370,7 @@ pub fn test_pattern_joined_rounds() -> Result<Pattern, PatternError> {
         part.slip_stitch(start);
     }
 
+    drop(part);
     Ok(pattern.into_inner())
 }
 
@@ -734,6 +1388,7 @@ pub fn test_pattern_flat(n: u32) -> Result<Pattern, PatternError> {
         }
     }
 
+    drop(part);
     Ok(pattern.into_inner())
 }
 
@@ -776,6 +1431,268 @@ mod tests {
         write!(file, "{}", pattern.to_graphviz()).unwrap();
     }
 
+    #[test]
+    fn test_matching_nodes() {
+        let pattern = |rows: usize| {
+            let pattern = Pattern::new();
+            let mut part = pattern.add_part();
+            // a first part, so that rows have to be matched within the right part
+            part.chain().unwrap();
+            drop(part);
+            let mut part = pattern.add_part();
+            part.magic_ring();
+            for _ in 0..rows {
+                part.new_row().unwrap();
+                for _ in 0..6 {
+                    part.sc_noskip().unwrap();
+                }
+            }
+            drop(part);
+            pattern.into_inner()
+        };
+        let previous = pattern(2);
+        let current = pattern(3);
+
+        let matches = current.matching_nodes(&previous);
+        let parts = current.parts();
+        let previous_parts = previous.parts();
+        assert_eq!(
+            matches[parts[1][2][3].index()],
+            Some(previous_parts[1][2][3])
+        );
+        assert_eq!(
+            matches[parts[1][0][0].index()],
+            Some(previous_parts[1][0][0])
+        );
+        assert!(parts[1][3]
+            .iter()
+            .all(|node| matches[node.index()].is_none()));
+    }
+
+    #[test]
+    fn test_is_closed() {
+        let sphere = test_pattern_sphere().unwrap();
+        assert!(sphere.is_closed(0));
+        assert_eq!(sphere.stuffing(0), None);
+
+        let flat = test_pattern_flat(5).unwrap();
+        assert!(!flat.is_closed(0));
+
+        // a cup, worked up from a ring but left open
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.magic_ring();
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.sc_noskip().unwrap();
+        }
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.inc().unwrap();
+        }
+        part.set_stuffing(0.5);
+        drop(part);
+        assert!(!pattern.is_closed(0));
+        assert_eq!(pattern.stuffing(0), Some(0.5));
+    }
+
+    #[test]
+    fn test_row_counts() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.magic_ring();
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.sc_noskip().unwrap();
+        }
+        part.expect_count(6).unwrap();
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.inc().unwrap();
+        }
+        part.expect_count(12).unwrap();
+        part.new_row().unwrap();
+        for _ in 0..4 {
+            part.dec_n(3, "sc").unwrap();
+        }
+        let err = part.expect_count(6).unwrap_err();
+        assert!(matches!(
+            err,
+            PatternError::WrongStitchCount {
+                row: 3,
+                expected: 6,
+                actual: 4
+            }
+        ));
+        assert_eq!(err.to_string(), "Row 3 has 4 stitches, expected 6.");
+        drop(part);
+
+        let counts = pattern.row_counts();
+        let row = |stitches, increases, decreases| RowCount {
+            stitches,
+            increases,
+            decreases,
+        };
+        assert_eq!(
+            counts,
+            vec![vec![
+                row(0, 0, 0),
+                row(6, 0, 0),
+                row(12, 6, 0),
+                row(4, 0, 8)
+            ]]
+        );
+
+        // a magic ring isn't counted, and the next round is worked into the stitches in it
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.magic_ring();
+        let ring = part.start();
+        part.set_insert(ring);
+        for _ in 0..6 {
+            part.sc_noskip().unwrap();
+        }
+        part.expect_count(6).unwrap();
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.inc().unwrap();
+        }
+        part.expect_count(12).unwrap();
+        drop(part);
+        assert_eq!(pattern.row_counts()[0], vec![row(6, 0, 0), row(12, 6, 0)]);
+
+        // turning chains aren't counted
+        let flat = test_pattern_flat(5).unwrap();
+        assert!(flat.row_counts()[0][1..]
+            .iter()
+            .all(|count| *count == row(5, 0, 0)));
+    }
+
+    #[test]
+    fn test_stitch_heights() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        for _ in 1..=5 {
+            part.chain().unwrap();
+        }
+        part.turn().unwrap();
+        let stitches = [
+            part.sl().unwrap(),
+            part.sc().unwrap(),
+            part.hdc().unwrap(),
+            part.dc().unwrap(),
+            part.tr().unwrap(),
+        ];
+        drop(part);
+        let pattern = pattern.into_inner();
+
+        let graph = pattern.graph();
+        let triangulated = pattern.triangulated_graph();
+        let insert_lengths = stitches.map(|stitch| {
+            let insert = graph
+                .edges(stitch)
+                .find(|e| e.weight().is_insert())
+                .unwrap();
+            triangulated[insert.id()]
+        });
+
+        let gauge = pattern.gauge();
+        assert_eq!(insert_lengths[3], gauge.height(&graph[stitches[3]]));
+        assert!((insert_lengths[3] / gauge.stitch_width() - GAUGE).abs() < 1e-6);
+        assert!(insert_lengths.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_gauge() {
+        let pattern = test_pattern_flat(3).unwrap();
+        let mut gauge = Gauge::new(20.0, 10.0);
+        gauge.heights.insert("sc", 4.0);
+        pattern.set_gauge(gauge.clone());
+        assert_eq!(gauge.stitch_width(), 5.0);
+        assert_eq!(gauge.height(&Node::stitch("dc", Vec3::ONE)), 10.0);
+        assert_eq!(gauge.height(&Node::stitch("tr", Vec3::ONE)), 15.0);
+        assert_eq!(gauge.height(&Node::stitch("sc", Vec3::ONE)), 4.0);
+
+        let graph = pattern.graph();
+        let triangulated = pattern.triangulated_graph();
+        for e in graph.edge_references() {
+            let length = triangulated[e.id()];
+            match e.weight() {
+                EdgeType::Previous if graph[e.source()].stitch_type() != "ch" => {
+                    assert_eq!(length, 5.0)
+                }
+                EdgeType::Insert(_) => assert_eq!(length, 10.0),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_clusters() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        for _ in 1..=8 {
+            part.chain().unwrap();
+        }
+        part.turn().unwrap();
+        let dc5tog = part.dec_n(5, "dc").unwrap();
+        let fan = part.inc_n(3, "sc").unwrap();
+        assert!(part.dec_n(0, "sc").is_err());
+        assert!(part.inc_n(2, "popcorn").is_err());
+        drop(part);
+        let pattern = pattern.into_inner();
+
+        let graph = pattern.graph();
+        let inserts = |node| {
+            graph
+                .edges(node)
+                .filter(|e| e.weight().is_insert())
+                .map(|e| e.target())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(graph[dc5tog].stitch_type(), "dc");
+        assert_eq!(inserts(dc5tog).iter().unique().count(), 5);
+        assert_eq!(fan.len(), 3);
+        assert!(fan.iter().all(|s| graph[*s].stitch_type() == "sc"));
+        assert!(fan.iter().map(|s| inserts(*s)).all_equal());
+    }
+
+    #[test]
+    fn test_back_loop_fold() {
+        let build = |mode| {
+            let pattern = Pattern::new();
+            let mut part = pattern.add_part();
+            for _ in 1..=6 {
+                part.chain().unwrap();
+            }
+            part.turn().unwrap();
+            for _ in 1..=6 {
+                part.sc().unwrap();
+            }
+            part.turn().unwrap();
+            part.set_insert_mode(mode);
+            for _ in 1..=6 {
+                part.sc().unwrap();
+            }
+            drop(part);
+            pattern.into_inner()
+        };
+
+        let flat = build(InsertMode::BothLoops);
+        let folded = build(InsertMode::BackLoop);
+        assert!(folded
+            .graph()
+            .edge_weights()
+            .any(|e| *e == EdgeType::Insert(InsertMode::BackLoop)));
+
+        // the back loop row is attached to the row below with fold edges, shorter than going over the ridge.
+        let (flat, folded) = (flat.triangulated_graph(), folded.triangulated_graph());
+        assert!(folded.edge_count() > flat.edge_count());
+        let fold_length = *folded.edge_weights().last().unwrap();
+        assert!(fold_length < 2.0 * Gauge::default().height(&Node::stitch("sc", Vec3::ONE)));
+    }
+
     #[test]
     fn test_triangulated() {
         use petgraph::dot::{Config, Dot};
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use rhai::{Dynamic, ImmutableString};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A set of values for a script's exported parameters. Along with the script, this defines a variant of the pattern.
+pub type ParameterSet = HashMap<ImmutableString, Dynamic>;
+
+/// The extension of a presets file, which is stored next to its script, e.g. `hat.presets.json` for `hat.ph`.
+pub const PRESETS_EXTENSION: &str = "presets.json";
+
+/// A presets file as stored on disk.
+/// Presets are stored as a list rather than a map so they keep their order, e.g. from the smallest size to the largest.
+#[derive(Serialize, Deserialize)]
+struct PresetsFile {
+    presets: Vec<PresetRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PresetRecord {
+    name: String,
+    parameters: serde_json::Map<String, Value>,
+}
+
+/// An error reading or writing a presets file.
+#[derive(Debug)]
+pub enum PresetsError {
+    Io(std::io::Error),
+    /// The file isn't valid JSON, or doesn't have the expected structure.
+    Json(serde_json::Error),
+    /// A parameter has a value that can't be stored as JSON, e.g. a function.
+    UnsupportedValue(ImmutableString),
+}
+
+impl Display for PresetsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Couldn't access presets file: {err}"),
+            Self::Json(err) => write!(f, "Invalid presets file: {err}"),
+            Self::UnsupportedValue(name) => {
+                write!(
+                    f,
+                    "The value of parameter \"{name}\" can't be saved in a preset."
+                )
+            }
+        }
+    }
+}
+
+impl Error for PresetsError {}
+
+impl From<std::io::Error> for PresetsError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PresetsError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Convert a parameter value to JSON, if it's made of values JSON can store.
+fn to_json(value: &Dynamic) -> Option<Value> {
+    if let Ok(v) = value.as_int() {
+        Some(v.into())
+    } else if let Ok(v) = value.as_float() {
+        serde_json::Number::from_f64(v).map(Value::Number)
+    } else if let Ok(v) = value.as_bool() {
+        Some(v.into())
+    } else if let Some(v) = value.read_lock::<ImmutableString>() {
+        Some(v.as_str().into())
+    } else if let Some(array) = value.read_lock::<rhai::Array>() {
+        array
+            .iter()
+            .map(to_json)
+            .collect::<Option<_>>()
+            .map(Value::Array)
+    } else if let Some(map) = value.read_lock::<rhai::Map>() {
+        map.iter()
+            .map(|(k, v)| Some((k.to_string(), to_json(v)?)))
+            .collect::<Option<_>>()
+            .map(Value::Object)
+    } else {
+        None
+    }
+}
+
+/// Convert a JSON value read from a presets file to a parameter value.
+fn from_json(value: Value) -> Dynamic {
+    match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(v) => v.into(),
+        Value::Number(v) => match v.as_i64() {
+            Some(v) => v.into(),
+            None => v.as_f64().unwrap_or_default().into(),
+        },
+        Value::String(v) => v.into(),
+        Value::Array(array) => array
+            .into_iter()
+            .map(from_json)
+            .collect::<rhai::Array>()
+            .into(),
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(k, v)| (k.into(), from_json(v)))
+            .collect::<rhai::Map>()
+            .into(),
+    }
+}
+
+/// Named sets of parameter values for a script, e.g. the sizes "Child" and "Adult", in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct Presets {
+    pub presets: Vec<(String, ParameterSet)>,
+}
+
+impl Presets {
+    /// The path of the presets file for the script at the given path.
+    pub fn path_for(script_path: &Path) -> PathBuf {
+        script_path.with_extension(PRESETS_EXTENSION)
+    }
+
+    /// Read presets from JSON.
+    pub fn from_json(json: &str) -> Result<Self, PresetsError> {
+        let file: PresetsFile = serde_json::from_str(json)?;
+        let presets = file
+            .presets
+            .into_iter()
+            .map(|preset| {
+                let parameters = preset
+                    .parameters
+                    .into_iter()
+                    .map(|(name, value)| (name.into(), from_json(value)))
+                    .collect();
+                (preset.name, parameters)
+            })
+            .collect();
+        Ok(Self { presets })
+    }
+
+    /// Write the presets as JSON.
+    pub fn to_json(&self) -> Result<String, PresetsError> {
+        let presets = self
+            .presets
+            .iter()
+            .map(|(name, parameters)| -> Result<_, PresetsError> {
+                let parameters = parameters
+                    .iter()
+                    .map(|(name, value)| {
+                        to_json(value)
+                            .map(|value| (name.to_string(), value))
+                            .ok_or_else(|| PresetsError::UnsupportedValue(name.clone()))
+                    })
+                    .try_collect::<serde_json::Map<_, _>>()?;
+                Ok(PresetRecord {
+                    name: name.clone(),
+                    parameters,
+                })
+            })
+            .try_collect::<Vec<_>>()?;
+        Ok(serde_json::to_string_pretty(&PresetsFile { presets })?)
+    }
+
+    /// Load the presets for the script at the given path. A script without a presets file has no presets.
+    pub fn load_for(script_path: &Path) -> Result<Self, PresetsError> {
+        match std::fs::read_to_string(Self::path_for(script_path)) {
+            Ok(json) => Self::from_json(&json),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Save the presets next to the script at the given path.
+    pub fn save_for(&self, script_path: &Path) -> Result<(), PresetsError> {
+        std::fs::write(Self::path_for(script_path), self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Get the parameter values of the preset with the given name.
+    pub fn get(&self, name: &str) -> Option<&ParameterSet> {
+        self.presets
+            .iter()
+            .find(|(preset, _)| preset == name)
+            .map(|(_, parameters)| parameters)
+    }
+
+    /// Set the parameter values of a preset, replacing it if there's one with the same name, or adding it at the end.
+    pub fn set(&mut self, name: &str, parameters: ParameterSet) {
+        match self.presets.iter_mut().find(|(preset, _)| preset == name) {
+            Some((_, existing)) => *existing = parameters,
+            None => self.presets.push((name.to_string(), parameters)),
+        }
+    }
+
+    /// Remove the preset with the given name, if there is one.
+    pub fn remove(&mut self, name: &str) {
+        self.presets.retain(|(preset, _)| preset != name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_round_trip() {
+        let mut presets = Presets::default();
+        presets.set(
+            "Child",
+            ParameterSet::from([
+                ("rows".into(), Dynamic::from(10_i64)),
+                ("size".into(), Dynamic::from(1.5)),
+                ("edging".into(), Dynamic::from(true)),
+                ("stitch".into(), Dynamic::from("sc")),
+            ]),
+        );
+        presets.set(
+            "Adult",
+            ParameterSet::from([(
+                "yarn".into(),
+                vec![Dynamic::from(1.0), Dynamic::from(0.5), Dynamic::from(0.0)].into(),
+            )]),
+        );
+        presets.set(
+            "Child",
+            ParameterSet::from([("rows".into(), Dynamic::from(12_i64))]),
+        );
+
+        let read = Presets::from_json(&presets.to_json().unwrap()).unwrap();
+        let names = read
+            .presets
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Child", "Adult"]);
+        assert_eq!(read.get("Child").unwrap()["rows"].as_int(), Ok(12));
+        let yarn = read.get("Adult").unwrap()["yarn"]
+            .clone()
+            .into_array()
+            .unwrap();
+        assert_eq!(yarn[1].as_float(), Ok(0.5));
+
+        let mut unsupported = Presets::default();
+        unsupported.set(
+            "f",
+            ParameterSet::from([("f".into(), Dynamic::from(rhai::FnPtr::new("f").unwrap()))]),
+        );
+        assert!(matches!(
+            unsupported.to_json(),
+            Err(PresetsError::UnsupportedValue(name)) if name == "f"
+        ));
+    }
+
+    #[test]
+    fn test_presets_path() {
+        let path = Presets::path_for(Path::new("examples/hat.ph"));
+        assert_eq!(path, Path::new("examples/hat.presets.json"));
+    }
+}
@@ -9,12 +9,14 @@ use std::{
 
 use glam::Vec3;
 use rhai::{
-    module_resolvers::FileModuleResolver, ASTFlags, Dynamic, Engine, EvalAltResult, EvalContext,
-    Expr, Expression, FnPtr, ImmutableString, Module, NativeCallContext, RhaiNativeFunc,
-    Stmt, AST,
+    module_resolvers::FileModuleResolver, ASTFlags, ASTNode, Dynamic, Engine, EvalAltResult,
+    EvalContext, Expr, Expression, FnPtr, ImmutableString, Module, NativeCallContext, ParseError,
+    Position, RhaiNativeFunc, Scope, Stmt, AST,
 };
 
-use crate::pattern::{Part, Pattern, PatternError};
+use crate::analysis::{color_hex, YarnOptions, YarnUsage};
+use crate::pattern::{Gauge, InsertMode, Node, Part, Pattern, PatternError, SourceLocation};
+use crate::presets::{ParameterSet, Presets};
 
 /// A textual script, can be loaded from or saved to a file.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -42,7 +44,6 @@ impl Script {
 
     /// Load a script from a given file path.
     pub fn load_file(path: &Path) -> std::io::Result<Self> {
-        println!("{path:?}");
         let mut f = File::open(path)?;
         let mut s = String::new();
         f.read_to_string(&mut s)?;
@@ -102,17 +103,804 @@ where
     }
 }
 
+/// Read a measurement passed to a script function, which can be an integer or a float but must be positive.
+fn positive_number(value: Dynamic) -> Result<f32, Box<EvalAltResult>> {
+    let number = value
+        .as_float()
+        .or_else(|_| value.as_int().map(|n| n as f64))
+        .map_err(|ty| format!("Expected a number, got {ty}"))?;
+    if number > 0.0 {
+        Ok(number as f32)
+    } else {
+        Err(format!("Expected a positive number, got {number}").into())
+    }
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The script can't be evaluated.
+    Error,
+    /// The script can be evaluated, but probably doesn't do what was meant.
+    Warning,
+}
+
+/// A function call or module import on the way to a [`Diagnostic`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The function called, or the path of the module imported.
+    pub name: String,
+    /// The file the call was made from, if it was in a file.
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+/// A problem found in a script, with where it is and how it was reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The file the problem is in, if it's in a file.
+    pub file: Option<String>,
+    /// The line of the problem, starting from 1.
+    pub line: Option<usize>,
+    /// The column of the problem, starting from 1.
+    pub column: Option<usize>,
+    /// The calls that led to the problem, outermost first, e.g. into functions imported from `common.ph`.
+    pub call_stack: Vec<CallFrame>,
+}
+
+impl Diagnostic {
+    /// Create an error diagnostic at a position in the given file.
+    fn error(message: impl Into<String>, file: Option<&str>, position: Position) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            file: file.map(String::from),
+            line: position.line(),
+            column: position.position(),
+            call_stack: vec![],
+        }
+    }
+
+    /// Create a warning diagnostic at a position in the given file.
+    fn warning(message: impl Into<String>, file: Option<&str>, position: Position) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, file, position)
+        }
+    }
+
+    /// Create a diagnostic from a syntax error in the given file.
+    pub fn from_parse_error(err: &ParseError, file: Option<&str>) -> Self {
+        Self::error(
+            format!("Syntax error: {}", err.err_type()),
+            file,
+            err.position(),
+        )
+    }
+
+    /// Create a diagnostic from an error evaluating a script in the given file,
+    /// following it through the function calls and module imports that led to it.
+    pub fn from_eval_error(err: &EvalAltResult, file: Option<&str>) -> Self {
+        let mut file = file.map(String::from);
+        let mut call_stack = vec![];
+        let mut err = err;
+        loop {
+            let (name, inner, position, inner_file) = match err {
+                EvalAltResult::ErrorInFunctionCall(name, source, inner, position) => {
+                    let inner_file = if source.is_empty() {
+                        file.clone()
+                    } else {
+                        Some(module_file(file.as_deref(), source))
+                    };
+                    (name, inner, position, inner_file)
+                }
+                EvalAltResult::ErrorInModule(path, inner, position) => (
+                    path,
+                    inner,
+                    position,
+                    Some(module_file(file.as_deref(), path)),
+                ),
+                _ => break,
+            };
+            call_stack.push(CallFrame {
+                name: name.clone(),
+                file: file.clone(),
+                line: position.line(),
+                column: position.position(),
+            });
+            file = inner_file;
+            err = inner;
+        }
+
+        let position = err.position();
+        let message = match err {
+            EvalAltResult::ErrorRuntime(value, _) => value.to_string(),
+            EvalAltResult::ErrorParsing(err, _) => format!("Syntax error: {err}"),
+            _ => {
+                let message = err.to_string();
+                message
+                    .strip_suffix(&format!(" ({position})"))
+                    .map_or(message.clone(), String::from)
+            }
+        };
+        Self {
+            call_stack,
+            ..Self::error(message, file.as_deref(), position)
+        }
+    }
+
+    /// Whether the problem, or a call leading to it, is at the given line of a file.
+    pub fn is_at(&self, file: Option<&str>, line: usize) -> bool {
+        (self.file.as_deref() == file && self.line == Some(line))
+            || self
+                .call_stack
+                .iter()
+                .any(|frame| frame.file.as_deref() == file && frame.line == Some(line))
+    }
+}
+
+/// The file of a module imported from a script, which is resolved relative to the importing file.
+fn module_file(importer: Option<&str>, module: &str) -> String {
+    if module.ends_with(".ph") {
+        return module.to_string();
+    }
+    importer
+        .and_then(|importer| Path::new(importer).parent())
+        .unwrap_or(Path::new(""))
+        .join(module)
+        .with_extension("ph")
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(line) = self.line {
+            write!(f, " (line {line}")?;
+            if let Some(column) = self.column {
+                write!(f, ", column {column}")?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(file) = &self.file {
+            write!(f, " in {file}")?;
+        }
+        for frame in self.call_stack.iter().rev() {
+            write!(f, "\nin '{}'", frame.name)?;
+            if let Some(line) = frame.line {
+                write!(f, " called at line {line}")?;
+            }
+            if let Some(file) = &frame.file {
+                write!(f, " of {file}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+/// The kind of value an exported parameter takes, and the values it's limited to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterKind {
+    Int {
+        min: Option<i64>,
+        max: Option<i64>,
+        step: Option<i64>,
+    },
+    Float {
+        min: Option<f64>,
+        max: Option<f64>,
+        step: Option<f64>,
+    },
+    Bool,
+    /// One of a list of strings.
+    Choice(Vec<ImmutableString>),
+    /// An RGB colour, as an array of three numbers from 0 to 1.
+    Color,
+    /// Any other value, which is passed to the script as it's given.
+    Other,
+}
+
+/// An exported parameter of a script, declared either as a literal, e.g. `export let rows = 10;`,
+/// or with options using `param`, e.g. `export let rows = param(10, #{ min: 1, max: 50 });`.
+/// Any other expression, e.g. `export let rows = width * 2;`, derives the parameter from the others.
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: ImmutableString,
+    /// The parameter's default value, or for a derived parameter, the value worked out from the others.
+    pub default: Dynamic,
+    pub kind: ParameterKind,
+    pub description: Option<String>,
+    /// Whether the parameter is worked out from other parameters, so can't be set itself.
+    pub derived: bool,
+}
+
+/// The options that can be given to `param`.
+const PARAMETER_OPTIONS: [&str; 6] = ["min", "max", "step", "choices", "color", "description"];
+
+/// Read a number, whether it's an integer or a float.
+fn as_number(value: &Dynamic) -> Option<f64> {
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|v| v as f64))
+}
+
+/// Check a value is within an optional minimum and maximum.
+fn check_range<T: PartialOrd + std::fmt::Display>(
+    name: &str,
+    value: T,
+    min: Option<T>,
+    max: Option<T>,
+) -> Result<(), String> {
+    if let Some(min) = min.filter(|min| value < *min) {
+        return Err(format!(
+            "Parameter \"{name}\" is {value}, below its minimum of {min}."
+        ));
+    }
+    if let Some(max) = max.filter(|max| value > *max) {
+        return Err(format!(
+            "Parameter \"{name}\" is {value}, above its maximum of {max}."
+        ));
+    }
+    Ok(())
+}
+
+impl Parameter {
+    /// Read a parameter from the expression it's exported with, which should be a literal or a call to `param`.
+    pub fn from_expr(name: &str, expr: &Expr) -> Result<Self, String> {
+        let (default, options) = match expr {
+            Expr::FnCall(call, _) if call.name == "param" && !call.is_qualified() => {
+                let literal = |i: usize| -> Result<Option<Dynamic>, String> {
+                    call.args
+                        .get(i)
+                        .map(|arg| {
+                            arg.get_literal_value().ok_or_else(|| {
+                                format!("The arguments to param for \"{name}\" must be literals.")
+                            })
+                        })
+                        .transpose()
+                };
+                let options = literal(1)?
+                    .map(|options| {
+                        options.try_cast::<rhai::Map>().ok_or_else(|| {
+                            format!("The options for \"{name}\" must be a map, e.g. #{{ min: 1 }}.")
+                        })
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                let default = literal(0)?
+                    .ok_or_else(|| format!("Parameter \"{name}\" needs a default value."))?;
+                (default, options)
+            }
+            _ => (
+                expr.get_literal_value().ok_or_else(|| {
+                    format!("Exported parameter \"{name}\" must be a literal or a call to param.")
+                })?,
+                rhai::Map::new(),
+            ),
+        };
+        Self::new(name, default, &options)
+    }
+
+    /// Create a parameter from its default value and the options given to `param`.
+    pub fn new(name: &str, default: Dynamic, options: &rhai::Map) -> Result<Self, String> {
+        if let Some(key) = options
+            .keys()
+            .find(|key| !PARAMETER_OPTIONS.contains(&key.as_str()))
+        {
+            return Err(format!(
+                "Unknown option \"{key}\" for parameter \"{name}\". Use one of {}.",
+                PARAMETER_OPTIONS.join(", ")
+            ));
+        }
+        let option = |key: &str| options.get(key).cloned();
+        let int_option = |key: &str| {
+            option(key)
+                .map(|v| {
+                    v.as_int()
+                        .map_err(|_| format!("The {key} of \"{name}\" must be an integer."))
+                })
+                .transpose()
+        };
+        let float_option = |key: &str| {
+            option(key)
+                .map(|v| {
+                    as_number(&v)
+                        .ok_or_else(|| format!("The {key} of \"{name}\" must be a number."))
+                })
+                .transpose()
+        };
+
+        let kind = if let Some(choices) = option("choices") {
+            let choices = choices
+                .try_cast::<rhai::Array>()
+                .and_then(|choices| {
+                    choices
+                        .into_iter()
+                        .map(|choice| choice.into_immutable_string().ok())
+                        .collect::<Option<Vec<_>>>()
+                })
+                .filter(|choices| !choices.is_empty())
+                .ok_or_else(|| format!("The choices of \"{name}\" must be a list of strings."))?;
+            ParameterKind::Choice(choices)
+        } else if option("color").is_some_and(|color| color.as_bool() == Ok(true)) {
+            ParameterKind::Color
+        } else if default.is_bool() {
+            ParameterKind::Bool
+        } else if default.is_int()
+            && !["min", "max", "step"]
+                .iter()
+                .any(|key| option(key).is_some_and(|v| v.is_float()))
+        {
+            ParameterKind::Int {
+                min: int_option("min")?,
+                max: int_option("max")?,
+                step: int_option("step")?,
+            }
+        } else if default.is_int() || default.is_float() {
+            ParameterKind::Float {
+                min: float_option("min")?,
+                max: float_option("max")?,
+                step: float_option("step")?,
+            }
+        } else {
+            ParameterKind::Other
+        };
+
+        let parameter = Self {
+            name: name.into(),
+            default: Dynamic::UNIT,
+            kind,
+            description: option("description").map(|description| description.to_string()),
+            derived: false,
+        };
+        let default = parameter
+            .coerce(default)
+            .map_err(|err| format!("Invalid default value: {err}"))?;
+        Ok(Self {
+            default,
+            ..parameter
+        })
+    }
+
+    /// Create a parameter worked out from other parameters, with the value it's worked out as.
+    pub fn derived(name: &str, value: Dynamic) -> Self {
+        Self {
+            name: name.into(),
+            default: value,
+            kind: ParameterKind::Other,
+            description: None,
+            derived: true,
+        }
+    }
+
+    /// Check a value is allowed for this parameter, converting it to the parameter's type if needed,
+    /// e.g. an integer given for a float parameter.
+    pub fn coerce(&self, value: Dynamic) -> Result<Dynamic, String> {
+        let name = &self.name;
+        let type_error = |expected: &str| {
+            format!(
+                "Parameter \"{name}\" should be {expected}, got {}.",
+                value.type_name()
+            )
+        };
+        match &self.kind {
+            ParameterKind::Int { min, max, .. } => {
+                let v = value.as_int().map_err(|_| type_error("an integer"))?;
+                check_range(name, v, *min, *max)?;
+                Ok(v.into())
+            }
+            ParameterKind::Float { min, max, .. } => {
+                let v = as_number(&value).ok_or_else(|| type_error("a number"))?;
+                check_range(name, v, *min, *max)?;
+                Ok(v.into())
+            }
+            ParameterKind::Bool => Ok(value
+                .as_bool()
+                .map_err(|_| type_error("true or false"))?
+                .into()),
+            ParameterKind::Choice(choices) => {
+                let v = value
+                    .clone()
+                    .into_immutable_string()
+                    .map_err(|_| type_error("a string"))?;
+                if choices.contains(&v) {
+                    Ok(v.into())
+                } else {
+                    Err(format!(
+                        "Parameter \"{name}\" is \"{v}\", which isn't one of {}.",
+                        choices.join(", ")
+                    ))
+                }
+            }
+            ParameterKind::Color => {
+                let color = value
+                    .clone()
+                    .try_cast::<rhai::Array>()
+                    .and_then(|color| color.iter().map(as_number).collect::<Option<Vec<_>>>())
+                    .filter(|color| {
+                        color.len() == 3 && color.iter().all(|c| (0.0..=1.0).contains(c))
+                    })
+                    .ok_or_else(|| type_error("an RGB colour like [1.0, 0.5, 0.0]"))?;
+                Ok(color
+                    .into_iter()
+                    .map(Dynamic::from)
+                    .collect::<rhai::Array>()
+                    .into())
+            }
+            ParameterKind::Other => Ok(value),
+        }
+    }
+}
+
+/// If a statement exports a parameter, get its name, the expression it's set to and its position.
+fn exported_var(stmt: &Stmt) -> Option<(&str, &Expr, Position)> {
+    match stmt {
+        Stmt::Var(body, flags, position)
+            if flags.contains(ASTFlags::EXPORTED) && !flags.contains(ASTFlags::CONSTANT) =>
+        {
+            Some((body.0.name.as_str(), &body.1, *position))
+        }
+        _ => None,
+    }
+}
+
+/// Whether an exported parameter is declared with a value of its own, rather than derived from other parameters.
+fn is_declared(expr: &Expr) -> bool {
+    matches!(expr, Expr::FnCall(call, _) if call.name == "param" && !call.is_qualified())
+        || expr.get_literal_value().is_some()
+}
+
+/// The names of the variables used in an expression.
+fn variables_used(expr: &Expr) -> Vec<ImmutableString> {
+    let mut names = vec![];
+    expr.walk(&mut vec![], &mut |path| {
+        if let Some(ASTNode::Expr(Expr::Variable(x, ..))) = path.last() {
+            if x.2.is_empty() {
+                names.push(x.1.clone());
+            }
+        }
+        true
+    });
+    names
+}
+
+/// Warn about each exported parameter that the script never uses, as changing it does nothing.
+fn unused_parameters(ast: &AST, file: Option<&str>) -> Vec<Diagnostic> {
+    let mut used = vec![];
+    ast.walk(&mut |path| {
+        if let Some(ASTNode::Expr(Expr::Variable(x, ..))) = path.last() {
+            used.push(x.1.clone());
+        }
+        true
+    });
+
+    ast.statements()
+        .iter()
+        .filter_map(exported_var)
+        .filter(|(name, ..)| !used.iter().any(|used| used.as_str() == *name))
+        .map(|(name, _, position)| {
+            Diagnostic::warning(
+                format!("Parameter \"{name}\" is never used."),
+                file,
+                position,
+            )
+        })
+        .collect()
+}
+
+/// What derived parameters can be worked out from, for errors when they use something else.
+const DERIVED_USES: &str = "Parameters can only be worked out from other parameters, \
+                            and the constants, variables and imports declared before them.";
+
+/// Work out the value of each exported parameter of a script, given the values of the parameters that are set.
+/// Derived parameters are worked out from the parameters they use, in dependency order, so they can use
+/// parameters declared after them.
+fn resolve_parameters(
+    ast: &AST,
+    exports: &HashMap<ImmutableString, Dynamic>,
+    file: Option<&str>,
+) -> Result<Vec<(Parameter, Dynamic)>, Diagnostic> {
+    let statements = ast
+        .statements()
+        .iter()
+        .filter(|stmt| exported_var(stmt).is_some())
+        .collect::<Vec<_>>();
+    let vars = statements
+        .iter()
+        .filter_map(|stmt| exported_var(stmt))
+        .collect::<Vec<_>>();
+
+    // the parameters declared with their own values, and what they're set to
+    let mut values = vars
+        .iter()
+        .map(|&(name, expr, position)| {
+            if !is_declared(expr) {
+                if exports.contains_key(name) {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "Parameter \"{name}\" is worked out from other parameters, so can't be set."
+                        ),
+                        file,
+                        position,
+                    ));
+                }
+                return Ok(None);
+            }
+            let parameter = Parameter::from_expr(name, expr)
+                .map_err(|err| Diagnostic::error(err, file, position))?;
+            let value = match exports.get(name) {
+                Some(value) => parameter
+                    .coerce(value.clone())
+                    .map_err(|err| Diagnostic::error(err, file, position))?,
+                None => parameter.default.clone(),
+            };
+            Ok(Some((parameter, value)))
+        })
+        .try_collect::<Vec<_>>()?;
+
+    // order the derived parameters so each comes after the parameters it uses
+    let dependencies = vars
+        .iter()
+        .map(|(_, expr, _)| {
+            variables_used(expr)
+                .into_iter()
+                .filter_map(|used| vars.iter().position(|(name, ..)| *name == used.as_str()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut resolved = values.iter().map(Option::is_some).collect::<Vec<_>>();
+    let mut order = vec![];
+    while let Some(unresolved) = resolved.iter().position(|done| !done) {
+        let next = (0..vars.len())
+            .find(|&i| !resolved[i] && dependencies[i].iter().all(|&dep| resolved[dep]));
+        let Some(next) = next else {
+            let names = (0..vars.len())
+                .filter(|&i| !resolved[i])
+                .map(|i| format!("\"{}\"", vars[i].0))
+                .collect::<Vec<_>>();
+            return Err(Diagnostic::error(
+                format!(
+                    "Parameters {} depend on each other, so can't be worked out.",
+                    names.join(", ")
+                ),
+                file,
+                vars[unresolved].2,
+            ));
+        };
+        resolved[next] = true;
+        order.push(next);
+    }
+
+    if !order.is_empty() {
+        let all_statements = ast.statements();
+        let first_export = all_statements
+            .iter()
+            .position(|stmt| exported_var(stmt).is_some())
+            .unwrap_or_default();
+        let last_export = all_statements
+            .iter()
+            .rposition(|stmt| exported_var(stmt).is_some())
+            .unwrap_or_default();
+
+        // derived parameters can also use the constants and variables declared before the parameters,
+        // so work out which of those they need
+        let mut needed = order
+            .iter()
+            .flat_map(|&i| variables_used(vars[i].1))
+            .collect::<Vec<_>>();
+        let mut usable = vec![false; first_export];
+        for (i, stmt) in all_statements[..first_export].iter().enumerate().rev() {
+            if let Stmt::Var(body, ..) = stmt {
+                if needed.contains(&body.0.name) {
+                    needed.extend(variables_used(&body.1));
+                    usable[i] = true;
+                }
+            }
+        }
+        for stmt in &all_statements[first_export..=last_export] {
+            let Stmt::Var(body, ..) = stmt else {
+                continue;
+            };
+            if exported_var(stmt).is_some() {
+                continue;
+            }
+            if let Some(&i) = order
+                .iter()
+                .find(|&&i| variables_used(vars[i].1).contains(&body.0.name))
+            {
+                return Err(Diagnostic::error(
+                    format!(
+                        "Parameter \"{}\" uses \"{}\", which is declared between the parameters. {DERIVED_USES}",
+                        vars[i].0, body.0.name
+                    ),
+                    file,
+                    vars[i].2,
+                ));
+            }
+        }
+
+        let mut engine = PatternScript::create_compile_engine();
+        // derived parameters are worked out as the script is edited, so shouldn't be able to hang
+        engine.set_max_operations(1_000_000);
+
+        // variables declared earlier are looked up by where they were declared, so every declaration is kept in its place.
+        // Each pass works out the derived parameters whose parameters are all known by then,
+        // with the parameters already known declared as their values and the rest as `()`.
+        let mut pending = order;
+        while !pending.is_empty() {
+            let (ready, rest): (Vec<_>, Vec<_>) = pending
+                .iter()
+                .partition(|&&i| dependencies[i].iter().all(|&dep| values[dep].is_some()));
+
+            let mut parameter = 0;
+            let stmts = all_statements[..=last_export]
+                .iter()
+                .enumerate()
+                .filter_map(|(i, stmt)| {
+                    let value = match stmt {
+                        Stmt::Import(..) => return Some(stmt.clone()),
+                        Stmt::Var(..) if exported_var(stmt).is_some() => {
+                            parameter += 1;
+                            match &values[parameter - 1] {
+                                Some((_, value)) => value.clone(),
+                                None if ready.contains(&(parameter - 1)) => {
+                                    return Some(stmt.clone())
+                                }
+                                None => Dynamic::UNIT,
+                            }
+                        }
+                        Stmt::Var(..) if usable.get(i).copied().unwrap_or_default() => {
+                            return Some(stmt.clone())
+                        }
+                        Stmt::Var(..) => Dynamic::UNIT,
+                        _ => return None,
+                    };
+                    let mut stmt = stmt.clone();
+                    if let Stmt::Var(ref mut body, ..) = stmt {
+                        body.1 = Expr::from_dynamic(value, body.1.position());
+                    }
+                    Some(stmt)
+                })
+                .collect::<Vec<_>>();
+            let mut derive_ast =
+                AST::new(stmts, Module::default()).merge(&ast.clone_functions_only());
+            if let Some(source) = ast.source() {
+                derive_ast.set_source(source);
+            }
+
+            // parameters used before they're declared are looked up by name instead,
+            // so the known ones are also put in scope ahead of the script
+            let mut scope = Scope::new();
+            for (name, value) in vars.iter().zip(&values).filter_map(|((name, ..), value)| {
+                value.as_ref().map(|(_, value)| (*name, value.clone()))
+            }) {
+                scope.push_dynamic(name, value);
+            }
+            engine
+                .run_ast_with_scope(&mut scope, &derive_ast)
+                .map_err(|err| match *err {
+                    EvalAltResult::ErrorVariableNotFound(ref name, position) => Diagnostic::error(
+                        format!("Variable \"{name}\" not found. {DERIVED_USES}"),
+                        file,
+                        position,
+                    ),
+                    _ => Diagnostic::from_eval_error(&err, file),
+                })?;
+
+            for i in ready {
+                let (name, ..) = vars[i];
+                let value = scope.get_value::<Dynamic>(name).unwrap_or_default();
+                values[i] = Some((Parameter::derived(name, value.clone()), value));
+            }
+            pending = rest;
+        }
+    }
+
+    Ok(values.into_iter().flatten().collect())
+}
+
+/// The location in the script of a native function call.
+fn call_location(ctx: &NativeCallContext) -> Option<SourceLocation> {
+    ctx.position().line().map(|line| SourceLocation {
+        file: ctx
+            .global_runtime_state()
+            .source
+            .as_ref()
+            .map(|source| source.to_string()),
+        line,
+    })
+}
+
+/// The variants of a pattern to evaluate in a sweep.
+#[derive(Clone, Debug)]
+pub enum Sweep {
+    /// Every combination of the given values of each parameter, e.g. a grid of sizes and gauges.
+    Grid(Vec<(ImmutableString, Vec<Dynamic>)>),
+    /// A list of named sets of parameter values, e.g. from presets.
+    List(Vec<(String, ParameterSet)>),
+}
+
+impl From<&Presets> for Sweep {
+    fn from(presets: &Presets) -> Self {
+        Self::List(presets.presets.clone())
+    }
+}
+
+impl Sweep {
+    /// The name and parameter values of each variant. Variants in a grid are named by their values, e.g. `rows=10, width=5`.
+    pub fn variants(&self) -> Vec<(String, ParameterSet)> {
+        match self {
+            Self::Grid(axes) => axes.iter().fold(
+                vec![(String::new(), ParameterSet::new())],
+                |variants, (name, values)| {
+                    variants
+                        .iter()
+                        .flat_map(|(variant, parameters)| {
+                            values.iter().map(move |value| {
+                                let separator = if variant.is_empty() { "" } else { ", " };
+                                let mut parameters = parameters.clone();
+                                parameters.insert(name.clone(), value.clone());
+                                (format!("{variant}{separator}{name}={value}"), parameters)
+                            })
+                        })
+                        .collect()
+                },
+            ),
+            Self::List(variants) => variants.clone(),
+        }
+    }
+}
+
+/// Stats of one variant of a pattern evaluated in a sweep.
+#[derive(Clone, Debug)]
+pub struct VariantStats {
+    pub parts: usize,
+    pub rows: usize,
+    /// The number of stitches in all rows, not counting turning chains.
+    pub stitches: usize,
+    pub yarn: YarnUsage,
+}
+
+impl VariantStats {
+    fn new(pattern: &Pattern, yarn: &YarnOptions) -> Self {
+        let row_counts = pattern.row_counts();
+        Self {
+            parts: row_counts.len(),
+            rows: row_counts.iter().map(Vec::len).sum(),
+            stitches: row_counts.iter().flatten().map(|row| row.stitches).sum(),
+            yarn: pattern.yarn_usage(yarn),
+        }
+    }
+}
+
+/// The result of evaluating one variant of a pattern in a sweep.
+#[derive(Debug)]
+pub struct VariantResult {
+    pub name: String,
+    pub parameters: ParameterSet,
+    pub stats: Result<VariantStats, Box<dyn Error + Send + Sync>>,
+}
+
 pub struct PatternScript;
 
 impl PatternScript {
     /// Create the Rhai engine used to compile a script, without added functions for evaluation.
-    fn create_compile_engine() -> rhai::Engine {
+    pub(crate) fn create_compile_engine() -> rhai::Engine {
         let mut engine = Engine::new();
 
         engine
             .set_max_expr_depths(64, 64)
             .set_module_resolver(FileModuleResolver::new_with_extension("ph"))
             .register_type_with_name::<petgraph::graph::NodeIndex>("StitchMark")
+            .register_fn("param", |default: Dynamic| default)
+            .register_fn("param", |default: Dynamic, _options: rhai::Map| default)
             .register_custom_syntax(
                 vec!["rep", "$expr$", "$expr$"],
                 true,
@@ -138,25 +926,51 @@ impl PatternScript {
     pub fn create_engine(pattern: Arc<Pattern>, part: Arc<RwLock<Part>>) -> rhai::Engine {
         let mut engine = PatternScript::create_compile_engine();
 
+        /// Lock the part, recording the script call's location for any nodes it makes.
+        fn located<'a>(
+            part: &'a RwLock<Part>,
+            ctx: &NativeCallContext,
+        ) -> std::sync::RwLockWriteGuard<'a, Part> {
+            let mut part = part.write().unwrap();
+            part.set_location(call_location(ctx));
+            part
+        }
+
         fn callback<F>(
             part: Arc<RwLock<Part>>,
             func: F,
-        ) -> impl RhaiNativeFunc<(), 0, false, (), false>
+        ) -> impl RhaiNativeFunc<(), 0, true, (), false>
         where
             F: Fn(&mut Part) + 'static + Send + Sync,
         {
-            move || func(&mut part.write().unwrap())
+            move |ctx: NativeCallContext| func(&mut located(&part, &ctx))
         }
 
         fn callback_fallible<F, R>(
             part: Arc<RwLock<Part>>,
             func: F,
-        ) -> impl RhaiNativeFunc<(), 0, false, R, true>
+        ) -> impl RhaiNativeFunc<(), 0, true, R, true>
         where
             F: Fn(&mut Part) -> Result<R, PatternError> + 'static + Send + Sync,
             R: Clone + Send + Sync + 'static,
         {
-            move || func(&mut part.write().unwrap()).map_err(|err| format!("{err}").into())
+            move |ctx: NativeCallContext| {
+                func(&mut located(&part, &ctx)).map_err(|err| format!("{err}").into())
+            }
+        }
+
+        fn scoped_insert_mode(
+            part: Arc<RwLock<Part>>,
+            mode: InsertMode,
+        ) -> impl Fn(NativeCallContext, FnPtr) -> Result<(), Box<EvalAltResult>> + Send + Sync
+        {
+            move |ctx, func| {
+                let previous = part.read().unwrap().insert_mode();
+                part.write().unwrap().set_insert_mode(mode);
+                let result = func.call_within_context::<()>(&ctx, ());
+                part.write().unwrap().set_insert_mode(previous);
+                result
+            }
         }
 
         #[allow(deprecated)]
@@ -172,10 +986,54 @@ impl PatternScript {
             .register_fn("turn_", callback_fallible(part.clone(), Part::turn_noskip))
             .register_fn("new_row", callback_fallible(part.clone(), Part::new_row))
             .register_fn("chain", callback_fallible(part.clone(), Part::chain))
+            .register_fn("sl", callback_fallible(part.clone(), Part::sl))
+            .register_fn("sl_", callback_fallible(part.clone(), Part::sl_noskip))
+            .register_fn("sc", callback_fallible(part.clone(), Part::sc))
+            .register_fn("sc_", callback_fallible(part.clone(), Part::sc_noskip))
+            .register_fn("hdc", callback_fallible(part.clone(), Part::hdc))
+            .register_fn("hdc_", callback_fallible(part.clone(), Part::hdc_noskip))
             .register_fn("dc", callback_fallible(part.clone(), Part::dc))
             .register_fn("dc_", callback_fallible(part.clone(), Part::dc_noskip))
+            .register_fn("tr", callback_fallible(part.clone(), Part::tr))
+            .register_fn("tr_", callback_fallible(part.clone(), Part::tr_noskip))
+            .register_fn("dtr", callback_fallible(part.clone(), Part::dtr))
+            .register_fn("dtr_", callback_fallible(part.clone(), Part::dtr_noskip))
             .register_fn("dec", callback_fallible(part.clone(), Part::dec))
+            .register_fn("dec_n", {
+                let part = part.clone();
+                move |ctx: NativeCallContext,
+                      n: i64,
+                      ty: ImmutableString|
+                      -> Result<_, Box<EvalAltResult>> {
+                    located(&part, &ctx)
+                        .dec_n(n.try_into().unwrap_or_default(), &ty)
+                        .map_err(|err| format!("{err}").into())
+                }
+            })
+            .register_fn("inc_n", {
+                let part = part.clone();
+                move |ctx: NativeCallContext,
+                      n: i64,
+                      ty: ImmutableString|
+                      -> Result<Dynamic, Box<EvalAltResult>> {
+                    located(&part, &ctx)
+                        .inc_n(n.try_into().unwrap_or_default(), &ty)
+                        .map(|v| v.into())
+                        .map_err(|err| format!("{err}").into())
+                }
+            })
             .register_fn("skip", callback_fallible(part.clone(), Part::skip))
+            .register_fn("expect_count", {
+                let part = part.clone();
+                move |expected: i64| -> Result<(), Box<EvalAltResult>> {
+                    let expected = usize::try_from(expected)
+                        .map_err(|_| format!("{}", PatternError::InvalidCount))?;
+                    part.read()
+                        .unwrap()
+                        .expect_count(expected)
+                        .map_err(|err| format!("{err}").into())
+                }
+            })
             .register_fn("magic_ring", callback(part.clone(), Part::magic_ring))
             .register_fn("mark", {
                 let part = part.clone();
@@ -218,9 +1076,7 @@ impl PatternScript {
                         .start_ch_sp()
                         .map_err(|err| -> Box<EvalAltResult> { format!("{err}").into() })?;
                     func.call_within_context::<()>(&ctx, ())?;
-                    let ch_sp = part
-                        .write()
-                        .unwrap()
+                    let ch_sp = located(&part, &ctx)
                         .end_ch_sp()
                         .map_err(|err| -> Box<EvalAltResult> { format!("{err}").into() })?;
                     Ok(ch_sp)
@@ -239,6 +1095,19 @@ impl PatternScript {
                     Ok(())
                 }
             })
+            .register_fn(
+                "flo",
+                scoped_insert_mode(part.clone(), InsertMode::FrontLoop),
+            )
+            .register_fn(
+                "blo",
+                scoped_insert_mode(part.clone(), InsertMode::BackLoop),
+            )
+            .register_fn(
+                "fp",
+                scoped_insert_mode(part.clone(), InsertMode::FrontPost),
+            )
+            .register_fn("bp", scoped_insert_mode(part.clone(), InsertMode::BackPost))
             .register_fn("sew", {
                 let pattern = pattern.clone();
                 move |row_1: rhai::Array, row_2: rhai::Array| -> Result<(), Box<EvalAltResult>> {
@@ -270,6 +1139,46 @@ impl PatternScript {
 
                     Ok(())
                 }
+            })
+            .register_fn("stuffing", {
+                let part = part.clone();
+                move |pressure: f64| part.write().unwrap().set_stuffing(pressure as f32)
+            })
+            .register_fn("stuffing", {
+                let part = part.clone();
+                move |pressure: i64| part.write().unwrap().set_stuffing(pressure as f32)
+            })
+            .register_fn("gauge", {
+                let pattern = pattern.clone();
+                move |stitches: Dynamic, rows: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                    let heights = pattern.gauge().heights;
+                    pattern.set_gauge(Gauge {
+                        heights,
+                        ..Gauge::new(positive_number(stitches)?, positive_number(rows)?)
+                    });
+                    Ok(())
+                }
+            })
+            .register_fn("stitch_height", {
+                let pattern = pattern.clone();
+                move |ty: &str, height: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                    let ty = Node::worked_stitch_type(ty).map_err(|err| format!("{err}"))?;
+                    let mut gauge = pattern.gauge();
+                    gauge.heights.insert(ty, positive_number(height)?);
+                    pattern.set_gauge(gauge);
+                    Ok(())
+                }
+            })
+            .register_fn("yarn_usage", {
+                let pattern = pattern.clone();
+                move || -> rhai::Map {
+                    pattern
+                        .yarn_usage(&YarnOptions::default())
+                        .colors
+                        .into_iter()
+                        .map(|usage| (color_hex(usage.color).into(), (usage.length as f64).into()))
+                        .collect()
+                }
             });
         // .on_var(|name, _index, ctx| {
         //     let var = ctx.scope().get_value::<Dynamic>(name);
@@ -285,73 +1194,105 @@ impl PatternScript {
         engine
     }
 
-    /// Parse a script and get all exported parameters from it.
-    pub fn get_script_exports(
+    /// Check a script for problems that can be found without evaluating it in full,
+    /// i.e. syntax errors, exported parameters that are invalid or can't be worked out, and unused parameters.
+    pub fn check_script(script: &Script) -> Vec<Diagnostic> {
+        let file = script.file_path.as_deref().and_then(|path| path.to_str());
+        let engine = PatternScript::create_compile_engine();
+        let ast = match engine.compile(&script.contents) {
+            Ok(ast) => ast,
+            Err(err) => return vec![Diagnostic::from_parse_error(&err, file)],
+        };
+
+        // which parameters are unused is only worth knowing once they can all be worked out
+        match resolve_parameters(&ast, &HashMap::new(), file) {
+            Ok(_) => unused_parameters(&ast, file),
+            Err(err) => vec![err],
+        }
+    }
+
+    /// Parse a script and get all exported parameters from it, with the values they're limited to.
+    pub fn get_script_parameters(
         script: &Script,
-    ) -> Result<Vec<(ImmutableString, Dynamic)>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Vec<Parameter>, Box<dyn Error + Send + Sync>> {
+        let file = script.file_path.as_deref().and_then(|path| path.to_str());
         let engine = PatternScript::create_compile_engine();
-        let ast = engine.compile(&script.contents)?;
+        let ast = engine
+            .compile(&script.contents)
+            .map_err(|err| Diagnostic::from_parse_error(&err, file))?;
 
-        let exports = ast
-            .statements()
-            .iter()
-            .filter_map(
-                |stmt| -> Option<Result<(ImmutableString, Dynamic), Box<dyn Error + Send + Sync>>> {
-                    let mut stmt = stmt.clone();
+        Ok(resolve_parameters(&ast, &HashMap::new(), file)?
+            .into_iter()
+            .map(|(parameter, _)| parameter)
+            .collect())
+    }
 
-                    if let rhai::Stmt::Var(ref mut body, flags, _position) = stmt {
-                        if flags.contains(ASTFlags::EXPORTED) && !flags.contains(ASTFlags::CONSTANT)
-                        {
-                            let value = body
-                                .1
-                                .get_literal_value()
-                                .ok_or("Exported parameter must be a literal.".to_string().into());
-                            Some(value.map(|v| (body.0.name.clone(), v)))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                },
-            )
-            .try_collect::<Vec<_>>()?;
+    /// Parse a script and get the value of each exported parameter when the given parameters are set,
+    /// including those derived from them.
+    pub fn get_parameter_values(
+        script: &Script,
+        exports: &HashMap<ImmutableString, Dynamic>,
+    ) -> Result<Vec<(ImmutableString, Dynamic)>, Box<dyn Error + Send + Sync>> {
+        let file = script.file_path.as_deref().and_then(|path| path.to_str());
+        let engine = PatternScript::create_compile_engine();
+        let ast = engine
+            .compile(&script.contents)
+            .map_err(|err| Diagnostic::from_parse_error(&err, file))?;
 
-        println!("{exports:?}");
+        Ok(resolve_parameters(&ast, exports, file)?
+            .into_iter()
+            .map(|(parameter, value)| (parameter.name, value))
+            .collect())
+    }
 
-        Ok(exports)
+    /// Parse a script and get all exported parameters from it, with their default values.
+    pub fn get_script_exports(
+        script: &Script,
+    ) -> Result<Vec<(ImmutableString, Dynamic)>, Box<dyn Error + Send + Sync>> {
+        Ok(PatternScript::get_script_parameters(script)?
+            .into_iter()
+            .map(|parameter| (parameter.name, parameter.default))
+            .collect())
     }
 
-    /// Parse a script and replace all exported parameters with their given values in the [`HashMap`].
+    /// Parse a script and replace all exported parameters with their given values in the [`HashMap`],
+    /// and the parameters derived from them with the values they're worked out as.
+    /// Fails if a value isn't allowed for its parameter, e.g. it's outside the parameter's range,
+    /// or if it's given for a derived parameter.
     pub fn preprocess_script(
         script: &Script,
         exports: &HashMap<ImmutableString, Dynamic>,
     ) -> Result<AST, Box<dyn Error + Send + Sync>> {
+        let file = script.file_path.as_deref().and_then(|path| path.to_str());
         let engine = PatternScript::create_compile_engine();
-        let mut ast = engine.compile(&script.contents)?;
-        if let Some(path) = &script.file_path {
-            ast.set_source(path.to_str().unwrap());
+        let mut ast = engine
+            .compile(&script.contents)
+            .map_err(|err| Diagnostic::from_parse_error(&err, file))?;
+        if let Some(file) = file {
+            ast.set_source(file);
         }
 
+        let mut parameters = resolve_parameters(&ast, exports, file)?.into_iter();
         let new_stmts = ast
             .statements()
             .iter()
-            .map(|stmt| -> Result<Stmt, Box<dyn Error + Send + Sync>> {
+            .map(|stmt| {
                 let mut stmt = stmt.clone();
-
-                if let rhai::Stmt::Var(ref mut body, flags, _position) = stmt {
-                    if flags.contains(ASTFlags::EXPORTED) && !flags.contains(ASTFlags::CONSTANT) {
-                        if let Some(v) = exports.get(&body.0.name) {
-                            body.1 = Expr::from_dynamic(v.clone(), body.1.position());
-                        }
+                if exported_var(&stmt).is_some() {
+                    if let (Stmt::Var(ref mut body, ..), Some((_, value))) =
+                        (&mut stmt, parameters.next())
+                    {
+                        body.1 = Expr::from_dynamic(value, body.1.position());
                     }
                 }
-
-                Ok(stmt)
+                stmt
             })
-            .try_collect::<Vec<_>>()?;
+            .collect::<Vec<_>>();
 
-        let new_ast = AST::new(new_stmts, Module::default()).merge(&ast.clone_functions_only());
+        let mut new_ast = AST::new(new_stmts, Module::default()).merge(&ast.clone_functions_only());
+        if let Some(source) = ast.source() {
+            new_ast.set_source(source);
+        }
 
         Ok(new_ast)
     }
@@ -362,12 +1303,17 @@ impl PatternScript {
         let part = Arc::new(RwLock::new(pattern.add_part()));
 
         {
+            let file = script.file_path.as_deref().and_then(|path| path.to_str());
             let engine = PatternScript::create_engine(pattern.clone(), part.clone());
-            let mut ast = engine.compile(&script.contents)?;
-            if let Some(path) = &script.file_path {
-                ast.set_source(path.to_str().unwrap());
+            let mut ast = engine
+                .compile(&script.contents)
+                .map_err(|err| Diagnostic::from_parse_error(&err, file))?;
+            if let Some(file) = file {
+                ast.set_source(file);
             }
-            engine.run_ast(&ast)?
+            engine
+                .run_ast(&ast)
+                .map_err(|err| Diagnostic::from_eval_error(&err, file))?
         }
 
         drop(part);
@@ -378,19 +1324,67 @@ impl PatternScript {
     pub fn eval_script_with_exports(
         script: &Script,
         exports: &HashMap<ImmutableString, Dynamic>,
+    ) -> Result<Pattern, Box<dyn Error + Send + Sync>> {
+        PatternScript::eval_script_with_progress(script, exports, |_| true)
+    }
+
+    /// Evaluate a script in full with the given export parameters set, reporting progress as it goes.
+    /// The callback is given the number of operations run so far, and returning `false` cancels evaluation.
+    pub fn eval_script_with_progress(
+        script: &Script,
+        exports: &HashMap<ImmutableString, Dynamic>,
+        progress: impl Fn(u64) -> bool + Send + Sync + 'static,
     ) -> Result<Pattern, Box<dyn Error + Send + Sync>> {
         let pattern = Pattern::new();
         let part = Arc::new(RwLock::new(pattern.add_part()));
 
         {
-            let engine = PatternScript::create_engine(pattern.clone(), part.clone());
+            let mut engine = PatternScript::create_engine(pattern.clone(), part.clone());
+            engine.on_progress(move |ops| (!progress(ops)).then_some(Dynamic::UNIT));
             let ast = PatternScript::preprocess_script(script, exports)?;
-            engine.run_ast(&ast)?
+            engine
+                .run_ast(&ast)
+                .map_err(|err| -> Box<dyn Error + Send + Sync> {
+                    match *err {
+                        EvalAltResult::ErrorTerminated(..) => "Evaluation was cancelled.".into(),
+                        _ => Diagnostic::from_eval_error(
+                            &err,
+                            script.file_path.as_deref().and_then(|path| path.to_str()),
+                        )
+                        .into(),
+                    }
+                })?
         }
 
         drop(part);
         Ok(pattern.into_inner())
     }
+
+    /// Evaluate a script once for each variant in a sweep, e.g. to grade a pattern across sizes,
+    /// with the `base` parameter values set for every variant unless the variant sets them itself.
+    /// A variant that fails doesn't stop the others from being evaluated.
+    pub fn sweep(
+        script: &Script,
+        sweep: &Sweep,
+        base: &ParameterSet,
+        yarn: &YarnOptions,
+    ) -> Vec<VariantResult> {
+        sweep
+            .variants()
+            .into_iter()
+            .map(|(name, parameters)| {
+                let mut exports = base.clone();
+                exports.extend(parameters.clone());
+                let stats = PatternScript::eval_script_with_exports(script, &exports)
+                    .map(|pattern| VariantStats::new(&pattern, yarn));
+                VariantResult {
+                    name,
+                    parameters,
+                    stats,
+                }
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -416,6 +1410,429 @@ rep 15 {
         assert_eq!(pattern, crate::pattern::test_pattern_flat(15).unwrap());
     }
 
+    #[test]
+    fn test_cancel() {
+        let script: Script = "rep 1000000 { chain(); }".into();
+        let err =
+            PatternScript::eval_script_with_progress(&script, &HashMap::new(), |ops| ops < 10_000)
+                .unwrap_err();
+        assert_eq!(err.to_string(), "Evaluation was cancelled.");
+    }
+
+    #[test]
+    fn test_stuffing() {
+        let pattern = PatternScript::eval_script(
+            &"magic_ring(); stuffing(0.5); new_part(); chain(); new_part(); stuffing(2);".into(),
+        )
+        .unwrap();
+        assert_eq!(pattern.stuffing(0), Some(0.5));
+        assert_eq!(pattern.stuffing(1), None);
+        assert_eq!(pattern.stuffing(2), Some(2.0));
+    }
+
+    #[test]
+    fn test_gauge() {
+        let pattern = PatternScript::eval_script(
+            &r#"stitch_height("sc", 4); gauge(20, 12.5); chain();"#.into(),
+        )
+        .unwrap();
+        let gauge = pattern.gauge();
+        assert_eq!((gauge.stitches, gauge.rows), (20.0, 12.5));
+        assert_eq!(gauge.heights.get("sc"), Some(&4.0));
+
+        assert!(PatternScript::eval_script(&"gauge(0, 10);".into()).is_err());
+        assert!(PatternScript::eval_script(&r#"stitch_height("puff", 5);"#.into()).is_err());
+    }
+
+    #[test]
+    fn test_expect_count() {
+        let script = |count| format!("rep 5 chain(); turn(); rep 5 sc(); expect_count({count});");
+        assert!(PatternScript::eval_script(&script(5).into()).is_ok());
+        let err = PatternScript::eval_script(&script(6).into()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Row 1 has 5 stitches, expected 6."));
+
+        // the magic ring itself isn't a stitch
+        let script = "magic_ring(); into(mark()); rep 6 sc_(); expect_count(6);\n\
+                      new_row(); rep 6 { sc_(); sc(); }; expect_count(12);";
+        PatternScript::eval_script(&script.into()).unwrap();
+    }
+
+    #[test]
+    fn test_source_locations() {
+        let script = Script {
+            contents: "export let n = 3;\nrep n chain();\nturn();\nrep n sc();\n".into(),
+            file_path: Some("test.ph".into()),
+        };
+        let pattern = PatternScript::eval_script_with_exports(&script, &HashMap::new()).unwrap();
+        let at_line = |line| SourceLocation {
+            file: Some("test.ph".into()),
+            line,
+        };
+
+        let chains = pattern.nodes_at(&at_line(2));
+        assert_eq!(chains.len(), 3);
+        let stitches = pattern.nodes_at(&at_line(4));
+        assert_eq!(stitches.len(), 3);
+        let origin = pattern.origin(stitches[0]).unwrap();
+        assert_eq!((origin.part, origin.row), (0, 1));
+        assert_eq!(origin.location, Some(at_line(4)));
+
+        // the starting chain isn't made by any line
+        let start = pattern.parts()[0][0][0];
+        assert_eq!(pattern.origin(start).unwrap().location, None);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let script = Script {
+            contents: "chain();\nturn();\n  skip(); skip();\n".into(),
+            file_path: Some("main.ph".into()),
+        };
+        let err = PatternScript::eval_script_with_exports(&script, &HashMap::new()).unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.message, PatternError::NoInsert.to_string());
+        assert_eq!(diagnostic.file.as_deref(), Some("main.ph"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(3), Some(11)));
+        assert!(diagnostic.call_stack.is_empty());
+
+        let script = Script {
+            contents: "export let n = 3;\nexport let m = n + 1;\nchain(\n".into(),
+            file_path: None,
+        };
+        let diagnostics = PatternScript::check_script(&script);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("Syntax error"));
+
+        let script: Script = "export let n = 3;\nexport let m = k + 1;\n".into();
+        let diagnostics = PatternScript::check_script(&script);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(2));
+    }
+
+    #[test]
+    fn test_diagnostics_in_module() {
+        let script = Script {
+            contents: "import \"common\" as c;\nchain();\nc::inc();\n".into(),
+            file_path: Some("examples/main.ph".into()),
+        };
+        let err = PatternScript::eval_script(&script).unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, PatternError::NoInsert.to_string());
+        assert_eq!(diagnostic.file.as_deref(), Some("examples/common.ph"));
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.call_stack.len(), 1);
+        assert_eq!(diagnostic.call_stack[0].name, "inc");
+        assert_eq!(
+            diagnostic.call_stack[0].file.as_deref(),
+            Some("examples/main.ph")
+        );
+        assert_eq!(diagnostic.call_stack[0].line, Some(3));
+        assert!(diagnostic.is_at(Some("examples/main.ph"), 3));
+    }
+
+    #[test]
+    fn test_unused_parameters() {
+        let script: Script = r"
+            export let width = param(4, #{ min: 1 });
+            export let rows = width * 2;
+            export let colour = [1, 0, 0];
+            rep width chain();
+            rep rows { turn(); rep width sc(); }
+        "
+        .into();
+        let diagnostics = PatternScript::check_script(&script);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].line, Some(4));
+        assert!(diagnostics[0].message.contains("\"colour\""));
+        assert!(PatternScript::eval_script(&script).is_ok());
+    }
+
+    #[test]
+    fn test_parameters() {
+        let script: Script = r##"
+            export let rows = param(10, #{ min: 1, max: 50, description: "Number of rows" });
+            export let size = param(2, #{ min: 0.5 });
+            export let edging = false;
+            export let stitch = param("sc", #{ choices: ["sc", "dc"] });
+            export let yarn = param([1, 0.5, 0], #{ color: true });
+            export let name = "hat";
+            for i in 0..rows { chain(); }
+        "##
+        .into();
+        let parameters = PatternScript::get_script_parameters(&script).unwrap();
+        let kinds = parameters
+            .iter()
+            .map(|p| p.kind.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ParameterKind::Int {
+                    min: Some(1),
+                    max: Some(50),
+                    step: None
+                },
+                ParameterKind::Float {
+                    min: Some(0.5),
+                    max: None,
+                    step: None
+                },
+                ParameterKind::Bool,
+                ParameterKind::Choice(vec!["sc".into(), "dc".into()]),
+                ParameterKind::Color,
+                ParameterKind::Other,
+            ]
+        );
+        assert_eq!(parameters[0].description.as_deref(), Some("Number of rows"));
+        // an integer default for a float parameter is converted to a float
+        assert_eq!(parameters[1].default.as_float(), Ok(2.0));
+
+        let pattern = PatternScript::eval_script(&script).unwrap();
+        assert_eq!(pattern.graph().node_count(), 11);
+
+        // values outside a parameter's limits are rejected before the script runs
+        for (name, value) in [
+            ("rows", Dynamic::from(51_i64)),
+            ("size", Dynamic::from(0.1)),
+            ("stitch", Dynamic::from("tr")),
+            ("yarn", Dynamic::from(vec![Dynamic::from(2.0)])),
+        ] {
+            let exports = HashMap::from([(name.into(), value)]);
+            let err = PatternScript::eval_script_with_exports(&script, &exports).unwrap_err();
+            let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+            assert!(diagnostic.message.contains(name), "{diagnostic}");
+        }
+        let exports = HashMap::from([("rows".into(), Dynamic::from(5_i64))]);
+        let pattern = PatternScript::eval_script_with_exports(&script, &exports).unwrap();
+        assert_eq!(pattern.graph().node_count(), 6);
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        for (contents, line) in [
+            (
+                "chain();\nexport let rows = param(60, #{ min: 1, max: 50 });\n",
+                2,
+            ),
+            ("export let rows = param(6, #{ maximum: 50 });\n", 1),
+            (
+                "export let s = param(\"tr\", #{ choices: [\"sc\", \"dc\"] });\n",
+                1,
+            ),
+        ] {
+            let script: Script = contents.into();
+            let diagnostics = PatternScript::check_script(&script);
+            assert_eq!(diagnostics.len(), 1, "{contents}");
+            assert_eq!(diagnostics[0].line, Some(line));
+            assert!(PatternScript::get_script_parameters(&script).is_err());
+        }
+    }
+
+    #[test]
+    fn test_derived_parameters() {
+        // derived parameters can use parameters declared after them, and functions in the script
+        let script: Script = r"
+            export let rows = double(width) + 1;
+            export let width = param(4, #{ min: 1 });
+            export let total = rows * width;
+            fn double(n) { n * 2 }
+            rep width chain();
+            rep rows { turn(); rep width sc(); }
+        "
+        .into();
+        let parameters = PatternScript::get_script_parameters(&script).unwrap();
+        let derived = parameters.iter().map(|p| p.derived).collect::<Vec<_>>();
+        assert_eq!(derived, vec![true, false, true]);
+        assert_eq!(parameters[0].default.as_int(), Ok(9));
+        assert_eq!(parameters[2].default.as_int(), Ok(36));
+
+        let exports = HashMap::from([("width".into(), Dynamic::from(2_i64))]);
+        let values = PatternScript::get_parameter_values(&script, &exports).unwrap();
+        let values = values
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.as_int().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                ("rows".into(), 5),
+                ("width".into(), 2),
+                ("total".into(), 10)
+            ]
+        );
+        let pattern = PatternScript::eval_script_with_exports(&script, &exports).unwrap();
+        assert_eq!(pattern.row_counts()[0].len(), 6);
+
+        // derived parameters can't be set themselves
+        let exports = HashMap::from([("total".into(), Dynamic::from(3_i64))]);
+        let err = PatternScript::eval_script_with_exports(&script, &exports).unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.line, Some(4));
+
+        let script: Script = "export let a = b + 1;\nexport let b = a * 2;\n".into();
+        let diagnostics = PatternScript::check_script(&script);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(1));
+        assert!(diagnostics[0].message.contains("depend on each other"));
+    }
+
+    #[test]
+    fn test_derived_parameters_from_constants() {
+        // derived parameters can use constants and variables declared before the parameters, e.g. a table of sizes
+        let script: Script = r"
+            const SIZES = [4, 6, 8];
+            let extra = 1;
+            let unused = new_part();
+            export let size = param(1, #{ min: 0, max: 2 });
+            export let rows = SIZES[size] + extra;
+            rep 4 chain();
+            rep rows { turn(); rep 4 sc(); }
+        "
+        .into();
+        assert!(PatternScript::check_script(&script).is_empty());
+        let exports = HashMap::from([("size".into(), Dynamic::from(2_i64))]);
+        let values = PatternScript::get_parameter_values(&script, &exports).unwrap();
+        assert_eq!(values[1].1.as_int(), Ok(9));
+
+        // other parameters declared in between don't get mixed up with the ones used
+        let script: Script = r"
+            export let width = param(4, #{ min: 1 });
+            export let colour = [1, 0, 0];
+            export let rows = width * 2;
+        "
+        .into();
+        let parameters = PatternScript::get_script_parameters(&script).unwrap();
+        assert_eq!(parameters[2].default.as_int(), Ok(8));
+
+        // but not anything declared after them
+        let script: Script = "export let rows = later * 2;\nlet later = 3;\n".into();
+        let diagnostics = PatternScript::check_script(&script);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(1));
+        assert!(diagnostics[0].message.contains("declared before them"));
+    }
+
+    #[test]
+    fn test_sweep() {
+        let script: Script = r#"
+            export let width = param(4, #{ min: 1 });
+            export let height = 2;
+            rep width chain();
+            rep height { turn(); rep width sc(); }
+        "#
+        .into();
+        let sweep = Sweep::Grid(vec![
+            (
+                "width".into(),
+                vec![2_i64.into(), 3_i64.into(), 0_i64.into()],
+            ),
+            ("height".into(), vec![1_i64.into(), 5_i64.into()]),
+        ]);
+        let results = PatternScript::sweep(&script, &sweep, &HashMap::new(), &Default::default());
+        let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "width=2, height=1",
+                "width=2, height=5",
+                "width=3, height=1",
+                "width=3, height=5",
+                "width=0, height=1",
+                "width=0, height=5",
+            ]
+        );
+        let stats = results[3].stats.as_ref().unwrap();
+        // the chain row includes the starting chain
+        assert_eq!((stats.parts, stats.rows, stats.stitches), (1, 6, 4 + 5 * 3));
+        assert!(stats.yarn.total_length() > 0.0);
+        // a width of 0 is below the parameter's minimum
+        assert!(results[4].stats.is_err());
+
+        let mut presets = Presets::default();
+        presets.set(
+            "Small",
+            ParameterSet::from([("width".into(), 2_i64.into())]),
+        );
+        let base = ParameterSet::from([("height".into(), 3_i64.into())]);
+        let results = PatternScript::sweep(&script, &(&presets).into(), &base, &Default::default());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].stats.as_ref().unwrap().stitches, 3 + 3 * 2);
+    }
+
+    #[test]
+    fn test_yarn_usage() {
+        let pattern = PatternScript::eval_script(
+            &r##"
+rep 4 chain();
+change_color([0.0, 0.0, 1.0]);
+turn();
+rep 3 sc();
+let usage = yarn_usage();
+if usage["#0000ff"] <= 0.0 { throw "no yarn used"; }
+        "##
+            .into(),
+        );
+        assert!(pattern.is_ok(), "{:?}", pattern.err());
+    }
+
+    #[test]
+    fn test_clusters() {
+        let pattern = PatternScript::eval_script(
+            &r#"
+rep 9 chain();
+turn();
+rep 3 dec_n(3, "sc");
+turn();
+rep 3 inc_n(3, "dc");
+        "#
+            .into(),
+        )
+        .expect("Error in evaluating script");
+
+        let stitches = pattern
+            .graph()
+            .node_weights()
+            .filter(|n| n.is_worked())
+            .count();
+        assert_eq!(stitches, 3 + 9);
+
+        PatternScript::eval_script(&r#"rep 3 chain(); turn(); dec_n(2, "bobble");"#.into())
+            .expect_err("Unknown stitch types should be an error");
+    }
+
+    #[test]
+    fn test_insert_modes() {
+        let pattern = PatternScript::eval_script(
+            &r#"
+magic_ring();
+new_row();
+rep 6 sc_();
+new_row();
+blo(|| rep 6 sc());
+new_row();
+fp(|| rep 3 dc());
+rep 3 dc();
+        "#
+            .into(),
+        )
+        .expect("Error in evaluating script");
+
+        let count = |mode| {
+            pattern
+                .graph()
+                .edge_weights()
+                .filter(|e| **e == crate::pattern::EdgeType::Insert(mode))
+                .count()
+        };
+        assert_eq!(count(InsertMode::BackLoop), 6);
+        assert_eq!(count(InsertMode::FrontPost), 3);
+        assert_eq!(count(InsertMode::BothLoops), 6 + 3);
+    }
+
     #[test]
     fn test_all_examples() {
         examples::EXAMPLES.iter().for_each(|&(name, path)| {
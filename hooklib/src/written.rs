@@ -0,0 +1,986 @@
+use std::error::Error;
+use std::fmt::{Display, Write};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use petgraph::{
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+
+use crate::pattern::{EdgeType, InsertMode, Node, Part, Pattern, PatternError};
+
+/// The names of each worked stitch type in both terminologies, as `(stitch type, US name, UK name)`.
+const STITCH_NAMES: [(&str, &str, &str); 6] = [
+    ("sl", "sl st", "ss"),
+    ("sc", "sc", "dc"),
+    ("hdc", "hdc", "htr"),
+    ("dc", "dc", "tr"),
+    ("tr", "tr", "dtr"),
+    ("dtr", "dtr", "trtr"),
+];
+
+/// Which set of stitch names to use in a written pattern.
+/// The same name can mean a different stitch in each, e.g. a US "sc" is a UK "dc".
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Terminology {
+    #[default]
+    Us,
+    Uk,
+}
+
+impl Terminology {
+    /// Returns the name of a stitch type in this terminology.
+    pub fn stitch_name(&self, ty: &'static str) -> &'static str {
+        STITCH_NAMES
+            .iter()
+            .find(|(t, _, _)| *t == ty)
+            .map(|(_, us, uk)| match self {
+                Terminology::Us => *us,
+                Terminology::Uk => *uk,
+            })
+            .unwrap_or(ty)
+    }
+}
+
+/// A single instruction in a written row, before it's turned into text.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Instruction {
+    /// A number of chains in a row.
+    Chain(usize),
+    /// A turning chain of the given length, starting a new row.
+    Turn(usize),
+    /// A single stitch worked into the next stitch.
+    Stitch(&'static str, InsertMode),
+    /// A number of stitches worked into a magic ring or chain space.
+    InRing(&'static str, usize),
+    /// A number of stitches worked into the same stitch.
+    Increase(&'static str, InsertMode, usize),
+    /// A stitch worked over a number of stitches.
+    Decrease(&'static str, InsertMode, usize),
+    /// A slip stitch joining the round.
+    SlipJoin,
+}
+
+impl Instruction {
+    /// Write out the instruction using the given terminology.
+    fn to_text(self, terminology: Terminology) -> String {
+        let with_mode = |name: &str, mode: InsertMode| match mode {
+            InsertMode::BothLoops => name.to_string(),
+            InsertMode::FrontLoop => format!("FLO {name}"),
+            InsertMode::BackLoop => format!("BLO {name}"),
+            InsertMode::FrontPost => format!("FP{name}"),
+            InsertMode::BackPost => format!("BP{name}"),
+        };
+
+        match self {
+            Instruction::Chain(n) => format!("ch {n}"),
+            Instruction::Turn(n) => format!("ch {n}, turn"),
+            Instruction::Stitch(ty, mode) => with_mode(terminology.stitch_name(ty), mode),
+            Instruction::InRing(ty, n) => format!("{n} {} in ring", terminology.stitch_name(ty)),
+            Instruction::Increase("sc", InsertMode::BothLoops, 2) => "inc".to_string(),
+            Instruction::Increase(ty, mode, n) => {
+                format!(
+                    "{n} {} in next st",
+                    with_mode(terminology.stitch_name(ty), mode)
+                )
+            }
+            Instruction::Decrease("dec", InsertMode::BothLoops, _) => "dec".to_string(),
+            // `inc` and `dec` only mean single crochet worked the usual way, anything else is written in full
+            Instruction::Decrease("dec", mode, n) => {
+                with_mode(&format!("{}{n}tog", terminology.stitch_name("sc")), mode)
+            }
+            Instruction::Decrease(ty, mode, n) => {
+                with_mode(&format!("{}{n}tog", terminology.stitch_name(ty)), mode)
+            }
+            Instruction::SlipJoin => format!("{} to join", terminology.stitch_name("sl")),
+        }
+    }
+}
+
+/// Compress repeated runs of instructions, e.g. `sc, inc, sc, inc` into `(sc, inc) x2`.
+fn compress(instructions: &[String]) -> Vec<String> {
+    let mut compressed = vec![];
+    let mut i = 0;
+
+    while i < instructions.len() {
+        // find the repeating unit that covers the most instructions from here
+        let (len, reps) = (1..=(instructions.len() - i) / 2)
+            .map(|len| {
+                let unit = &instructions[i..i + len];
+                let reps = instructions[i..]
+                    .chunks_exact(len)
+                    .take_while(|chunk| *chunk == unit)
+                    .count();
+                (len, reps)
+            })
+            .filter(|(_, reps)| *reps >= 2)
+            .fold((1, 1), |best, (len, reps)| {
+                if len * reps > best.0 * best.1 {
+                    (len, reps)
+                } else {
+                    best
+                }
+            });
+
+        if reps == 1 {
+            compressed.push(instructions[i].clone());
+        } else if len == 1 {
+            compressed.push(format!("{} x{reps}", instructions[i]));
+        } else {
+            let unit = compress(&instructions[i..i + len]).join(", ");
+            compressed.push(format!("({unit}) x{reps}"));
+        }
+
+        i += len * reps;
+    }
+
+    compressed
+}
+
+/// Returns the number of chains that turn the work before a row of the given stitch type.
+/// The turning chain stands up to the height of the first stitch in the row.
+fn turning_chain(ty: &str) -> usize {
+    match ty {
+        "hdc" => 2,
+        "dc" => 3,
+        "tr" => 4,
+        "dtr" => 5,
+        _ => 1,
+    }
+}
+
+/// Returns the stitch that the given stitch is worked into, if it's worked into exactly one.
+fn single_insert(
+    graph: &DiGraph<Node, EdgeType>,
+    stitch: NodeIndex,
+) -> Option<(NodeIndex, InsertMode)> {
+    graph
+        .edges_directed(stitch, Direction::Outgoing)
+        .filter_map(|e| match e.weight() {
+            EdgeType::Insert(mode) => Some((e.target(), *mode)),
+            _ => None,
+        })
+        .exactly_one()
+        .ok()
+}
+
+impl Pattern {
+    /// Convert a single row of stitches into a list of instructions.
+    fn row_instructions(&self, row: &[NodeIndex]) -> Vec<Instruction> {
+        let graph = self.graph();
+        let mut instructions: Vec<Instruction> = vec![];
+        let mut i = 0;
+
+        while i < row.len() {
+            let stitch = row[i];
+            let node = graph[stitch];
+            let mut worked = 1;
+
+            let instruction = if node.is_turn() {
+                let first = row[i + 1..]
+                    .iter()
+                    .map(|s| graph[*s])
+                    .find(|s| s.is_worked())
+                    .map(|s| s.stitch_type())
+                    .unwrap_or("sc");
+                Instruction::Turn(turning_chain(first))
+            } else if !node.is_worked() && node.stitch_type() != "dec" {
+                Instruction::Chain(1)
+            } else if let Some((insert, mode)) = single_insert(&graph, stitch) {
+                // stitches of the same type worked into the same place are worked as one instruction
+                worked += row[i + 1..]
+                    .iter()
+                    .take_while(|s| {
+                        graph[**s].stitch_type() == node.stitch_type()
+                            && single_insert(&graph, **s) == Some((insert, mode))
+                    })
+                    .count();
+
+                match graph[insert] {
+                    Node::MagicRing | Node::ChainSpace => {
+                        Instruction::InRing(node.stitch_type(), worked)
+                    }
+                    _ if worked == 1 => Instruction::Stitch(node.stitch_type(), mode),
+                    _ => Instruction::Increase(node.stitch_type(), mode, worked),
+                }
+            } else {
+                let inserts = graph
+                    .edges_directed(stitch, Direction::Outgoing)
+                    .filter_map(|e| match e.weight() {
+                        EdgeType::Insert(mode) => Some(*mode),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let mode = inserts.first().copied().unwrap_or_default();
+                Instruction::Decrease(node.stitch_type(), mode, inserts.len())
+            };
+
+            match (instructions.last_mut(), instruction) {
+                (Some(Instruction::Chain(n)), Instruction::Chain(1)) => *n += 1,
+                _ => instructions.push(instruction),
+            }
+
+            let joins = row[i..i + worked].iter().any(|s| {
+                graph
+                    .edges_directed(*s, Direction::Outgoing)
+                    .any(|e| *e.weight() == EdgeType::Slip)
+            });
+            if joins {
+                instructions.push(Instruction::SlipJoin);
+            }
+
+            i += worked;
+        }
+
+        instructions
+    }
+
+    /// Convert the pattern into written, round-by-round instructions, e.g. `Rnd 3: (sc, inc) x6 (18)`.
+    /// Each part is written from the rows recorded in [`Pattern::parts`].
+    pub fn to_written(&self, terminology: Terminology) -> String {
+        let parts = self.parts();
+        // skip any parts that never had anything worked into them
+        let parts = parts
+            .iter()
+            .filter(|rows| rows.len() > 1 || rows.first().is_some_and(|row| row.len() > 1))
+            .collect::<Vec<_>>();
+
+        let mut written = String::new();
+        for (part_idx, rows) in parts.iter().enumerate() {
+            if parts.len() > 1 {
+                if part_idx > 0 {
+                    writeln!(written).unwrap();
+                }
+                writeln!(written, "Part {}", part_idx + 1).unwrap();
+            }
+
+            let Some((foundation, rows)) = rows.split_first() else {
+                continue;
+            };
+            let mut rows = rows.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+            if foundation
+                .first()
+                .is_some_and(|s| self.graph()[*s] == Node::MagicRing)
+            {
+                writeln!(written, "Magic ring").unwrap();
+                // stitches worked into the ring in the same row as it make up the first round
+                if foundation.len() > 1 {
+                    rows.insert(0, &foundation[1..]);
+                }
+            } else {
+                let instructions = self
+                    .row_instructions(foundation)
+                    .into_iter()
+                    .map(|instruction| match instruction {
+                        Instruction::Chain(n) => format!("Ch {n}"),
+                        other => other.to_text(terminology),
+                    })
+                    .join(", ");
+                writeln!(written, "{instructions}").unwrap();
+            }
+
+            for (row_idx, row) in rows.iter().enumerate() {
+                let graph = self.graph();
+                let is_turned = row.iter().any(|s| graph[*s].is_turn());
+                let count = row.iter().filter(|s| !graph[**s].is_turn()).count();
+                drop(graph);
+
+                let instructions = self
+                    .row_instructions(row)
+                    .into_iter()
+                    .map(|instruction| instruction.to_text(terminology))
+                    .collect::<Vec<_>>();
+
+                writeln!(
+                    written,
+                    "{} {}: {} ({count})",
+                    if is_turned { "Row" } else { "Rnd" },
+                    row_idx + 1,
+                    compress(&instructions).join(", ")
+                )
+                .unwrap();
+            }
+        }
+
+        written
+    }
+}
+
+/// The different ways reading a written pattern can fail.
+#[derive(Debug)]
+pub enum WrittenErrorKind {
+    /// The instruction couldn't be understood.
+    UnknownInstruction(String),
+    /// The stitch count at the end of a row doesn't match the stitches worked.
+    StitchCount { expected: usize, actual: usize },
+    /// The instruction was understood but couldn't be worked.
+    Pattern(PatternError),
+}
+
+/// Error type for reading a written pattern, with the line number (starting at 1) it occurred on.
+#[derive(Debug)]
+pub struct WrittenError {
+    pub line: usize,
+    pub kind: WrittenErrorKind,
+}
+
+impl Display for WrittenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            WrittenErrorKind::UnknownInstruction(instruction) => {
+                write!(
+                    f,
+                    "Line {}: unknown instruction \"{instruction}\".",
+                    self.line
+                )
+            }
+            WrittenErrorKind::StitchCount { expected, actual } => write!(
+                f,
+                "Line {}: expected {expected} stitches but {actual} were worked.",
+                self.line
+            ),
+            WrittenErrorKind::Pattern(err) => write!(f, "Line {}: {err}", self.line),
+        }
+    }
+}
+
+impl Error for WrittenError {}
+
+/// Builds a [`Pattern`] from a written pattern, line by line.
+struct Reader {
+    pattern: Arc<Pattern>,
+    part: Part,
+    terminology: Terminology,
+    /// The magic ring or joined foundation chain space that stitches can be worked "in ring".
+    ring: Option<NodeIndex>,
+    /// Whether anything has been worked in the current part yet.
+    started: bool,
+}
+
+impl Reader {
+    /// Look up a stitch type from its name in the reader's terminology.
+    fn stitch_type(&self, name: &str) -> Option<&'static str> {
+        STITCH_NAMES
+            .iter()
+            .find(|(_, us, uk)| match self.terminology {
+                Terminology::Us => *us == name,
+                Terminology::Uk => *uk == name,
+            })
+            .map(|(ty, _, _)| *ty)
+    }
+
+    /// Split a stitch name from its insertion mode prefix, e.g. `blo sc` or `fpdc`.
+    fn stitch_with_mode(&self, name: &str) -> Option<(&'static str, InsertMode)> {
+        let (mode, name) = split_mode(name);
+        Some((self.stitch_type(name)?, mode))
+    }
+
+    /// Start a new part, unless nothing has been worked in the current one yet.
+    fn new_part(&mut self) {
+        if self.started {
+            self.part = self.pattern.add_part();
+            self.ring = None;
+            self.started = false;
+        }
+    }
+
+    /// Read the foundation of a part, e.g. `Ch 16` or `Ch 3, sl st to join`.
+    fn foundation(&mut self, line: &str) -> Result<bool, WrittenErrorKind> {
+        if ["magic ring", "mr"].contains(&line) {
+            self.part.magic_ring();
+            self.ring = Some(self.part.start());
+            self.started = true;
+            return Ok(true);
+        }
+
+        let instructions = split_top_level(line);
+        let Some(chains) = instructions
+            .first()
+            .and_then(|ch| ch.strip_prefix("ch "))
+            .and_then(|n| n.trim().parse::<usize>().ok())
+        else {
+            return Ok(false);
+        };
+        let joined = match &instructions[1..] {
+            [] => false,
+            [join] if self.is_join(join) => true,
+            _ => return Err(WrittenErrorKind::UnknownInstruction(line.to_string())),
+        };
+
+        // the part already starts with a single chain
+        let start = self.part.start();
+        if joined {
+            self.part.start_ch_sp().map_err(WrittenErrorKind::Pattern)?;
+        }
+        for _ in 1..chains {
+            self.part.chain().map_err(WrittenErrorKind::Pattern)?;
+        }
+        if joined {
+            self.part.slip_stitch(start);
+            self.ring = Some(self.part.end_ch_sp().map_err(WrittenErrorKind::Pattern)?);
+        }
+        self.started = true;
+
+        Ok(true)
+    }
+
+    /// Returns whether the instruction is a slip stitch joining the round.
+    fn is_join(&self, instruction: &str) -> bool {
+        instruction == "join"
+            || instruction == format!("{} to join", self.terminology.stitch_name("sl"))
+    }
+
+    /// Work a single instruction, which may be a repeated group of instructions.
+    fn instruction(&mut self, instruction: &str) -> Result<(), WrittenErrorKind> {
+        let unknown = || WrittenErrorKind::UnknownInstruction(instruction.to_string());
+        let instruction = instruction.trim();
+
+        // repeats, e.g. `(sc, inc) x6` or `sc x6`
+        if let Some((unit, reps)) = instruction
+            .rsplit_once(" x")
+            .or_else(|| instruction.rsplit_once(" ×"))
+            .and_then(|(unit, reps)| Some((unit, reps.trim().parse::<usize>().ok()?)))
+        {
+            let unit = unit.trim();
+            let group = unit
+                .strip_prefix('(')
+                .and_then(|u| u.strip_suffix(')'))
+                .or_else(|| unit.strip_prefix('[').and_then(|u| u.strip_suffix(']')));
+            for _ in 0..reps {
+                match group {
+                    Some(group) => split_top_level(group)
+                        .into_iter()
+                        .try_for_each(|i| self.instruction(&i))?,
+                    None => self.instruction(unit)?,
+                }
+            }
+            return Ok(());
+        }
+
+        // increases and decreases, possibly worked in one loop or around the post, e.g. `blo inc` or `fpdec`
+        match split_mode(instruction) {
+            (mode, "inc") => return self.with_mode(mode, |part| part.inc_n(2, "sc").map(drop)),
+            (mode, "dec") => return self.with_mode(mode, |part| part.dec().map(drop)),
+            _ => {}
+        }
+
+        let pattern_err = WrittenErrorKind::Pattern;
+        let words = instruction.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["turn"] => self.part.turn().map_err(pattern_err)?,
+            ["ch"] => drop(self.part.chain().map_err(pattern_err)?),
+            ["ch", n] => {
+                let n = n.parse::<usize>().map_err(|_| unknown())?;
+                for _ in 0..n {
+                    self.part.chain().map_err(pattern_err)?;
+                }
+            }
+            ["sk" | "skip"] => self.part.skip().map_err(pattern_err)?,
+            ["sk" | "skip", n] => {
+                let n = n.parse::<usize>().map_err(|_| unknown())?;
+                for _ in 0..n {
+                    self.part.skip().map_err(pattern_err)?;
+                }
+            }
+            _ if self.is_join(instruction) => {
+                let first = *self
+                    .part
+                    .current_row()
+                    .map_err(pattern_err)?
+                    .first()
+                    .ok_or(unknown())?;
+                self.part.slip_stitch(first);
+            }
+            _ => return self.stitches(instruction).ok_or_else(unknown)?,
+        }
+
+        Ok(())
+    }
+
+    /// Work an instruction made of stitches, e.g. `sc`, `6 sc in ring`, `sc3tog`, `hdc across` or `blo sc`.
+    fn stitches(&mut self, instruction: &str) -> Option<Result<(), WrittenErrorKind>> {
+        // stitches worked together, e.g. `sc3tog`
+        if let Some(together) = instruction.strip_suffix("tog") {
+            let name = together.trim_end_matches(|c: char| c.is_ascii_digit());
+            let (ty, mode) = self.stitch_with_mode(name)?;
+            let n = together[name.len()..].parse::<usize>().ok()?;
+            return Some(self.with_mode(mode, |part| part.dec_n(n, ty).map(drop)));
+        }
+
+        let (count, rest) = match instruction.split_once(' ') {
+            Some((n, rest)) if n.parse::<usize>().is_ok() => (n.parse::<usize>().ok()?, rest),
+            _ => (1, instruction),
+        };
+
+        if let Some(name) = rest
+            .strip_suffix(" in ring")
+            .or_else(|| rest.strip_suffix(" in magic ring"))
+        {
+            let (ty, mode) = self.stitch_with_mode(name)?;
+            let ring = self.ring?;
+            self.part.set_insert(ring);
+            return Some(self.with_mode(mode, |part| {
+                (0..count).try_for_each(|_| part.stitch_noskip(ty).map(drop))
+            }));
+        }
+
+        if let Some(name) = rest
+            .strip_suffix(" in next st")
+            .or_else(|| rest.strip_suffix(" in same st"))
+        {
+            let (ty, mode) = self.stitch_with_mode(name)?;
+            return Some(self.with_mode(mode, |part| part.inc_n(count, ty).map(drop)));
+        }
+
+        if let Some(name) = rest
+            .strip_suffix(" across")
+            .or_else(|| rest.strip_suffix(" around"))
+            .or_else(|| rest.strip_suffix(" in each st"))
+        {
+            let (ty, mode) = self.stitch_with_mode(name)?;
+            return Some(self.with_mode(mode, |part| {
+                // stop at the end of the row, or if working into something that never ends (like a ring)
+                let remaining = part.previous_row()?.len();
+                for _ in 0..remaining {
+                    if part.insert().is_none() {
+                        break;
+                    }
+                    part.stitch(ty)?;
+                }
+                Ok(())
+            }));
+        }
+
+        // either `6 sc` or `sc 6`
+        let (count, name) = match rest.rsplit_once(' ') {
+            Some((name, n)) if count == 1 && n.parse::<usize>().is_ok() => {
+                (n.parse::<usize>().ok()?, name)
+            }
+            _ => (count, rest),
+        };
+        let (ty, mode) = self.stitch_with_mode(name)?;
+        Some(self.with_mode(mode, |part| {
+            (0..count).try_for_each(|_| part.stitch(ty).map(drop))
+        }))
+    }
+
+    /// Work some stitches with the given insertion mode, then go back to working under both loops.
+    fn with_mode(
+        &mut self,
+        mode: InsertMode,
+        f: impl FnOnce(&mut Part) -> Result<(), PatternError>,
+    ) -> Result<(), WrittenErrorKind> {
+        self.part.set_insert_mode(mode);
+        let result = f(&mut self.part);
+        self.part.set_insert_mode(InsertMode::BothLoops);
+        result.map_err(WrittenErrorKind::Pattern)
+    }
+
+    /// Read a row or round, e.g. `Rnd 3: (sc, inc) x6 (18)` or `Rows 2-5: ch 1, turn, sc across (15)`.
+    fn row(&mut self, header: &str, body: &str) -> Result<bool, WrittenErrorKind> {
+        let Some((kind, numbers)) = header.split_once(' ') else {
+            return Ok(false);
+        };
+        let turned = match kind {
+            "rnd" | "rnds" | "round" | "rounds" => false,
+            "row" | "rows" => true,
+            _ => return Ok(false),
+        };
+        let repeats = match numbers.split_once('-') {
+            Some((from, to)) => {
+                let from = from.trim().parse::<usize>();
+                let to = to.trim().parse::<usize>();
+                match (from, to) {
+                    (Ok(from), Ok(to)) if to >= from => to - from + 1,
+                    _ => return Ok(false),
+                }
+            }
+            None if numbers.trim().parse::<usize>().is_ok() => 1,
+            None => return Ok(false),
+        };
+
+        // the stitch count at the end of the row, e.g. `(18)` or `(18 sts)`
+        let (body, expected) = match body.trim().rsplit_once('(') {
+            Some((rest, count)) => {
+                let count = count.trim_end_matches(')').trim();
+                let count = count
+                    .strip_suffix("sts")
+                    .or_else(|| count.strip_suffix("st"))
+                    .unwrap_or(count)
+                    .trim();
+                match count.parse::<usize>() {
+                    Ok(count) => (rest.trim().trim_end_matches(',').trim(), Some(count)),
+                    Err(_) => (body.trim(), None),
+                }
+            }
+            None => (body.trim(), None),
+        };
+
+        let mut instructions = split_top_level(body);
+        if turned {
+            // the turning chain is already worked as part of `turn`
+            let skip = match &instructions[..] {
+                [ch, turn, ..] if ch.starts_with("ch ") && turn == "turn" => 2,
+                [turn, ..] if turn == "turn" => 1,
+                _ => 0,
+            };
+            instructions.drain(..skip);
+        }
+
+        for _ in 0..repeats {
+            if turned {
+                self.part.turn().map_err(WrittenErrorKind::Pattern)?;
+            } else {
+                self.part.new_row().map_err(WrittenErrorKind::Pattern)?;
+            }
+
+            instructions
+                .iter()
+                .try_for_each(|instruction| self.instruction(instruction))?;
+
+            if let Some(expected) = expected {
+                let graph = self.pattern.graph();
+                let actual = self
+                    .part
+                    .current_row()
+                    .map_err(WrittenErrorKind::Pattern)?
+                    .iter()
+                    .filter(|s| !graph[**s].is_turn())
+                    .count();
+                if actual != expected {
+                    return Err(WrittenErrorKind::StitchCount { expected, actual });
+                }
+            }
+        }
+        self.started = true;
+
+        Ok(true)
+    }
+
+    /// Read a single line of a written pattern.
+    fn line(&mut self, line: &str) -> Result<(), WrittenErrorKind> {
+        let line = line.trim().to_lowercase();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let read = if line.starts_with("part") || line.starts_with("piece") {
+            self.new_part();
+            true
+        } else if let Some((header, body)) = line.split_once(':') {
+            self.row(header.trim(), body)?
+        } else {
+            self.foundation(&line)?
+        };
+
+        if read {
+            Ok(())
+        } else {
+            Err(WrittenErrorKind::UnknownInstruction(line))
+        }
+    }
+}
+
+/// Split a list of instructions on commas, ignoring any inside brackets.
+fn split_top_level(instructions: &str) -> Vec<String> {
+    let mut split = vec![String::new()];
+    let mut depth = 0;
+    for c in instructions.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        split.last_mut().unwrap().push(c);
+    }
+
+    split
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl Pattern {
+    /// Build a pattern from written, round-by-round instructions, e.g. `Rnd 2: inc x6 (12)`.
+    /// Stitch counts at the end of each row are checked against the stitches actually worked.
+    /// `inc` and `dec` are taken to mean single crochet, as in most amigurumi patterns.
+    pub fn from_written(text: &str, terminology: Terminology) -> Result<Pattern, WrittenError> {
+        let pattern = Pattern::new();
+        let mut reader = Reader {
+            part: pattern.add_part(),
+            pattern: pattern.clone(),
+            terminology,
+            ring: None,
+            started: false,
+        };
+
+        text.lines().enumerate().try_for_each(|(i, line)| {
+            reader
+                .line(line)
+                .map_err(|kind| WrittenError { line: i + 1, kind })
+        })?;
+
+        drop(reader);
+        Ok(pattern.into_inner())
+    }
+}
+
+/// Split an insertion mode prefix from an instruction, e.g. `blo sc` or `fpdc`.
+fn split_mode(name: &str) -> (InsertMode, &str) {
+    let (mode, name) = if let Some(name) = name.strip_prefix("blo ") {
+        (InsertMode::BackLoop, name)
+    } else if let Some(name) = name.strip_prefix("flo ") {
+        (InsertMode::FrontLoop, name)
+    } else if let Some(name) = name.strip_prefix("fp") {
+        (InsertMode::FrontPost, name)
+    } else if let Some(name) = name.strip_prefix("bp") {
+        (InsertMode::BackPost, name)
+    } else {
+        (InsertMode::BothLoops, name)
+    };
+    (mode, name.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pattern_ball() -> Pattern {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.magic_ring();
+        part.new_row().unwrap();
+        for _ in 1..=6 {
+            part.sc_noskip().unwrap();
+        }
+        part.new_row().unwrap();
+        for _ in 1..=6 {
+            part.inc_n(2, "sc").unwrap();
+        }
+        part.new_row().unwrap();
+        for _ in 1..=6 {
+            part.sc().unwrap();
+            part.inc_n(2, "sc").unwrap();
+        }
+        part.new_row().unwrap();
+        for _ in 1..=6 {
+            part.sc().unwrap();
+            part.dec_n(2, "sc").unwrap();
+        }
+        drop(part);
+        pattern.into_inner()
+    }
+
+    #[test]
+    fn test_written_us() {
+        let written = test_pattern_ball().to_written(Terminology::Us);
+        assert_eq!(
+            written,
+            "Magic ring\n\
+             Rnd 1: 6 sc in ring (6)\n\
+             Rnd 2: inc x6 (12)\n\
+             Rnd 3: (sc, inc) x6 (18)\n\
+             Rnd 4: (sc, sc2tog) x6 (12)\n"
+        );
+    }
+
+    #[test]
+    fn test_written_uk() {
+        let written = test_pattern_ball().to_written(Terminology::Uk);
+        assert!(written.contains("Rnd 1: 6 dc in ring (6)"));
+        assert!(written.contains("Rnd 4: (dc, dc2tog) x6 (12)"));
+    }
+
+    #[test]
+    fn test_read_written() {
+        let pattern = Pattern::from_written(
+            "Magic ring\n\
+             Rnd 1: 6 sc in ring (6)\n\
+             Rnd 2: inc x6 (12)\n\
+             \n\
+             Rnd 3: (sc, inc) x6 (18)\n\
+             Rnds 4-5: sc around (18)\n\
+             Rnd 6: (sc, sc2tog) x6 (12)\n",
+            Terminology::Us,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pattern.to_written(Terminology::Us).lines().nth(5),
+            Some("Rnd 5: sc x18 (18)")
+        );
+        assert_eq!(
+            Pattern::from_written(&pattern.to_written(Terminology::Uk), Terminology::Uk).unwrap(),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_read_written_flat() {
+        let pattern = Pattern::from_written(
+            "Ch 16\nRow 1: ch 1, turn, dc across (15)\nRows 2-15: turn, dc x15 (15)",
+            Terminology::Us,
+        )
+        .unwrap();
+        assert_eq!(pattern, crate::pattern::test_pattern_flat(15).unwrap());
+    }
+
+    #[test]
+    fn test_read_written_errors() {
+        let err = Pattern::from_written(
+            "Magic ring\nRnd 1: 6 sc in ring (6)\nRnd 2: inc x5 (12)",
+            Terminology::Us,
+        )
+        .unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(matches!(
+            err.kind,
+            WrittenErrorKind::StitchCount {
+                expected: 12,
+                actual: 10
+            }
+        ));
+
+        let err = Pattern::from_written("Ch 5\nRow 1: ch 1, turn, bobble x4", Terminology::Us)
+            .unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(matches!(err.kind, WrittenErrorKind::UnknownInstruction(_)));
+    }
+
+    #[test]
+    fn test_written_flat() {
+        let written = crate::pattern::test_pattern_flat(5)
+            .unwrap()
+            .to_written(Terminology::Us);
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("Ch 6"));
+        assert_eq!(lines.next(), Some("Row 1: ch 3, turn, dc x5 (5)"));
+        assert_eq!(lines.count(), 4);
+    }
+
+    #[test]
+    fn test_written_dc_increases() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        for _ in 1..=7 {
+            part.chain().unwrap();
+        }
+        part.turn().unwrap();
+        for _ in 1..=6 {
+            part.dc().unwrap();
+        }
+        part.turn().unwrap();
+        for _ in 1..=3 {
+            part.dc().unwrap();
+            part.inc_n(2, "dc").unwrap();
+        }
+        drop(part);
+        let pattern = pattern.into_inner();
+
+        let written = pattern.to_written(Terminology::Us);
+        assert_eq!(
+            written.lines().nth(2),
+            Some("Row 2: ch 3, turn, (dc, 2 dc in next st) x3 (9)")
+        );
+        assert!(!written.contains("inc"));
+        assert_eq!(
+            Pattern::from_written(&written, Terminology::Us).unwrap(),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_written_from_script() {
+        // scripts work the first round into the magic ring in the same row as the ring
+        let script = crate::script::Script::from(include_str!("../examples/sphere.ph"));
+        let pattern = crate::script::PatternScript::eval_script(&script).unwrap();
+        let written = pattern.to_written(Terminology::Us);
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("Magic ring"));
+        assert_eq!(lines.next(), Some("Rnd 1: 6 dc in ring (6)"));
+        assert_eq!(lines.next(), Some("Rnd 2: 2 dc in next st x6 (12)"));
+
+        let read = Pattern::from_written(&written, Terminology::Us).unwrap();
+        assert_eq!(read.to_written(Terminology::Us), written);
+    }
+
+    #[test]
+    fn test_written_insert_modes() {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        part.magic_ring();
+        part.new_row().unwrap();
+        for _ in 0..6 {
+            part.sc_noskip().unwrap();
+        }
+        let mut round = |mode, work: &dyn Fn(&mut Part) -> Result<(), PatternError>| {
+            part.new_row().unwrap();
+            part.set_insert_mode(mode);
+            for _ in 0..6 {
+                work(&mut part).unwrap();
+            }
+            part.set_insert_mode(InsertMode::BothLoops);
+        };
+        round(InsertMode::BackLoop, &|part| part.inc_n(2, "sc").map(drop));
+        round(InsertMode::FrontLoop, &|part| part.dec_n(2, "sc").map(drop));
+        round(InsertMode::FrontPost, &|part| part.inc_n(2, "sc").map(drop));
+        round(InsertMode::BackPost, &|part| part.dec_n(2, "sc").map(drop));
+        drop(part);
+        let pattern = pattern.into_inner();
+
+        let written = pattern.to_written(Terminology::Us);
+        assert_eq!(
+            written.lines().skip(2).collect::<Vec<_>>(),
+            vec![
+                "Rnd 2: 2 BLO sc in next st x6 (12)",
+                "Rnd 3: FLO sc2tog x6 (6)",
+                "Rnd 4: 2 FPsc in next st x6 (12)",
+                "Rnd 5: BPsc2tog x6 (6)",
+            ]
+        );
+        assert_eq!(
+            Pattern::from_written(&written, Terminology::Us).unwrap(),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_read_written_insert_modes() {
+        let pattern = Pattern::from_written(
+            "Magic ring\n\
+             Rnd 1: 6 sc in ring (6)\n\
+             Rnd 2: blo inc x6 (12)\n\
+             Rnd 3: flo dec x6 (6)\n\
+             Rnd 4: fpinc x6 (12)\n\
+             Rnd 5: bpdec x6 (6)\n",
+            Terminology::Us,
+        )
+        .unwrap();
+
+        let written = pattern.to_written(Terminology::Us);
+        assert_eq!(
+            written.lines().skip(2).collect::<Vec<_>>(),
+            vec![
+                "Rnd 2: 2 BLO sc in next st x6 (12)",
+                "Rnd 3: FLO sc2tog x6 (6)",
+                "Rnd 4: 2 FPsc in next st x6 (12)",
+                "Rnd 5: BPsc2tog x6 (6)",
+            ]
+        );
+        // `dec` is written as sc2tog with a mode, so compare the written form
+        assert_eq!(
+            Pattern::from_written(&written, Terminology::Us)
+                .unwrap()
+                .to_written(Terminology::Us),
+            written
+        );
+    }
+}
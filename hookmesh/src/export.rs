@@ -0,0 +1,354 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use glam::Vec3;
+use hooklib::pattern::Pattern;
+use serde_json::json;
+
+use crate::{mesh_from_pattern, LayoutOptions, Mesh};
+
+/// A file format that a [`Mesh`] can be written to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MeshFormat {
+    /// Wavefront OBJ, with vertex colours after each position.
+    Obj,
+    /// Binary STL. This only stores geometry, so colours are lost.
+    Stl,
+    /// Binary little-endian PLY, with normals, texture coordinates and colours.
+    Ply,
+    /// Binary glTF 2.0 (GLB).
+    Glb,
+}
+
+impl MeshFormat {
+    /// All formats, in the order they should be offered to the user.
+    pub const ALL: [MeshFormat; 4] = [Self::Obj, Self::Stl, Self::Ply, Self::Glb];
+
+    /// The file extension used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Obj => "obj",
+            Self::Stl => "stl",
+            Self::Ply => "ply",
+            Self::Glb => "glb",
+        }
+    }
+
+    /// Find the format with the given file extension, ignoring case.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+
+    /// Find the format to use for the given path, from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension)
+    }
+}
+
+/// Convert a colour from 0-1 floats to bytes.
+fn color_bytes(color: Vec3) -> [u8; 3] {
+    (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+        .round()
+        .to_array()
+        .map(|c| c as u8)
+}
+
+impl Mesh {
+    /// Write the mesh in the given format.
+    pub fn write(&self, format: MeshFormat, w: impl Write) -> io::Result<()> {
+        match format {
+            MeshFormat::Obj => self.write_obj(w),
+            MeshFormat::Stl => self.write_stl(w),
+            MeshFormat::Ply => self.write_ply(w),
+            MeshFormat::Glb => self.write_glb(w),
+        }
+    }
+
+    /// Write the mesh to a file, choosing the format from its extension.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = MeshFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown mesh format for {}", path.display()),
+            )
+        })?;
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(format, &mut w)?;
+        w.flush()
+    }
+
+    /// Write the mesh as a Wavefront OBJ file.
+    /// Vertex colours are written after each position, which Blender and MeshLab both understand.
+    pub fn write_obj(&self, mut w: impl Write) -> io::Result<()> {
+        writeln!(w, "# Exported from Polyhook")?;
+        for v in &self.vertices {
+            let [x, y, z] = v.position.to_array();
+            let [r, g, b] = v.color.to_array();
+            writeln!(w, "v {x} {y} {z} {r} {g} {b}")?;
+        }
+        for v in &self.vertices {
+            writeln!(w, "vt {} {}", v.uv.x, v.uv.y)?;
+        }
+        for v in &self.vertices {
+            let [x, y, z] = v.normal.to_array();
+            writeln!(w, "vn {x} {y} {z}")?;
+        }
+        for [a, b, c] in self.triangles() {
+            let (a, b, c) = (a + 1, b + 1, c + 1);
+            writeln!(w, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the mesh as a binary STL file.
+    pub fn write_stl(&self, mut w: impl Write) -> io::Result<()> {
+        let mut header = [0u8; 80];
+        let title = b"Exported from Polyhook";
+        header[..title.len()].copy_from_slice(title);
+        w.write_all(&header)?;
+        w.write_all(&(self.num_triangles() as u32).to_le_bytes())?;
+
+        for tri in self.triangles() {
+            let [a, b, c] = tri.map(|i| self.vertices[i as usize].position);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for v in [normal, a, b, c] {
+                for f in v.to_array() {
+                    w.write_all(&f.to_le_bytes())?;
+                }
+            }
+            w.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write the mesh as a binary little-endian PLY file.
+    pub fn write_ply(&self, mut w: impl Write) -> io::Result<()> {
+        writeln!(w, "ply")?;
+        writeln!(w, "format binary_little_endian 1.0")?;
+        writeln!(w, "comment Exported from Polyhook")?;
+        writeln!(w, "element vertex {}", self.vertices.len())?;
+        for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+            writeln!(w, "property float {property}")?;
+        }
+        for property in ["red", "green", "blue"] {
+            writeln!(w, "property uchar {property}")?;
+        }
+        writeln!(w, "element face {}", self.num_triangles())?;
+        writeln!(w, "property list uchar uint vertex_indices")?;
+        writeln!(w, "end_header")?;
+
+        for v in &self.vertices {
+            for f in v
+                .position
+                .to_array()
+                .into_iter()
+                .chain(v.normal.to_array())
+                .chain(v.uv.to_array())
+            {
+                w.write_all(&f.to_le_bytes())?;
+            }
+            w.write_all(&color_bytes(v.color))?;
+        }
+        for tri in self.triangles() {
+            w.write_all(&[3])?;
+            for i in tri {
+                w.write_all(&i.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the mesh as a binary glTF 2.0 (GLB) file, with a single mesh in a single scene.
+    pub fn write_glb(&self, mut w: impl Write) -> io::Result<()> {
+        // Each attribute is stored one after another in a single buffer.
+        // All components are 4 bytes, so every view is already aligned.
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut views = Vec::new();
+        let mut add_view = |data: Vec<f32>, target: u32| {
+            let offset = buffer.len();
+            buffer.extend(data.into_iter().flat_map(f32::to_le_bytes));
+            views.push(json!({
+                "buffer": 0,
+                "byteOffset": offset,
+                "byteLength": buffer.len() - offset,
+                "target": target,
+            }));
+        };
+
+        const ARRAY_BUFFER: u32 = 34962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+        const FLOAT: u32 = 5126;
+        const UNSIGNED_INT: u32 = 5125;
+
+        let positions = self.vertices.iter().flat_map(|v| v.position.to_array());
+        add_view(positions.collect(), ARRAY_BUFFER);
+        let normals = self.vertices.iter().flat_map(|v| v.normal.to_array());
+        add_view(normals.collect(), ARRAY_BUFFER);
+        let uvs = self.vertices.iter().flat_map(|v| v.uv.to_array());
+        add_view(uvs.collect(), ARRAY_BUFFER);
+        let colors = self.vertices.iter().flat_map(|v| v.color.to_array());
+        add_view(colors.collect(), ARRAY_BUFFER);
+
+        let offset = buffer.len();
+        buffer.extend(self.indices.iter().flat_map(|i| i.to_le_bytes()));
+        views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": buffer.len() - offset,
+            "target": ELEMENT_ARRAY_BUFFER,
+        }));
+
+        // Positions need their bounds given, and glTF doesn't allow empty accessors.
+        let (min, max) = self.vertices.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), v| (min.min(v.position), max.max(v.position)),
+        );
+        let (min, max) = if self.vertices.is_empty() {
+            (Vec3::ZERO, Vec3::ZERO)
+        } else {
+            (min, max)
+        };
+
+        let count = self.vertices.len();
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "Polyhook" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": {
+                        "POSITION": 0,
+                        "NORMAL": 1,
+                        "TEXCOORD_0": 2,
+                        "COLOR_0": 3,
+                    },
+                    "indices": 4,
+                    "material": 0,
+                }],
+            }],
+            "materials": [{
+                "pbrMetallicRoughness": { "metallicFactor": 0.0, "roughnessFactor": 1.0 },
+                "doubleSided": true,
+            }],
+            "accessors": [
+                { "bufferView": 0, "componentType": FLOAT, "count": count, "type": "VEC3",
+                  "min": min.to_array(), "max": max.to_array() },
+                { "bufferView": 1, "componentType": FLOAT, "count": count, "type": "VEC3" },
+                { "bufferView": 2, "componentType": FLOAT, "count": count, "type": "VEC2" },
+                { "bufferView": 3, "componentType": FLOAT, "count": count, "type": "VEC3" },
+                { "bufferView": 4, "componentType": UNSIGNED_INT, "count": self.indices.len(), "type": "SCALAR" },
+            ],
+            "bufferViews": views,
+            "buffers": [{ "byteLength": buffer.len() }],
+        });
+
+        // Both chunks must be padded to 4 bytes, the JSON with spaces and the buffer with zeros.
+        let mut json = serde_json::to_vec(&document)?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        w.write_all(b"glTF")?;
+        w.write_all(&2u32.to_le_bytes())?;
+        w.write_all(&(length as u32).to_le_bytes())?;
+        w.write_all(&(json.len() as u32).to_le_bytes())?;
+        w.write_all(b"JSON")?;
+        w.write_all(&json)?;
+        w.write_all(&(buffer.len() as u32).to_le_bytes())?;
+        w.write_all(b"BIN\0")?;
+        w.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+/// Lay out a pattern in 3D and write the resulting mesh to a file, choosing the format from its extension.
+pub fn export_pattern(pattern: &Pattern, options: &LayoutOptions, path: &Path) -> io::Result<()> {
+    mesh_from_pattern(pattern, options).save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use hooklib::pattern::test_pattern_flat;
+
+    use super::*;
+    use crate::mesh_from_pattern_2d;
+
+    fn test_mesh() -> Mesh {
+        mesh_from_pattern_2d(&test_pattern_flat(5).unwrap(), &LayoutOptions::default())
+    }
+
+    #[test]
+    fn test_text_formats() {
+        let mesh = test_mesh();
+        assert!(mesh.num_triangles() > 0);
+
+        let mut obj = Vec::new();
+        mesh.write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let count = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(count("v "), mesh.vertices.len());
+        assert_eq!(count("vn "), mesh.vertices.len());
+        assert_eq!(count("f "), mesh.num_triangles());
+        assert_eq!(obj.lines().nth(1).unwrap().split(' ').count(), 7);
+
+        let mut ply = Vec::new();
+        mesh.write_ply(&mut ply).unwrap();
+        let header_end = b"end_header\n";
+        let body = ply
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .unwrap()
+            + header_end.len();
+        assert_eq!(
+            ply.len() - body,
+            mesh.vertices.len() * (8 * 4 + 3) + mesh.num_triangles() * (1 + 3 * 4)
+        );
+    }
+
+    #[test]
+    fn test_binary_formats() {
+        let mesh = test_mesh();
+
+        let mut stl = Vec::new();
+        mesh.write_stl(&mut stl).unwrap();
+        assert_eq!(stl.len(), 84 + 50 * mesh.num_triangles());
+        assert_eq!(
+            u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize,
+            mesh.num_triangles()
+        );
+
+        let mut glb = Vec::new();
+        mesh.write_glb(&mut glb).unwrap();
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let document: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        assert_eq!(
+            document["accessors"][4]["count"].as_u64().unwrap() as usize,
+            mesh.indices.len()
+        );
+        assert_eq!(
+            document["buffers"][0]["byteLength"].as_u64().unwrap() as usize,
+            glb.len() - 20 - json_len - 8
+        );
+    }
+
+    #[test]
+    fn test_formats_from_path() {
+        assert_eq!(
+            MeshFormat::from_path(Path::new("bear.GLB")),
+            Some(MeshFormat::Glb)
+        );
+        assert_eq!(MeshFormat::from_path(Path::new("bear.ph")), None);
+        assert!(test_mesh().save(Path::new("bear.fbx")).is_err());
+    }
+}
@@ -0,0 +1,665 @@
+//! Building triangle meshes from laid-out crochet patterns, without needing a GPU.
+
+use std::collections::HashSet;
+
+use glam::{Vec2, Vec3};
+use hooklib::pattern::{EdgeType, Node, Pattern};
+use petgraph::{
+    graph::NodeIndex,
+    visit::{EdgeRef, IntoNodeReferences},
+    Direction::{Incoming, Outgoing},
+};
+use sgd::sgd_with_progress;
+
+pub use sgd::{Cancelled, LayoutOptions, ProgressFn, Region, SgdMode};
+
+/// Writing meshes to common 3D file formats.
+pub mod export;
+
+/// A single vertex of a [`Mesh`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshVertex {
+    pub position: Vec3,
+    pub uv: Vec2,
+    pub color: Vec3,
+    pub normal: Vec3,
+    pub tangent: Vec3,
+}
+
+impl MeshVertex {
+    pub fn new(position: Vec3, uv: Vec2, color: Vec3, normal: Vec3, tangent: Vec3) -> Self {
+        Self {
+            position,
+            uv,
+            color,
+            normal,
+            tangent,
+        }
+    }
+}
+
+/// A triangle mesh, with every three indices making up one triangle.
+/// Meshes built from patterns are in millimetres, sized by the pattern's gauge.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    /// Iterate over each triangle in the mesh, as the indices of its three vertices.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|tri| [tri[0], tri[1], tri[2]])
+    }
+
+    /// The number of triangles in the mesh.
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+fn mesh_from_graph(graph: petgraph::Graph<(Vec3, &Node), &EdgeType>) -> Mesh {
+    let mut verts: Vec<MeshVertex> = Vec::new();
+    let mut tris: Vec<[u32; 3]> = Vec::new();
+
+    let mut create_rect =
+        |source_pos: Vec3, target_pos: Vec3, tangent: Vec3, width: f32, color: Vec3| {
+            let dir = target_pos - source_pos;
+            let offset_len = width * 0.5;
+
+            let normal = dir.cross(tangent).normalize();
+            let offset_x = normal.cross(dir).normalize() * offset_len;
+
+            let idx = verts.len() as u32;
+            verts.extend([
+                MeshVertex::new(
+                    source_pos - offset_x,
+                    [1.0, 0.0].into(),
+                    color,
+                    normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    source_pos + offset_x,
+                    [0.0, 0.0].into(),
+                    color,
+                    normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    target_pos + offset_x,
+                    [0.0, 0.5].into(),
+                    color,
+                    normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    target_pos - offset_x,
+                    [1.0, 0.5].into(),
+                    color,
+                    normal,
+                    tangent,
+                ),
+            ]);
+            tris.push([idx, idx + 1, idx + 2]);
+            tris.push([idx + 2, idx + 3, idx]);
+
+            let idx = verts.len() as u32;
+            verts.extend([
+                MeshVertex::new(
+                    source_pos - offset_x,
+                    [0.0, 0.5].into(),
+                    color,
+                    -normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    source_pos + offset_x,
+                    [1.0, 0.5].into(),
+                    color,
+                    -normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    target_pos + offset_x,
+                    [1.0, 1.0].into(),
+                    color,
+                    -normal,
+                    tangent,
+                ),
+                MeshVertex::new(
+                    target_pos - offset_x,
+                    [0.0, 1.0].into(),
+                    color,
+                    -normal,
+                    tangent,
+                ),
+            ]);
+            tris.push([idx, idx + 2, idx + 1]);
+            tris.push([idx + 3, idx + 2, idx]);
+        };
+
+    graph
+        .node_references()
+        .for_each(|(node, (source_pos, node_type))| {
+            let color = match node_type {
+                Node::Stitch { color, .. } => *color,
+                _ => Vec3::ONE,
+            };
+            graph.edges_directed(node, Outgoing).for_each(|e| {
+                if e.weight().is_insert() {
+                    let target_pos = graph.node_weight(e.target()).unwrap().0;
+
+                    let tangent_1 = graph
+                        .edges_directed(node, Incoming)
+                        .find(|e| **e.weight() == EdgeType::Previous)
+                        .map(|e| source_pos - graph.node_weight(e.source()).unwrap().0)
+                        .unwrap_or(Vec3::X);
+                    let tangent_2 = graph
+                        .edges_directed(node, Outgoing)
+                        .find(|e| **e.weight() == EdgeType::Previous)
+                        .map(|e| source_pos - graph.node_weight(e.target()).unwrap().0)
+                        .unwrap_or(Vec3::X);
+                    let tangent = if tangent_1.dot(tangent_2) <= 0.0 {
+                        (tangent_1 - tangent_2) / 2.0
+                    } else {
+                        (tangent_1 + tangent_2) / 2.0
+                    };
+
+                    create_rect(*source_pos, target_pos, tangent, tangent.length(), color);
+                } else if **e.weight() == EdgeType::Previous && node_type.stitch_type() == "ch" {
+                    let target_pos = graph.node_weight(e.target()).unwrap().0;
+
+                    let tangent_1 = graph
+                        .edges_directed(node, Incoming)
+                        .find(|e| e.weight().is_insert())
+                        .map(|e| source_pos - graph.node_weight(e.source()).unwrap().0)
+                        .unwrap_or(Vec3::X);
+                    let tangent_2 = graph
+                        .edges_directed(node, Incoming)
+                        .find(|e| **e.weight() == EdgeType::Previous)
+                        .map(|e| source_pos - graph.node_weight(e.source()).unwrap().0)
+                        .unwrap_or(Vec3::X);
+                    let tangent = if tangent_1.dot(tangent_2) <= 0.0 {
+                        (tangent_1 - tangent_2) / 2.0
+                    } else {
+                        (tangent_1 + tangent_2) / 2.0
+                    };
+
+                    create_rect(*source_pos, target_pos, tangent, tangent.length(), color);
+                }
+            });
+        });
+
+    Mesh {
+        vertices: verts,
+        indices: tris.into_iter().flatten().collect(),
+    }
+}
+
+/// The regions of a pattern to inflate: every part the script stuffed, and every other part that looks closed.
+/// Closed parts use [`LayoutOptions::pressure`], so they're only inflated if it's set.
+pub fn stuffed_regions(pattern: &Pattern) -> Vec<Region> {
+    pattern
+        .parts()
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rows)| {
+            let pressure = pattern.stuffing(i);
+            (pressure.is_some() || pattern.is_closed(i)).then(|| Region {
+                nodes: rows.iter().flatten().copied().collect(),
+                pressure,
+            })
+        })
+        .collect()
+}
+
+/// The triangulated graph of a pattern, with lengths in stitch widths rather than millimetres.
+/// Layout works in stitch widths so that [`LayoutOptions`] mean the same whatever the gauge,
+/// returning the width of a stitch in millimetres to scale the result back up.
+fn layout_graph(pattern: &Pattern) -> (petgraph::Graph<(), f32>, f32) {
+    let width = pattern.gauge().stitch_width();
+    let mut graph = pattern.triangulated_graph();
+    graph.edge_weights_mut().for_each(|length| *length /= width);
+    (graph, width)
+}
+
+/// Lay out a pattern in 3D, returning the position of each node by index in millimetres.
+/// `initial` gives starting positions for some or all nodes, e.g. from [`warm_start`], and can be empty.
+/// Progress is reported from 0 to 1 over the whole layout, and returning `false` cancels it.
+pub fn layout_pattern(
+    pattern: &Pattern,
+    options: &LayoutOptions,
+    initial: &[Option<Vec3>],
+    progress: &ProgressFn<'_>,
+) -> Result<Vec<Vec3>, Cancelled> {
+    // force-directed layout and inflation are much quicker than SGD, so they only get a small share of the progress
+    const SGD_SHARE: f32 = 0.9;
+    const FDG_SHARE: f32 = 0.05;
+
+    let (layout_graph, width) = layout_graph(pattern);
+    let initial = initial
+        .iter()
+        .map(|p| p.map(|p| p / width))
+        .collect::<Vec<_>>();
+    let mut graph =
+        sgd_with_progress::<Vec3, _, _>(&layout_graph, options, &initial, &|fraction| {
+            progress(fraction * SGD_SHARE)
+        })?;
+    sgd::fdg_with_progress(&mut graph, options, &|fraction| {
+        progress(SGD_SHARE + fraction * FDG_SHARE)
+    })?;
+    sgd::inflate_with_progress(
+        &mut graph,
+        &stuffed_regions(pattern),
+        options,
+        &|fraction| progress(SGD_SHARE + FDG_SHARE + fraction * (1.0 - SGD_SHARE - FDG_SHARE)),
+    )?;
+    sgd::rescale(&mut graph);
+    let _ = sgd::normalize(&mut graph);
+
+    let mut positions = graph.node_weights().map(|p| *p * width).collect::<Vec<_>>();
+    // folds and post stitches only have their size set by the layout, so mirror it if they're on the wrong side
+    if pattern.side_agreement(&positions) < 0.0 {
+        positions.iter_mut().for_each(|p| p.x = -p.x);
+    }
+
+    Ok(positions)
+}
+
+/// Lay out a pattern as a flat sheet, returning the position of each node by index in millimetres with a z of zero.
+pub fn layout_pattern_2d(
+    pattern: &Pattern,
+    options: &LayoutOptions,
+    initial: &[Option<Vec3>],
+    progress: &ProgressFn<'_>,
+) -> Result<Vec<Vec3>, Cancelled> {
+    let (layout_graph, width) = layout_graph(pattern);
+    let initial = initial
+        .iter()
+        .map(|p| p.map(|p| p.truncate() / width))
+        .collect::<Vec<_>>();
+    let mut graph = sgd_with_progress::<Vec2, _, _>(&layout_graph, options, &initial, progress)?;
+    sgd::rescale(&mut graph);
+
+    Ok(graph
+        .node_weights()
+        .map(|p| (*p * width).extend(0.0))
+        .collect())
+}
+
+/// Find starting positions for laying out a pattern from the layout of a previous version of it.
+/// Nodes at the same place in both versions (see [`Pattern::matching_nodes`]) start where they were before.
+pub fn warm_start(
+    pattern: &Pattern,
+    previous: &Pattern,
+    previous_positions: &[Vec3],
+) -> Vec<Option<Vec3>> {
+    pattern
+        .matching_nodes(previous)
+        .into_iter()
+        .map(|node| node.and_then(|node| previous_positions.get(node.index()).copied()))
+        .collect()
+}
+
+/// The colour of highlighted stitches, see [`mesh_from_layout_highlighted`].
+pub const HIGHLIGHT_COLOR: Vec3 = Vec3::new(1.0, 0.75, 0.0);
+
+/// Build a mesh from a pattern and the position of each of its nodes.
+pub fn mesh_from_layout(pattern: &Pattern, positions: &[Vec3]) -> Mesh {
+    mesh_from_layout_highlighted(pattern, positions, &[])
+}
+
+/// Build a mesh from a pattern and the position of each of its nodes,
+/// with the given stitches drawn in [`HIGHLIGHT_COLOR`] instead of their own colour.
+pub fn mesh_from_layout_highlighted(
+    pattern: &Pattern,
+    positions: &[Vec3],
+    highlighted: &[NodeIndex],
+) -> Mesh {
+    let highlighted = highlighted.iter().copied().collect::<HashSet<_>>();
+    let orig_graph = pattern.graph();
+    let nodes = orig_graph
+        .node_indices()
+        .map(|ix| match orig_graph[ix] {
+            Node::Stitch { ty, turn, .. } if highlighted.contains(&ix) => Node::Stitch {
+                ty,
+                turn,
+                color: HIGHLIGHT_COLOR,
+            },
+            node => node,
+        })
+        .collect::<Vec<_>>();
+    let graph = orig_graph.map(
+        |ix, _| (positions[ix.index()], &nodes[ix.index()]),
+        |_ix, edge| edge,
+    );
+
+    mesh_from_graph(graph)
+}
+
+/// Lay out a pattern in 3D and build a mesh from it.
+/// The same pattern laid out with the same options always gives an identical mesh.
+pub fn mesh_from_pattern(pattern: &Pattern, options: &LayoutOptions) -> Mesh {
+    let positions = layout_pattern(pattern, options, &[], &|_| true);
+    mesh_from_layout(pattern, &positions.expect("Layout can't be cancelled"))
+}
+
+/// Lay out a pattern as a flat sheet and build a mesh from it.
+pub fn mesh_from_pattern_2d(pattern: &Pattern, options: &LayoutOptions) -> Mesh {
+    let positions = layout_pattern_2d(pattern, options, &[], &|_| true);
+    mesh_from_layout(pattern, &positions.expect("Layout can't be cancelled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use hooklib::pattern::{test_pattern_flat, Gauge, InsertMode};
+
+    use super::*;
+    use std::io::Write;
+
+    const TEST_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_out");
+
+    #[test]
+    fn test_runtime() {
+        // Requirement: a pattern of less than 2500 nodes shouldn't take more than 30s to compute.
+
+        // Flat pattern should have at least 49 * 49 = 2401 nodes
+        // (more including foundation chain)
+        let pattern = test_pattern_flat(49).unwrap();
+        assert!(pattern.graph().node_count() <= 2500);
+
+        let start_time = std::time::Instant::now();
+        let _mesh = mesh_from_pattern(&pattern, &LayoutOptions::default());
+        let elapsed = start_time.elapsed().as_secs_f64();
+
+        assert!(elapsed <= 30.0);
+    }
+
+    #[test]
+    #[ignore = "Analyzes the runtime for many different graph sizes, takes a few minutes to run."]
+    fn test_analyze_runtime() {
+        // Requirement: a pattern of less than 2500 nodes shouldn't take more than 30s to compute.
+
+        // Flat pattern should have at least 49 * 49 = 2401 nodes
+        // (more including foundation chain)
+
+        let mut file = std::fs::File::create(format!("{TEST_DIR}/runtime.csv")).unwrap();
+        (5..49).for_each(|i| {
+            let pattern = test_pattern_flat(i).unwrap();
+            let nodes = pattern.graph().node_count();
+
+            let start_time = std::time::Instant::now();
+            let _mesh = mesh_from_pattern(&pattern, &LayoutOptions::default());
+            let elapsed = start_time.elapsed().as_secs_f64();
+
+            writeln!(file, "{nodes}, {elapsed}").unwrap()
+        });
+    }
+
+    /// Two flat panels, sewn together along their last rows like the Sew example.
+    fn sewn_panels(width: usize, rows: usize) -> Pattern {
+        let pattern = Pattern::new();
+        let mut last_rows = vec![];
+        for _ in 0..2 {
+            let mut part = pattern.add_part();
+            for _ in 0..width {
+                part.chain().unwrap();
+            }
+            for _ in 0..rows {
+                part.turn().unwrap();
+                for _ in 0..width {
+                    part.dc().unwrap();
+                }
+            }
+            last_rows.push(part.current_row().unwrap().clone());
+        }
+        pattern
+            .sew(last_rows[0].clone(), last_rows[1].clone())
+            .unwrap();
+        pattern.into_inner()
+    }
+
+    /// The triangles of the fabric of a pattern: each stitch, the one before it, and the stitches they're worked into.
+    /// Each is paired with its corners with stitches sewn together treated as one,
+    /// so triangles meeting at a seam count as sharing a corner.
+    fn fabric_triangles(pattern: &Pattern) -> Vec<([usize; 3], [usize; 3])> {
+        let graph = pattern.graph();
+        let mut seams = (0..graph.node_count()).collect::<Vec<_>>();
+        for e in graph.edge_references() {
+            if *e.weight() == EdgeType::Sew {
+                seams[e.target().index()] = seams[e.source().index()];
+            }
+        }
+        let insert = |node| {
+            graph
+                .edges_directed(node, Outgoing)
+                .find(|e| e.weight().is_insert())
+                .map(|e| e.target())
+        };
+        graph
+            .node_indices()
+            .filter_map(|node| {
+                let below = insert(node)?;
+                let previous = graph
+                    .edges_directed(node, Incoming)
+                    .find(|e| *e.weight() == EdgeType::Previous)?
+                    .source();
+                let previous_below = insert(previous)?;
+                let mut triangles = vec![[node.index(), previous.index(), below.index()]];
+                if previous_below != below {
+                    triangles.push([previous.index(), previous_below.index(), below.index()]);
+                }
+                Some(triangles)
+            })
+            .flatten()
+            .map(|t| (t, t.map(|n| seams[n])))
+            .collect()
+    }
+
+    /// Whether the segment from `a` to `b` passes through the triangle `t`.
+    fn segment_hits_triangle(a: Vec3, b: Vec3, t: [Vec3; 3]) -> bool {
+        let dir = b - a;
+        let (e1, e2) = (t[1] - t[0], t[2] - t[0]);
+        let h = dir.cross(e2);
+        let det = e1.dot(h);
+        if det.abs() < 1e-9 {
+            return false;
+        }
+        let s = a - t[0];
+        let u = s.dot(h) / det;
+        let q = s.cross(e1);
+        let v = dir.dot(q) / det;
+        let along = e2.dot(q) / det;
+        u > 0.0 && v > 0.0 && u + v < 1.0 && along > 0.0 && along < 1.0
+    }
+
+    /// Count the pairs of triangles that don't share a corner but pass through each other.
+    fn count_intersections(triangles: &[([usize; 3], [usize; 3])], positions: &[Vec3]) -> usize {
+        let corners = |t: &[usize; 3]| t.map(|n| positions[n]);
+        triangles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, t1)| triangles[i + 1..].iter().map(move |t2| (t1, t2)))
+            .filter(|((_, c1), (_, c2))| !c1.iter().any(|n| c2.contains(n)))
+            .filter(|((t1, _), (t2, _))| {
+                let edges_through = |t1: &[usize; 3], t2: &[usize; 3]| {
+                    let (c1, c2) = (corners(t1), corners(t2));
+                    (0..3).any(|i| segment_hits_triangle(c1[i], c1[(i + 1) % 3], c2))
+                };
+                edges_through(t1, t2) || edges_through(t2, t1)
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_sewn_panels_dont_intersect() {
+        let pattern = sewn_panels(8, 6);
+        let triangles = fabric_triangles(&pattern);
+        for seed in 0..3 {
+            let options = LayoutOptions {
+                seed,
+                repulsion: 1.0,
+                ..Default::default()
+            };
+            let positions = layout_pattern(&pattern, &options, &[], &|_| true).unwrap();
+            assert_eq!(count_intersections(&triangles, &positions), 0);
+        }
+    }
+
+    #[test]
+    fn test_physical_size() {
+        // at 20 stitches and 20 rows of dc per 10cm, 20 stitches should be 10cm wide, and 10 rows 5cm tall
+        let pattern = test_pattern_flat(20).unwrap();
+        let gauge = Gauge::new(20.0, 20.0);
+        pattern.set_gauge(gauge.clone());
+        let positions =
+            layout_pattern(&pattern, &LayoutOptions::default(), &[], &|_| true).unwrap();
+        let path_length = |nodes: &[NodeIndex]| {
+            nodes
+                .windows(2)
+                .map(|w| positions[w[0].index()].distance(positions[w[1].index()]))
+                .sum::<f32>()
+        };
+
+        // skip the turning chain at the start of the row
+        let row = pattern.parts()[0][10][1..].to_vec();
+        let width = path_length(&row) + gauge.stitch_width();
+        assert!((width - 100.0).abs() < 3.0, "row is {width}mm wide");
+
+        let graph = pattern.graph();
+        let column = std::iter::successors(Some(row[0]), |node| {
+            graph
+                .edges_directed(*node, Outgoing)
+                .find(|e| e.weight().is_insert())
+                .map(|e| e.target())
+        })
+        .take(10)
+        .collect::<Vec<_>>();
+        let height = path_length(&column) + gauge.height(&graph[row[0]]);
+        assert!((height - 50.0).abs() < 1.5, "column is {height}mm tall");
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let pattern = hooklib::pattern::test_pattern_sphere().unwrap();
+        let options = LayoutOptions {
+            seed: 42,
+            ..Default::default()
+        };
+        let positions = |mesh: Mesh| {
+            mesh.vertices
+                .iter()
+                .flat_map(|v| v.position.to_array().map(f32::to_bits))
+                .collect::<Vec<_>>()
+        };
+
+        let first = mesh_from_pattern(&pattern, &options);
+        let second = mesh_from_pattern(&pattern, &options);
+        assert_eq!(first.indices, second.indices);
+        assert_eq!(positions(first), positions(second));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let pattern = test_pattern_flat(3).unwrap();
+        let positions = layout_pattern_2d(&pattern, &LayoutOptions::default(), &[], &|_| true)
+            .expect("Layout can't be cancelled");
+        let row = pattern.parts()[0][1].clone();
+        let plain = mesh_from_layout(&pattern, &positions);
+        let highlighted = mesh_from_layout_highlighted(&pattern, &positions, &row);
+
+        let highlights = |mesh: &Mesh| {
+            mesh.vertices
+                .iter()
+                .filter(|v| v.color == HIGHLIGHT_COLOR)
+                .count()
+        };
+        assert_eq!(highlights(&plain), 0);
+        assert!(highlights(&highlighted) > 0);
+        assert_eq!(plain.indices, highlighted.indices);
+    }
+
+    /// A flat piece of plain rows with a single row worked into the row below it as given by `modes`, repeated
+    /// across the row, then more plain rows on top. Returns the pattern and the index of the row worked that way.
+    fn piece_with_row(modes: &[InsertMode]) -> (Pattern, usize) {
+        let pattern = Pattern::new();
+        let mut part = pattern.add_part();
+        for _ in 0..12 {
+            part.chain().unwrap();
+        }
+        for row in 0..7 {
+            part.turn().unwrap();
+            for i in 0..11 {
+                let mode = if row == 3 {
+                    modes[i % modes.len()]
+                } else {
+                    InsertMode::BothLoops
+                };
+                part.set_insert_mode(mode);
+                part.dc().unwrap();
+            }
+        }
+        drop(part);
+        (pattern.into_inner(), 4)
+    }
+
+    /// How far each stitch in a row stands out from the stitch it's worked into,
+    /// towards the front of the fabric as the row is worked.
+    fn front_offsets(pattern: &Pattern, positions: &[Vec3], row: usize) -> Vec<f32> {
+        let graph = pattern.graph();
+        let insert = |node: NodeIndex| {
+            graph
+                .edges_directed(node, Outgoing)
+                .find(|e| e.weight().is_insert())
+                .map(|e| e.target())
+                .unwrap()
+        };
+        let position = |node: NodeIndex| positions[node.index()];
+
+        // skip the turning chain at the start of the row
+        let row = pattern.parts()[0][row][1..].to_vec();
+        let (first, last) = (row[0], *row.last().unwrap());
+        let middle = insert(row[row.len() / 2]);
+        // the row is worked right to left when looking at its front
+        let along = position(last) - position(first);
+        let up = position(middle) - position(insert(middle));
+        let front = up.cross(along).normalize();
+
+        row.iter()
+            .map(|s| front.dot(position(*s) - position(insert(*s))))
+            .collect()
+    }
+
+    #[test]
+    fn test_fold_direction() {
+        for (mode, towards_front) in [(InsertMode::BackLoop, false), (InsertMode::FrontLoop, true)]
+        {
+            let (pattern, row) = piece_with_row(&[mode]);
+            let positions =
+                layout_pattern(&pattern, &LayoutOptions::default(), &[], &|_| true).unwrap();
+            let offset = front_offsets(&pattern, &positions, row).iter().sum::<f32>();
+            assert_eq!(
+                offset > 0.0,
+                towards_front,
+                "{mode:?} row folds the wrong way"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ribbing_sides() {
+        let (pattern, row) = piece_with_row(&[InsertMode::FrontPost, InsertMode::BackPost]);
+        let positions =
+            layout_pattern(&pattern, &LayoutOptions::default(), &[], &|_| true).unwrap();
+        let offsets = front_offsets(&pattern, &positions, row);
+        let (front_post, back_post): (Vec<_>, Vec<_>) =
+            offsets.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+        let mean = |offsets: Vec<(usize, &f32)>| {
+            offsets.iter().map(|(_, o)| **o).sum::<f32>() / offsets.len() as f32
+        };
+        assert!(mean(front_post) > mean(back_post));
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use hooklib::analysis::{color_hex, YarnOptions};
+use hooklib::pattern::{Node, Pattern};
+use hooklib::presets::Presets;
+use hooklib::script::{PatternScript, Script, Sweep, VariantResult};
+use hooklib::written::Terminology;
+use hookmesh::export::MeshFormat;
+use hookmesh::{layout_pattern, layout_pattern_2d, mesh_from_layout, LayoutOptions, SgdMode};
+use rhai::{Dynamic, ImmutableString};
+
+type CliResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Evaluate a Polyhook pattern script without a window, and write out the results.
+///
+/// Text outputs can be given `-` as a path to write them to stdout instead.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    /// The `.ph` pattern script to evaluate.
+    script: PathBuf,
+
+    /// Override an exported parameter, e.g. `--set rows=20`.
+    /// Values are read as Rhai expressions, or as a plain string if they aren't valid.
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_set)]
+    set: Vec<(ImmutableString, Dynamic)>,
+
+    /// Start from the parameter values of a preset saved next to the script, e.g. `--preset Adult`.
+    /// Values given with `--set` override the preset's.
+    #[arg(long, value_name = "NAME")]
+    preset: Option<String>,
+
+    /// Save the parameter values from `--preset` and `--set` as a preset next to the script, replacing any with the same name.
+    #[arg(long, value_name = "NAME")]
+    save_preset: Option<String>,
+
+    /// Evaluate the script for each of the given values of a parameter, e.g. `--sweep rows=10,20,30`, and report on each variant
+    /// instead of writing outputs. Given more than once, every combination of the values is evaluated.
+    #[arg(long, value_name = "NAME=VALUES", value_parser = parse_sweep)]
+    sweep: Vec<(ImmutableString, Vec<Dynamic>)>,
+
+    /// Evaluate the script for each preset saved next to it, and report on each variant instead of writing outputs.
+    #[arg(long, conflicts_with = "sweep")]
+    sweep_presets: bool,
+
+    /// Write the crochet graph in GraphViz format.
+    #[arg(long, value_name = "PATH")]
+    graphviz: Option<PathBuf>,
+
+    /// Write the crochet graph in Polyhook's JSON file format.
+    #[arg(long, value_name = "PATH")]
+    json: Option<PathBuf>,
+
+    /// Lay out the pattern and write it as a mesh. The format is chosen from the extension (obj, stl, ply or glb).
+    #[arg(long, value_name = "PATH")]
+    mesh: Vec<PathBuf>,
+
+    /// Lay out meshes as a flat sheet rather than in 3D.
+    #[arg(long = "2d")]
+    flat: bool,
+
+    /// The seed for the random initial layout. The same seed always gives the same mesh.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Use sparse SGD layout with the given number of pivots, for large patterns.
+    #[arg(long, value_name = "N")]
+    pivots: Option<usize>,
+
+    /// Apply SGD updates from many threads at once. This is faster, but the layout is no longer deterministic.
+    #[arg(long)]
+    hogwild: bool,
+
+    /// The number of iterations of SGD layout.
+    #[arg(long, value_name = "N")]
+    iterations: Option<u32>,
+
+    /// The number of iterations of force-directed layout, after SGD.
+    #[arg(long, value_name = "N")]
+    fdg_iterations: Option<u32>,
+
+    /// Push apart stitches that aren't connected with the given strength, so sewn parts don't pass through each other.
+    #[arg(long, value_name = "STRENGTH", default_value_t = 0.0)]
+    repulsion: f32,
+
+    /// How firmly to stuff closed parts that don't set their own stuffing, inflating them. 1 is firmly stuffed.
+    #[arg(long, value_name = "PRESSURE", default_value_t = 0.0)]
+    stuffing: f32,
+
+    /// Give up if evaluating the script and laying out the mesh take longer than this many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<f64>,
+
+    /// Write the pattern as written instructions.
+    #[arg(long, value_name = "PATH")]
+    written: Option<PathBuf>,
+
+    /// Use UK terminology for written instructions.
+    #[arg(long)]
+    uk: bool,
+
+    /// Write a report of the stitch counts, increases and decreases of each row, and of each stitch type. Defaults to stdout.
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "-")]
+    report: Option<PathBuf>,
+
+    /// Write an estimate of the yarn used in each colour, and the colour changes in each row. Defaults to stdout.
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "-")]
+    yarn: Option<PathBuf>,
+
+    /// The length of yarn in 100g, from the yarn label, to estimate the weight of yarn used.
+    #[arg(long, value_name = "METRES")]
+    metres_per_100g: Option<f32>,
+}
+
+/// Split a `NAME=VALUE` argument into the parameter name and its value.
+fn split_assignment(arg: &str) -> Result<(&str, &str), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got \"{arg}\""))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("missing parameter name in \"{arg}\""));
+    }
+    Ok((name, value))
+}
+
+/// Parse a `NAME=VALUE` parameter override.
+fn parse_set(arg: &str) -> Result<(ImmutableString, Dynamic), String> {
+    let (name, value) = split_assignment(arg)?;
+
+    let value = rhai::Engine::new()
+        .eval_expression::<Dynamic>(value)
+        .unwrap_or_else(|_| value.into());
+    Ok((name.into(), value))
+}
+
+/// Parse a `NAME=VALUE,VALUE,...` parameter sweep.
+/// Values are read as a list of Rhai expressions, or as plain strings if none of them are valid.
+/// A mix of both is rejected, rather than quietly reading the valid ones as strings too.
+fn parse_sweep(arg: &str) -> Result<(ImmutableString, Vec<Dynamic>), String> {
+    let (name, values) = split_assignment(arg)?;
+    let engine = rhai::Engine::new();
+    let values = match engine.eval_expression::<rhai::Array>(&format!("[{values}]")) {
+        Ok(values) => values,
+        Err(_) => {
+            let values = values.split(',').map(str::trim).collect::<Vec<_>>();
+            if let Some(value) = values
+                .iter()
+                .find(|v| engine.eval_expression::<Dynamic>(v).is_ok())
+            {
+                return Err(format!(
+                    "mixed values for \"{name}\": \"{value}\" is a value but the others aren't, quote any text"
+                ));
+            }
+            values.into_iter().map(|v| v.into()).collect()
+        }
+    };
+    if values.is_empty() {
+        return Err(format!("missing values for \"{name}\""));
+    }
+    Ok((name.into(), values))
+}
+
+/// Write text to the given path, or to stdout if the path is `-`.
+fn write_text(path: &Path, text: &str) -> CliResult<()> {
+    if path == Path::new("-") {
+        std::io::stdout().write_all(text.as_bytes())?;
+    } else {
+        std::fs::write(path, text)
+            .map_err(|err| format!("Couldn't write {}: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Count the stitches, increases and decreases in each row of each part, and the total of each stitch type.
+fn stitch_report(pattern: &Pattern) -> String {
+    let graph = pattern.graph();
+    let mut report = String::new();
+
+    for (i, rows) in pattern.row_counts().iter().enumerate() {
+        if rows.iter().all(|row| row.stitches == 0) {
+            continue;
+        }
+        writeln!(report, "Part {}", i + 1).unwrap();
+        for (j, row) in rows.iter().enumerate() {
+            write!(report, "  Row {j}: {}", row.stitches).unwrap();
+            if row.increases > 0 {
+                write!(report, ", {} inc", row.increases).unwrap();
+            }
+            if row.decreases > 0 {
+                write!(report, ", {} dec", row.decreases).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+    }
+
+    let mut totals = BTreeMap::new();
+    for node in graph.node_weights() {
+        if let Node::Stitch { ty, .. } = node {
+            *totals.entry(*ty).or_insert(0) += 1;
+        }
+    }
+    writeln!(report, "Totals").unwrap();
+    for (ty, count) in &totals {
+        writeln!(report, "  {ty}: {count}").unwrap();
+    }
+    writeln!(report, "  all: {}", totals.values().sum::<usize>()).unwrap();
+
+    report
+}
+
+/// Estimate the yarn used in each colour, and count the colour changes in each row.
+fn yarn_report(pattern: &Pattern, options: &YarnOptions) -> String {
+    let usage = pattern.yarn_usage(options);
+    let mut report = String::new();
+
+    let weight = |weight: Option<f32>| weight.map_or(String::new(), |w| format!(", {w:.1}g"));
+    for color in &usage.colors {
+        let stitches = color
+            .stitches
+            .iter()
+            .map(|(ty, count)| format!("{count} {ty}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            report,
+            "{}: {:.2}m{} ({stitches})",
+            color_hex(color.color),
+            color.length,
+            weight(color.weight)
+        )
+        .unwrap();
+    }
+    writeln!(
+        report,
+        "Total: {:.2}m{}",
+        usage.total_length(),
+        weight(usage.total_weight())
+    )
+    .unwrap();
+
+    for (i, rows) in usage.color_changes.iter().enumerate() {
+        if rows.iter().all(|changes| *changes == 0) {
+            continue;
+        }
+        writeln!(report, "Colour changes in part {}", i + 1).unwrap();
+        for (j, changes) in rows.iter().enumerate().filter(|(_, c)| **c > 0) {
+            writeln!(report, "  Row {j}: {changes}").unwrap();
+        }
+    }
+
+    report
+}
+
+/// Summarise each variant evaluated in a sweep on a line.
+fn sweep_report(results: &[VariantResult]) -> String {
+    let mut report = String::new();
+    for result in results {
+        match &result.stats {
+            Ok(stats) => {
+                write!(
+                    report,
+                    "{}: {} parts, {} rows, {} stitches, {:.2}m",
+                    result.name,
+                    stats.parts,
+                    stats.rows,
+                    stats.stitches,
+                    stats.yarn.total_length()
+                )
+                .unwrap();
+                if let Some(weight) = stats.yarn.total_weight() {
+                    write!(report, ", {weight:.1}g").unwrap();
+                }
+                writeln!(report).unwrap();
+            }
+            Err(err) => writeln!(report, "{}: {err}", result.name).unwrap(),
+        }
+    }
+    report
+}
+
+fn run(args: Args) -> CliResult<()> {
+    let script = Script::load_file(&args.script)
+        .map_err(|err| format!("Couldn't open {}: {err}", args.script.display()))?;
+
+    let exports = PatternScript::get_script_exports(&script)?;
+    if let Some(name) = args
+        .set
+        .iter()
+        .map(|(name, _)| name)
+        .chain(args.sweep.iter().map(|(name, _)| name))
+        .find(|name| !exports.iter().any(|(export, _)| export == *name))
+    {
+        return Err(format!("The script has no exported parameter \"{name}\".").into());
+    }
+
+    // only read the presets file when it's used, so a broken one doesn't stop other runs
+    let uses_presets = args.preset.is_some() || args.save_preset.is_some() || args.sweep_presets;
+    let mut presets = if uses_presets {
+        Presets::load_for(&args.script)?
+    } else {
+        Presets::default()
+    };
+    let mut parameters = match &args.preset {
+        Some(name) => presets.get(name).cloned().ok_or_else(|| {
+            format!(
+                "There's no preset \"{name}\" in {}.",
+                Presets::path_for(&args.script).display()
+            )
+        })?,
+        None => HashMap::new(),
+    };
+    parameters.extend(args.set);
+    if let Some(name) = &args.save_preset {
+        presets.set(name, parameters.clone());
+        presets.save_for(&args.script)?;
+    }
+
+    if !args.sweep.is_empty() || args.sweep_presets {
+        let has_outputs = args.graphviz.is_some()
+            || args.json.is_some()
+            || !args.mesh.is_empty()
+            || args.written.is_some()
+            || args.report.is_some()
+            || args.yarn.is_some();
+        if has_outputs {
+            return Err(
+                "Outputs can't be written for a sweep. Run once per variant with --set or --preset instead."
+                    .into(),
+            );
+        }
+        let sweep = if args.sweep_presets {
+            Sweep::from(&presets)
+        } else {
+            Sweep::Grid(args.sweep)
+        };
+        let options = YarnOptions {
+            metres_per_100g: args.metres_per_100g,
+            ..Default::default()
+        };
+        let results = PatternScript::sweep(&script, &sweep, &parameters, &options);
+        write_text(Path::new("-"), &sweep_report(&results))?;
+
+        let failed = results.iter().filter(|r| r.stats.is_err()).count();
+        if failed > 0 {
+            return Err(format!("{failed} of {} variants failed.", results.len()).into());
+        }
+        return Ok(());
+    }
+
+    let deadline = args
+        .timeout
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+    let in_time = move || deadline.is_none_or(|deadline| Instant::now() < deadline);
+
+    let pattern =
+        PatternScript::eval_script_with_progress(&script, &parameters, move |_| in_time())
+            .map_err(|err| {
+                if in_time() {
+                    err
+                } else {
+                    "Evaluation timed out.".into()
+                }
+            })?;
+
+    if let Some(path) = &args.graphviz {
+        write_text(path, &pattern.to_graphviz())?;
+    }
+    if let Some(path) = &args.json {
+        write_text(path, &pattern.to_json())?;
+    }
+    if let Some(path) = &args.written {
+        let terminology = if args.uk {
+            Terminology::Uk
+        } else {
+            Terminology::Us
+        };
+        write_text(path, &pattern.to_written(terminology))?;
+    }
+    if let Some(path) = &args.report {
+        write_text(path, &stitch_report(&pattern))?;
+    }
+    if let Some(path) = &args.yarn {
+        let options = YarnOptions {
+            metres_per_100g: args.metres_per_100g,
+            ..Default::default()
+        };
+        write_text(path, &yarn_report(&pattern, &options))?;
+    }
+
+    if !args.mesh.is_empty() {
+        // Check every path before doing the layout, since it can take a while.
+        if let Some(path) = args
+            .mesh
+            .iter()
+            .find(|p| MeshFormat::from_path(p).is_none())
+        {
+            return Err(format!("Unknown mesh format for {}", path.display()).into());
+        }
+        let defaults = LayoutOptions::default();
+        let options = LayoutOptions {
+            seed: args.seed,
+            mode: args
+                .pivots
+                .map_or(SgdMode::Exact, |pivots| SgdMode::Sparse { pivots }),
+            iterations: args.iterations.unwrap_or(defaults.iterations),
+            fdg_iterations: args.fdg_iterations.unwrap_or(defaults.fdg_iterations),
+            hogwild: args.hogwild,
+            pressure: args.stuffing,
+            repulsion: args.repulsion,
+            ..defaults
+        };
+        let progress = |_| in_time();
+        let positions = if args.flat {
+            layout_pattern_2d(&pattern, &options, &[], &progress)
+        } else {
+            layout_pattern(&pattern, &options, &[], &progress)
+        }
+        .map_err(|_| "Layout timed out.")?;
+        let mesh = mesh_from_layout(&pattern, &positions);
+        for path in &args.mesh {
+            mesh.save(path)
+                .map_err(|err| format!("Couldn't write {}: {err}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_set() {
+        let (name, value) = parse_set("rows=20").unwrap();
+        assert_eq!(name, "rows");
+        assert_eq!(value.as_int().unwrap(), 20);
+
+        let (_, value) = parse_set("size = 1.5").unwrap();
+        assert_eq!(value.as_float().unwrap(), 1.5);
+
+        let (_, value) = parse_set("color=red").unwrap();
+        assert_eq!(value.into_string().unwrap(), "red");
+
+        assert!(parse_set("rows").is_err());
+        assert!(parse_set("=5").is_err());
+    }
+
+    #[test]
+    fn test_parse_sweep() {
+        let (name, values) = parse_sweep("rows=10, 20,30").unwrap();
+        assert_eq!(name, "rows");
+        let values = values
+            .iter()
+            .map(|v| v.as_int().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![10, 20, 30]);
+
+        let (_, values) = parse_sweep("size=S,M,L").unwrap();
+        let values = values
+            .into_iter()
+            .map(|v| v.into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["S", "M", "L"]);
+
+        assert!(parse_sweep("rows=").is_err());
+        assert!(parse_sweep("rows=10,abc").is_err());
+        let (_, values) = parse_sweep(r#"size=10,"abc""#).unwrap();
+        assert_eq!(values[0].as_int(), Ok(10));
+    }
+
+    #[test]
+    fn test_broken_presets() {
+        let dir = std::env::temp_dir().join(format!("polyhook-cli-presets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("flat.ph");
+        std::fs::write(&script, "rep 4 chain();\nturn();\nrep 3 sc();\n").unwrap();
+        std::fs::write(Presets::path_for(&script), "not json").unwrap();
+        let args = |extra: &[&str]| {
+            let written = dir.join("flat.txt");
+            let mut args = vec!["polyhook-cli", script.to_str().unwrap()];
+            args.extend_from_slice(&["--written", written.to_str().unwrap()]);
+            args.extend_from_slice(extra);
+            Args::parse_from(args)
+        };
+
+        // the presets file is only read when presets are used
+        let plain = run(args(&[]));
+        let with_preset = run(args(&["--preset", "Adult"]));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(plain.is_ok());
+        assert!(with_preset.is_err());
+    }
+
+    #[test]
+    fn test_stitch_report() {
+        let pattern = hooklib::pattern::test_pattern_flat(3).unwrap();
+        let report = stitch_report(&pattern);
+        assert!(report.contains("Part 1\n"));
+        assert!(report.contains("  Row 1: 3\n"));
+        assert!(report.contains("  dc: 9\n"));
+        assert!(report.contains(&format!("  all: {}\n", pattern.graph().node_count())));
+    }
+
+    #[test]
+    fn test_yarn_report() {
+        let pattern = hooklib::pattern::test_pattern_flat(3).unwrap();
+        let options = YarnOptions {
+            metres_per_100g: Some(100.0),
+            ..Default::default()
+        };
+        let report = yarn_report(&pattern, &options);
+        assert!(report.starts_with("#ffffff: "));
+        assert!(report.contains("9 dc"));
+        assert!(report.contains("Total: "));
+        assert!(report.contains("g\n"));
+        assert!(!report.contains("Colour changes"));
+    }
+}
@@ -1,25 +1,138 @@
-use egui::{Color32, TextStyle};
+use egui::text::{CCursor, CCursorRange, LayoutJob};
+use egui::{Align, Color32, Id, Stroke, TextStyle};
 use egui_extras::syntax_highlighting::{highlight, CodeTheme};
-use hooklib::{examples, script::Script};
+use hooklib::examples;
+use hooklib::script::{Diagnostic, PatternScript, Script, Severity};
+
+/// A line of code marked by a diagnostic, and the column the problem starts at.
+struct Mark {
+    line: usize,
+    column: Option<usize>,
+    severity: Severity,
+}
+
+fn severity_color(severity: Severity) -> Color32 {
+    match severity {
+        Severity::Error => Color32::RED,
+        Severity::Warning => Color32::YELLOW,
+    }
+}
+
+/// Underline the code marked by diagnostics, from the column of the problem to the end of its line.
+fn underline_marks(job: &mut LayoutJob, text: &str, marks: &[Mark]) {
+    let mut line_start = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        for mark in marks.iter().filter(|mark| mark.line == i + 1) {
+            let column = mark.column.unwrap_or(1).saturating_sub(1);
+            let start = line_start
+                + line
+                    .char_indices()
+                    .nth(column)
+                    .map_or(line.len(), |(byte, _)| byte);
+            let end = line_start + line.trim_end().len();
+            for section in &mut job.sections {
+                if section.byte_range.start < end && section.byte_range.end > start {
+                    section.format.underline = Stroke::new(1.5_f32, severity_color(mark.severity));
+                }
+            }
+        }
+        line_start += line.len();
+    }
+}
 
 pub struct CodeView {
     pub code: Script,
+    /// The line the text cursor is on, starting from 1.
+    cursor_line: Option<usize>,
+    /// A line to move the cursor to and scroll into view the next time the code is shown.
+    goto_line: Option<usize>,
+    /// Problems found in the code as it's written, without evaluating it.
+    diagnostics: Vec<Diagnostic>,
+    /// Whether the code has changed since it was last checked for problems.
+    needs_check: bool,
 }
 
 impl Default for CodeView {
     fn default() -> Self {
-        Self {
-            code: examples::EXAMPLE_FLAT.into(),
-        }
+        Self::new(examples::EXAMPLE_FLAT.into())
     }
 }
 
 impl CodeView {
+    pub fn new(code: Script) -> Self {
+        Self {
+            code,
+            cursor_line: None,
+            goto_line: None,
+            diagnostics: vec![],
+            needs_check: true,
+        }
+    }
+
     pub fn load_code(&mut self, code: Script) {
         self.code = code;
+        self.cursor_line = None;
+        self.needs_check = true;
+    }
+
+    /// Replace the code, keeping the file it's from, e.g. after it's been edited in the visual view.
+    pub fn set_source(&mut self, source: String) {
+        *self.code.source_mut() = source;
+        self.needs_check = true;
+    }
+
+    /// The line the text cursor is on, starting from 1.
+    pub fn cursor_line(&self) -> Option<usize> {
+        self.cursor_line
+    }
+
+    /// Move the cursor to the start of a line, starting from 1, and scroll to it.
+    pub fn goto_line(&mut self, line: usize) {
+        self.goto_line = Some(line);
+    }
+
+    /// The lines of this file marked by the given diagnostics, either where the problem is or where it was called from.
+    fn marks<'a>(&self, diagnostics: impl Iterator<Item = &'a Diagnostic>) -> Vec<Mark> {
+        let file = self.code.path().and_then(|path| path.to_str());
+        diagnostics
+            .flat_map(|diagnostic| {
+                let frames = diagnostic
+                    .call_stack
+                    .iter()
+                    .map(|frame| (frame.file.as_deref(), frame.line, frame.column));
+                std::iter::once((
+                    diagnostic.file.as_deref(),
+                    diagnostic.line,
+                    diagnostic.column,
+                ))
+                .chain(frames)
+                .filter(|(frame_file, _, _)| *frame_file == file)
+                .filter_map(|(_, line, column)| {
+                    Some(Mark {
+                        line: line?,
+                        column,
+                        severity: diagnostic.severity,
+                    })
+                })
+                .collect::<Vec<_>>()
+            })
+            .collect()
     }
 
-    pub fn code_view_show(&mut self, ui: &mut egui::Ui) {
+    /// Show the code editor, marking problems found as the code is written and the given problem from evaluating it.
+    pub fn code_view_show(&mut self, ui: &mut egui::Ui, eval_diagnostic: Option<&Diagnostic>) {
+        if self.needs_check {
+            self.diagnostics = PatternScript::check_script(&self.code);
+            self.needs_check = false;
+        }
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .chain(eval_diagnostic)
+            .cloned()
+            .collect::<Vec<_>>();
+        let marks = self.marks(diagnostics.iter());
+
         egui::Frame::default()
             .fill(ui.visuals().extreme_bg_color)
             .stroke(ui.visuals().window_stroke)
@@ -32,7 +145,29 @@ impl CodeView {
                     .and_then(|name| name.to_str())
                     .unwrap_or("<unsaved file>");
                 ui.label(file_label);
+                let editor_id = Id::new("code_view_editor");
+                let goto = self.goto_line.take().map(|line| {
+                    let ccursor = CCursor::new(
+                        self.code
+                            .source()
+                            .split_inclusive('\n')
+                            .take(line.saturating_sub(1))
+                            .map(|line| line.chars().count())
+                            .sum(),
+                    );
+                    let mut state =
+                        egui::TextEdit::load_state(ui.ctx(), editor_id).unwrap_or_default();
+                    state
+                        .cursor
+                        .set_char_range(Some(CCursorRange::one(ccursor)));
+                    state.store(ui.ctx(), editor_id);
+                    ui.ctx()
+                        .memory_mut(|memory| memory.request_focus(editor_id));
+                    ccursor
+                });
                 let code = self.code.source_mut();
+                let mut cursor_line = self.cursor_line;
+                let mut changed = false;
                 egui::ScrollArea::vertical()
                     .max_height(ui.available_height() - 100.0)
                     .show(ui, |ui| {
@@ -56,15 +191,27 @@ impl CodeView {
                         ui.horizontal_top(|ui| {
                             let mut linenums_layouter =
                                 |ui: &egui::Ui, string: &str, _wrap_width: f32| {
-                                    let mut layout_job = egui::text::LayoutJob::single_section(
-                                        string.to_string(),
-                                        egui::TextFormat::simple(
-                                            egui::FontId::monospace(
-                                                TextStyle::Monospace.resolve(ui.style()).size,
-                                            ),
-                                            Color32::LIGHT_GRAY,
-                                        ),
+                                    let font_id = egui::FontId::monospace(
+                                        TextStyle::Monospace.resolve(ui.style()).size,
                                     );
+                                    let mut layout_job = LayoutJob::default();
+                                    for (i, line) in string.split_inclusive('\n').enumerate() {
+                                        // mark lines with problems in the gutter, errors over warnings
+                                        let severity = marks
+                                            .iter()
+                                            .filter(|mark| mark.line == i + 1)
+                                            .map(|mark| mark.severity)
+                                            .min_by_key(|severity| *severity != Severity::Error);
+                                        let mut format = egui::TextFormat::simple(
+                                            font_id.clone(),
+                                            Color32::LIGHT_GRAY,
+                                        );
+                                        if let Some(severity) = severity {
+                                            format.color = Color32::BLACK;
+                                            format.background = severity_color(severity);
+                                        }
+                                        layout_job.append(line, 0.0, format);
+                                    }
                                     layout_job.wrap.max_width = f32::INFINITY;
                                     ui.fonts(|f| f.layout_job(layout_job))
                                 };
@@ -91,22 +238,57 @@ impl CodeView {
                                     string,
                                     "rs",
                                 );
+                                underline_marks(&mut layout_job, string, &marks);
                                 layout_job.wrap.max_width = wrap_width;
 
                                 ui.fonts(|f| f.layout_job(layout_job))
                             };
 
-                            ui.add_sized(
-                                ui.available_size(),
-                                egui::TextEdit::multiline(code)
-                                    .font(TextStyle::Monospace)
-                                    .frame(false)
-                                    .lock_focus(true)
-                                    .layouter(&mut layouter)
-                                    .hint_text("Type your code here..."),
-                            );
+                            let output = egui::TextEdit::multiline(code)
+                                .id(editor_id)
+                                .font(TextStyle::Monospace)
+                                .frame(false)
+                                .lock_focus(true)
+                                .desired_width(ui.available_width())
+                                .min_size(ui.available_size())
+                                .layouter(&mut layouter)
+                                .hint_text("Type your code here...")
+                                .show(ui);
+
+                            changed = output.response.changed();
+                            if let Some(range) = output.cursor_range {
+                                cursor_line = Some(range.primary.pcursor.paragraph + 1);
+                            }
+                            if let Some(ccursor) = goto {
+                                let cursor = output.galley.from_ccursor(ccursor);
+                                let rect = output
+                                    .galley
+                                    .pos_from_cursor(&cursor)
+                                    .translate(output.galley_pos.to_vec2());
+                                ui.scroll_to_rect(rect, Some(Align::Center));
+                            }
                         })
                     });
+                self.cursor_line = cursor_line;
+                self.needs_check |= changed;
+
+                for diagnostic in &diagnostics {
+                    let label = ui.add(
+                        egui::Label::new(
+                            egui::RichText::new(diagnostic.to_string())
+                                .color(severity_color(diagnostic.severity)),
+                        )
+                        .wrap()
+                        .sense(egui::Sense::click()),
+                    );
+                    let line = self
+                        .marks(std::iter::once(diagnostic))
+                        .first()
+                        .map(|mark| mark.line);
+                    if let Some(line) = line.filter(|_| label.clicked()) {
+                        self.goto_line(line);
+                    }
+                }
             });
     }
 }
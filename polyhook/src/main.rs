@@ -5,12 +5,16 @@ mod parameter_view;
 mod visual_view;
 
 use egui::{Color32, Ui, Vec2};
+use glam::Vec3;
 use hooklib::examples;
-use hooklib::script::{PatternScript, Script};
+use hooklib::pattern::{Pattern, SourceLocation};
+use hooklib::script::{Diagnostic, PatternScript, Script};
+use hookmesh::{layout_pattern, layout_pattern_2d, warm_start, LayoutOptions};
 use parameter_view::ParameterView;
+use petgraph::graph::NodeIndex;
 use render::model::ModelData;
-use render::pattern_model::{model_from_pattern, model_from_pattern_2d};
-use render::transform::Orbit;
+use render::pattern_model::{model_from_layout, view_scale};
+use render::transform::{Mvp, Orbit};
 use rfd::FileDialog;
 use rhai::{Dynamic, ImmutableString};
 use std::collections::HashMap;
@@ -18,7 +22,8 @@ use std::env::args;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use visual_view::VisualView;
 
 use std::{
@@ -26,41 +31,106 @@ use std::{
     thread::{spawn, JoinHandle},
 };
 
+/// The pattern and layout from the last render, so the next render can start from it.
+struct LastRender {
+    pattern: Pattern,
+    positions: Vec<Vec3>,
+    is_2d_mode: bool,
+}
+
+type RenderResult = Result<(ModelData, LastRender), Box<dyn Error + Send + Sync>>;
+
+/// Shared between the GUI and the render thread, to show how far the render has got and to cancel it.
+#[derive(Default)]
+struct RenderProgress {
+    cancelled: AtomicBool,
+    /// The current stage, and how far through it the render is if that's known.
+    status: Mutex<(&'static str, Option<f32>)>,
+}
+
+impl RenderProgress {
+    /// Record progress through a stage, returning `false` if the render should stop.
+    fn update(&self, stage: &'static str, fraction: Option<f32>) -> bool {
+        *self.status.lock().unwrap() = (stage, fraction);
+        !self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Default)]
 struct RenderButton {
     err: Option<Box<dyn Error + Send + Sync>>,
-    thread: Option<JoinHandle<Result<ModelData, Box<dyn Error + Send + Sync>>>>,
+    thread: Option<JoinHandle<RenderResult>>,
+    progress: Arc<RenderProgress>,
     is_2d_mode: bool,
+    /// How firmly closed parts are stuffed, unless the script sets it.
+    stuffing: f32,
+    /// Whether to push apart stitches that aren't connected, so parts don't pass through each other.
+    avoid_intersections: bool,
+    last_render: Option<Arc<LastRender>>,
 }
 
 impl RenderButton {
     fn start_render(&mut self, code: Script, parameters: HashMap<ImmutableString, Dynamic>) {
         let is_2d_mode = self.is_2d_mode;
+        let options = LayoutOptions {
+            pressure: self.stuffing,
+            repulsion: if self.avoid_intersections { 1.0 } else { 0.0 },
+            ..Default::default()
+        };
+        let previous = self
+            .last_render
+            .clone()
+            .filter(|last| last.is_2d_mode == is_2d_mode);
+        let progress = Arc::new(RenderProgress::default());
+        self.progress = progress.clone();
         self.thread = Some(spawn(move || {
-            let pattern =
-                hooklib::script::PatternScript::eval_script_with_exports(&code, &parameters);
-            match pattern {
-                Ok(pattern) => {
-                    if is_2d_mode {
-                        Ok(model_from_pattern_2d(&pattern))
-                    } else {
-                        Ok(model_from_pattern(&pattern))
-                    }
-                }
-                Err(err) => Err(err),
-            }
+            let eval_progress = progress.clone();
+            let pattern = hooklib::script::PatternScript::eval_script_with_progress(
+                &code,
+                &parameters,
+                move |_| eval_progress.update("Evaluating", None),
+            )?;
+
+            // carry over the layout of stitches that haven't changed, so small edits are quicker and stay in place
+            let initial = previous
+                .map(|last| warm_start(&pattern, &last.pattern, &last.positions))
+                .unwrap_or_default();
+            let layout_progress = |fraction| progress.update("Laying out", Some(fraction));
+            let positions = if is_2d_mode {
+                layout_pattern_2d(&pattern, &options, &initial, &layout_progress)?
+            } else {
+                layout_pattern(&pattern, &options, &initial, &layout_progress)?
+            };
+
+            let model = model_from_layout(&pattern, &positions, &[]);
+            Ok((
+                model,
+                LastRender {
+                    pattern,
+                    positions,
+                    is_2d_mode,
+                },
+            ))
         }));
     }
 
+    /// The problem found in the script by the last render, if it failed evaluating it.
+    fn diagnostic(&self) -> Option<&Diagnostic> {
+        self.err.as_ref()?.downcast_ref()
+    }
+
     fn check_render(&mut self) -> Option<Result<ModelData, Box<dyn Error + Send + Sync>>> {
         if self.thread.as_ref().is_some_and(|t| t.is_finished()) {
-            Some(
-                self.thread
-                    .take()
-                    .unwrap()
-                    .join()
-                    .expect("Failed to join thread."),
-            )
+            let result = self
+                .thread
+                .take()
+                .unwrap()
+                .join()
+                .expect("Failed to join thread.");
+            Some(result.map(|(model, last_render)| {
+                self.last_render = Some(Arc::new(last_render));
+                model
+            }))
         } else {
             None
         }
@@ -84,8 +154,31 @@ impl RenderButton {
             });
         }
 
+        if self.thread.as_ref().is_some_and(|t| !t.is_finished()) {
+            let (stage, fraction) = *self.progress.status.lock().unwrap();
+            ui.horizontal(|ui| {
+                if ui.button("Cancel").clicked() {
+                    self.progress.cancelled.store(true, Ordering::Relaxed);
+                }
+                let bar = match fraction {
+                    Some(fraction) => egui::ProgressBar::new(fraction)
+                        .text(format!("{stage} ({:.0}%)", fraction * 100.0)),
+                    None => egui::ProgressBar::new(0.0).animate(true).text(stage),
+                };
+                ui.add(bar);
+            });
+        }
+
         ui.add_enabled_ui(self.thread.as_ref().is_none_or(|t| t.is_finished()), |ui| {
             ui.checkbox(&mut self.is_2d_mode, "2D Mode");
+            ui.add_enabled(
+                !self.is_2d_mode,
+                egui::Slider::new(&mut self.stuffing, 0.0..=2.0).text("Stuffing"),
+            );
+            ui.add_enabled(
+                !self.is_2d_mode,
+                egui::Checkbox::new(&mut self.avoid_intersections, "Avoid intersections"),
+            );
             let button = ui.add_sized(ui.available_size(), egui::Button::new("Render"));
             if button.clicked() {
                 self.err = None;
@@ -120,6 +213,137 @@ struct App {
     render_button: RenderButton,
     orbit: Orbit,
     tab: AppTab,
+    /// The code line whose stitches are highlighted in the model.
+    highlighted_line: Option<usize>,
+    /// A description of the stitch last clicked in the model.
+    picked: Option<String>,
+}
+
+/// How far from a stitch, in points, a click can be and still pick it.
+const PICK_RADIUS: f32 = 12.0;
+
+/// Find the stitch drawn nearest to a point on screen, if there's one close enough to it.
+fn pick_node(
+    last: &LastRender,
+    mvp: &Mvp,
+    rect: egui::Rect,
+    pointer: egui::Pos2,
+) -> Option<NodeIndex> {
+    let scale = view_scale(&last.pattern);
+    let matrix = mvp.projection * mvp.view * mvp.model;
+    last.positions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, position)| {
+            let clip = matrix * (*position * scale).extend(1.0);
+            if clip.w <= 0.0 {
+                return None;
+            }
+            let ndc = clip.truncate() / clip.w;
+            let screen = egui::pos2(
+                rect.left() + (ndc.x + 1.0) * 0.5 * rect.width(),
+                rect.top() + (1.0 - ndc.y) * 0.5 * rect.height(),
+            );
+            let distance = screen.distance(pointer);
+            (distance < PICK_RADIUS).then_some((i, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| NodeIndex::new(i))
+}
+
+impl App {
+    /// The location of a line in the code view, as recorded in the origins of the nodes it makes.
+    fn code_location(&self, line: usize) -> SourceLocation {
+        SourceLocation {
+            file: self
+                .code_view
+                .code
+                .path()
+                .and_then(|path| path.to_str())
+                .map(String::from),
+            line,
+        }
+    }
+
+    /// Highlight the stitches made by the line the code view's cursor is on.
+    fn highlight_cursor_line(&mut self) {
+        let line = self
+            .code_view
+            .cursor_line()
+            .filter(|_| self.tab == AppTab::Code);
+        if line == self.highlighted_line {
+            return;
+        }
+        self.highlighted_line = line;
+        if let Some(last) = &self.render_button.last_render {
+            let highlighted = line
+                .map(|line| last.pattern.nodes_at(&self.code_location(line)))
+                .unwrap_or_default();
+            self.renderer.set_model(model_from_layout(
+                &last.pattern,
+                &last.positions,
+                &highlighted,
+            ));
+        }
+    }
+
+    /// Carry edits made in the visual view over to the code.
+    fn apply_visual_edits(&mut self) {
+        if self.tab == AppTab::Visual {
+            if let Some(code) = self.visual_view.modified_code() {
+                self.code_view.set_source(code);
+                self.visual_view.load_script(self.code_view.code.source());
+            }
+        }
+    }
+
+    /// Show another tab, carrying edits made in the visual view over to the code.
+    fn switch_tab(&mut self, tab: AppTab) {
+        if tab != AppTab::Visual {
+            self.apply_visual_edits();
+        }
+        match tab {
+            AppTab::Visual if self.tab != AppTab::Visual => {
+                self.visual_view.load_script(self.code_view.code.source())
+            }
+            AppTab::Parameters => self.parameter_view.refresh_parameters(&self.code_view.code),
+            _ => {}
+        }
+        self.tab = tab;
+    }
+
+    /// Load a script into the code view, and into the visual view if that's showing.
+    fn load_code(&mut self, script: Script) {
+        self.code_view.load_code(script);
+        if self.tab == AppTab::Visual {
+            self.visual_view.load_script(self.code_view.code.source());
+        }
+    }
+
+    /// Jump to the line of code that made the stitch under the pointer.
+    fn pick(&mut self, rect: egui::Rect, pointer: egui::Pos2) {
+        let Some(last) = self.render_button.last_render.clone() else {
+            return;
+        };
+        let Some(node) = pick_node(&last, &self.renderer.mvp, rect, pointer) else {
+            self.picked = None;
+            return;
+        };
+        let ty = last.pattern.graph()[node].stitch_type();
+        let Some(origin) = last.pattern.origin(node) else {
+            self.picked = Some(ty.to_string());
+            return;
+        };
+        let mut description = format!("{ty} in part {}, row {}", origin.part + 1, origin.row);
+        if let Some(location) = origin.location {
+            description += &format!(", line {}", location.line);
+            if location == self.code_location(location.line) {
+                self.code_view.goto_line(location.line);
+                self.switch_tab(AppTab::Code);
+            }
+        }
+        self.picked = Some(description);
+    }
 }
 
 impl App {
@@ -153,7 +377,7 @@ impl App {
             .unwrap_or((Script::new(""), None));
 
         Self {
-            code_view: code_view::CodeView { code },
+            code_view: code_view::CodeView::new(code),
             visual_view: Default::default(),
             parameter_view: Default::default(),
             renderer: render::Renderer::new(
@@ -168,6 +392,8 @@ impl App {
                 d: 3.0,
             },
             tab: AppTab::Code,
+            highlighted_line: None,
+            picked: None,
         }
     }
 }
@@ -179,7 +405,7 @@ impl eframe::App for App {
                 ui.menu_button("File", |ui| {
                     if ui.button("New").clicked() {
                         let script = Script::new("");
-                        self.code_view.load_code(script);
+                        self.load_code(script);
                         ui.close_menu();
                     }
                     if ui.button("Open").clicked() {
@@ -193,11 +419,12 @@ impl eframe::App for App {
                                 .ok()
                         });
                         if let Some(script) = script {
-                            self.code_view.load_code(script)
+                            self.load_code(script)
                         }
                         ui.close_menu();
                     }
                     if ui.button("Save").clicked() {
+                        self.apply_visual_edits();
                         let file = self
                             .code_view
                             .code
@@ -217,6 +444,7 @@ impl eframe::App for App {
                         ui.close_menu();
                     }
                     if ui.button("Save As").clicked() {
+                        self.apply_visual_edits();
                         let file = FileDialog::new()
                             .add_filter("polyhook", &["ph"])
                             .set_directory(".")
@@ -246,7 +474,7 @@ impl eframe::App for App {
                                 .inspect_err(|err| eprintln!("Couldn't load file: {err}"))
                                 .ok();
                             if let Some(script) = script {
-                                self.code_view.load_code(script)
+                                self.load_code(script)
                             }
                             ui.close_menu();
                         }
@@ -263,25 +491,25 @@ impl eframe::App for App {
                             .selectable_label(self.tab == AppTab::Code, "Code View")
                             .clicked()
                         {
-                            self.tab = AppTab::Code;
+                            self.switch_tab(AppTab::Code);
                         }
                         if ui
                             .selectable_label(self.tab == AppTab::Parameters, "Parameters")
                             .clicked()
                         {
-                            self.parameter_view.refresh_parameters(&self.code_view.code);
-                            self.tab = AppTab::Parameters;
+                            self.switch_tab(AppTab::Parameters);
                         }
                         if ui
                             .selectable_label(self.tab == AppTab::Visual, "Visual View")
                             .clicked()
                         {
-                            self.tab = AppTab::Visual;
+                            self.switch_tab(AppTab::Visual);
                         }
                     });
 
                     if self.tab == AppTab::Code {
-                        self.code_view.code_view_show(ui);
+                        let diagnostic = self.render_button.diagnostic().cloned();
+                        self.code_view.code_view_show(ui, diagnostic.as_ref());
                     } else if self.tab == AppTab::Visual {
                         ui.add(&mut self.visual_view);
                     } else if self.tab == AppTab::Parameters {
@@ -292,15 +520,22 @@ impl eframe::App for App {
                         if self.tab == AppTab::Code || self.tab == AppTab::Parameters {
                             (
                                 self.code_view.code.clone(),
-                                self.parameter_view.parameters.clone(),
+                                self.parameter_view.changed_parameters(),
                             )
                         } else {
-                            (self.visual_view.get_code().into(), Default::default())
+                            let code = self.visual_view.get_code();
+                            let script = match self.code_view.code.path() {
+                                Some(path) => Script::new_with_path(code, path),
+                                None => Script::new(code),
+                            };
+                            (script, Default::default())
                         }
                     });
                     if let Some(new_model) = new_model {
                         // TODO: switch the model to the new one
                         self.renderer.set_model(new_model);
+                        self.highlighted_line = None;
+                        self.picked = None;
                     }
                 });
 
@@ -311,11 +546,17 @@ impl eframe::App for App {
                 let response = ui.allocate_rect(
                     rect,
                     egui::Sense {
-                        click: false,
+                        click: true,
                         drag: true,
                         focusable: false,
                     },
                 );
+                if let Some(pointer) = response
+                    .interact_pointer_pos()
+                    .filter(|_| response.clicked())
+                {
+                    self.pick(rect, pointer);
+                }
                 ui.painter()
                     .add(eframe::egui_wgpu::Callback::new_paint_callback(
                         rect,
@@ -326,8 +567,19 @@ impl eframe::App for App {
                 let z = ctx.input(|input| input.smooth_scroll_delta.y);
                 self.orbit.update(glam::vec3(drag.x, drag.y, z));
                 self.renderer.mvp.view = self.orbit.matrix();
+
+                if let Some(picked) = &self.picked {
+                    ui.painter().text(
+                        rect.left_top() + Vec2::splat(8.0),
+                        egui::Align2::LEFT_TOP,
+                        picked,
+                        egui::FontId::proportional(14.0),
+                        ui.visuals().text_color(),
+                    );
+                }
             });
         });
+        self.highlight_cursor_line();
 
         // self.renderer.mvp.update_model(glam::Mat4::from_rotation_y(0.02) * self.renderer.mvp.model);
         ctx.request_repaint();
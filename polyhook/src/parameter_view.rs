@@ -1,18 +1,119 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::thread::{spawn, JoinHandle};
 
 use egui::{Layout, Widget};
 use egui_extras::{Column, TableBuilder};
-use hooklib::script::{PatternScript, Script};
+use hooklib::presets::Presets;
+use hooklib::script::{Parameter, ParameterKind, PatternScript, Script, Sweep, VariantResult};
 use rhai::{Dynamic, ImmutableString};
 
 #[derive(Default)]
 pub struct ParameterView {
     pub parameters: HashMap<ImmutableString, Dynamic>,
+    /// The script's exported parameters, in the order they're declared.
+    specs: Vec<Parameter>,
+    /// The parameters the user has changed from their defaults.
+    changed: HashSet<ImmutableString>,
+    /// The script the parameters are from, to work out derived parameters from the others.
+    script: Option<Script>,
+    /// The presets saved next to the script.
+    presets: Presets,
+    /// The name of the preset to save or delete.
+    preset_name: String,
+    /// The last problem loading or saving presets.
+    presets_error: Option<String>,
+    /// The sweep over all presets that's running, to compare them.
+    sweep: Option<JoinHandle<Vec<VariantResult>>>,
+    /// The results of the last sweep over all presets.
+    sweep_results: Vec<VariantResult>,
+}
+
+/// Show the widget for editing a parameter's value, limited to the values it allows.
+/// Derived parameters are shown, but can't be changed.
+fn parameter_widget(ui: &mut egui::Ui, spec: &Parameter, value: &mut Dynamic) -> egui::Response {
+    if spec.derived {
+        return ui
+            .weak(value.to_string())
+            .on_hover_text("Worked out from other parameters");
+    }
+    match &spec.kind {
+        ParameterKind::Int { min, max, step } => {
+            let mut v = value.as_int().unwrap_or_default();
+            let response = match (min, max) {
+                (Some(min), Some(max)) => {
+                    ui.add(egui::Slider::new(&mut v, *min..=*max).step_by(step.unwrap_or(1) as f64))
+                }
+                _ => ui.add(
+                    egui::DragValue::new(&mut v)
+                        .range(min.unwrap_or(i64::MIN)..=max.unwrap_or(i64::MAX))
+                        .speed(step.unwrap_or(1) as f64),
+                ),
+            };
+            *value = v.into();
+            response
+        }
+        ParameterKind::Float { min, max, step } => {
+            let mut v = value.as_float().unwrap_or_default();
+            let response = match (min, max) {
+                (Some(min), Some(max)) => {
+                    ui.add(egui::Slider::new(&mut v, *min..=*max).step_by(step.unwrap_or(0.0)))
+                }
+                _ => ui.add(
+                    egui::DragValue::new(&mut v)
+                        .range(min.unwrap_or(f64::NEG_INFINITY)..=max.unwrap_or(f64::INFINITY))
+                        .speed(step.unwrap_or(0.1)),
+                ),
+            };
+            *value = v.into();
+            response
+        }
+        ParameterKind::Bool => {
+            let mut v = value.as_bool().unwrap_or_default();
+            let response = ui.checkbox(&mut v, "");
+            *value = v.into();
+            response
+        }
+        ParameterKind::Choice(choices) => {
+            let mut v = value.clone().into_immutable_string().unwrap_or_default();
+            let combo = egui::ComboBox::from_id_salt(spec.name.as_str())
+                .selected_text(v.as_str())
+                .show_ui(ui, |ui| {
+                    choices
+                        .iter()
+                        .map(|choice| ui.selectable_value(&mut v, choice.clone(), choice.as_str()))
+                        .reduce(|a, b| a | b)
+                });
+            let mut response = combo.response;
+            if combo.inner.flatten().is_some_and(|inner| inner.changed()) {
+                response.mark_changed();
+            }
+            *value = v.into();
+            response
+        }
+        ParameterKind::Color => {
+            let mut rgb = [0.0f32; 3];
+            if let Some(color) = value.clone().try_cast::<rhai::Array>() {
+                for (c, v) in rgb.iter_mut().zip(color) {
+                    *c = v.as_float().unwrap_or_default() as f32;
+                }
+            }
+            let response = ui.color_edit_button_rgb(&mut rgb);
+            *value = rgb
+                .into_iter()
+                .map(|c| Dynamic::from(c as f64))
+                .collect::<rhai::Array>()
+                .into();
+            response
+        }
+        ParameterKind::Other => ui.label(value.to_string()),
+    }
 }
 
 impl Widget for &mut ParameterView {
     fn ui(self, ui: &mut egui::Ui) -> egui::Response {
-        egui::ScrollArea::vertical()
+        self.presets_show(ui);
+        let mut edited = false;
+        let response = egui::ScrollArea::vertical()
             .max_height(ui.available_height() - 100.0)
             .show(ui, |ui| {
                 egui::Frame::group(ui.style())
@@ -35,7 +136,13 @@ impl Widget for &mut ParameterView {
                                 });
                             })
                             .body(|mut body| {
-                                self.parameters.iter_mut().for_each(|(name, value)| {
+                                for spec in &self.specs {
+                                    let changed = &mut self.changed;
+                                    let edited = &mut edited;
+                                    let Some(value) = self.parameters.get_mut(&spec.name) else {
+                                        continue;
+                                    };
+                                    let name = &spec.name;
                                     body.row(30.0, |mut row| {
                                         row.col(|ui| {
                                             ui.with_layout(
@@ -46,43 +153,239 @@ impl Widget for &mut ParameterView {
                                         row.col(|ui| {
                                             ui.with_layout(
                                                 Layout::top_down(egui::Align::Center),
-                                                |ui| match value.type_name() {
-                                                    "i64" => {
-                                                        let mut v = value.as_int().unwrap();
-                                                        ui.add(egui::DragValue::new(&mut v));
-                                                        *value = v.into();
+                                                |ui| {
+                                                    let response =
+                                                        parameter_widget(ui, spec, value);
+                                                    if response.changed() {
+                                                        changed.insert(spec.name.clone());
+                                                        *edited = true;
                                                     }
-                                                    "f64" => {
-                                                        let mut v = value.as_float().unwrap();
-                                                        ui.add(egui::DragValue::new(&mut v));
-                                                        *value = v.into();
+                                                    if let Some(description) = &spec.description {
+                                                        response.on_hover_text(description);
                                                     }
-                                                    _ => (),
                                                 },
                                             );
                                         });
                                     });
-                                });
+                                }
                             });
                         ui.allocate_space(ui.available_size());
                     })
                     .response
             })
-            .inner
+            .inner;
+        if edited {
+            self.update_derived();
+        }
+        response
     }
 }
 
 impl ParameterView {
+    /// Update the parameters to those exported by the script, keeping the values the user has changed
+    /// if they're still allowed. The parameters are left as they are if the script has errors.
     pub fn refresh_parameters(&mut self, script: &Script) {
-        let exports =
-            PatternScript::get_script_exports(script).expect("Couldn't get script exports");
+        let Ok(specs) = PatternScript::get_script_parameters(script) else {
+            return;
+        };
+
+        let parameters = &self.parameters;
+        self.changed.retain(|name| {
+            specs.iter().any(|spec| {
+                spec.name == *name
+                    && !spec.derived
+                    && parameters
+                        .get(name)
+                        .is_some_and(|value| spec.coerce(value.clone()).is_ok())
+            })
+        });
+        let changed = self.changed_parameters();
+        self.parameters = specs
+            .iter()
+            .map(|spec| {
+                let value = changed.get(&spec.name).unwrap_or(&spec.default);
+                (spec.name.clone(), value.clone())
+            })
+            .collect();
+        self.specs = specs;
+        self.script = Some(script.clone());
+        self.update_derived();
+
+        let presets = script.path().map(Presets::load_for).transpose();
+        self.presets_error = presets.as_ref().err().map(|err| err.to_string());
+        self.presets = presets.ok().flatten().unwrap_or_default();
+    }
+
+    /// Use the values of a preset, leaving parameters it doesn't set at their defaults.
+    fn apply_preset(&mut self, name: &str) {
+        let Some(preset) = self.presets.get(name) else {
+            return;
+        };
+        self.changed.clear();
+        for spec in &self.specs {
+            let value = preset
+                .get(&spec.name)
+                .filter(|_| !spec.derived)
+                .and_then(|value| spec.coerce(value.clone()).ok());
+            if value.is_some() {
+                self.changed.insert(spec.name.clone());
+            }
+            self.parameters
+                .insert(spec.name.clone(), value.unwrap_or(spec.default.clone()));
+        }
+        self.preset_name = name.to_string();
+        self.update_derived();
+    }
+
+    /// Save the parameters the user has changed as a preset next to the script.
+    fn save_preset(&mut self) {
+        let Some(path) = self.script.as_ref().and_then(|script| script.path()) else {
+            return;
+        };
+        self.presets
+            .set(&self.preset_name, self.changed_parameters());
+        self.presets_error = self.presets.save_for(path).err().map(|err| err.to_string());
+    }
+
+    /// Delete the preset with the current name, and save the remaining presets.
+    fn delete_preset(&mut self) {
+        let Some(path) = self.script.as_ref().and_then(|script| script.path()) else {
+            return;
+        };
+        self.presets.remove(&self.preset_name);
+        self.presets_error = self.presets.save_for(path).err().map(|err| err.to_string());
+    }
+
+    /// Evaluate the script for each preset in the background, to compare them.
+    fn start_sweep(&mut self) {
+        let Some(script) = self.script.clone() else {
+            return;
+        };
+        let sweep = Sweep::from(&self.presets);
+        // values changed by hand are kept for anything the presets don't set
+        let base = self.changed_parameters();
+        self.sweep = Some(spawn(move || {
+            PatternScript::sweep(&script, &sweep, &base, &Default::default())
+        }));
+    }
 
-        exports.iter().for_each(|(exp, default)| {
-            if !self.parameters.contains_key(exp) {
-                self.parameters.insert(exp.clone(), default.clone());
+    /// Show the controls for choosing, saving and comparing presets, and the results of the last comparison.
+    fn presets_show(&mut self, ui: &mut egui::Ui) {
+        if self.sweep.as_ref().is_some_and(|sweep| sweep.is_finished()) {
+            self.sweep_results = self
+                .sweep
+                .take()
+                .unwrap()
+                .join()
+                .expect("Failed to join thread.");
+        }
+        let has_path = self
+            .script
+            .as_ref()
+            .is_some_and(|script| script.path().is_some());
+
+        ui.horizontal(|ui| {
+            let mut chosen = None;
+            egui::ComboBox::from_id_salt("preset")
+                .selected_text("Presets")
+                .show_ui(ui, |ui| {
+                    for (name, _) in &self.presets.presets {
+                        if ui
+                            .selectable_label(*name == self.preset_name, name)
+                            .clicked()
+                        {
+                            chosen = Some(name.clone());
+                        }
+                    }
+                });
+            if let Some(name) = chosen {
+                self.apply_preset(&name);
+            }
+
+            ui.add(
+                egui::TextEdit::singleline(&mut self.preset_name)
+                    .hint_text("Preset name")
+                    .desired_width(120.0),
+            );
+            let can_save = has_path && !self.preset_name.is_empty();
+            if ui
+                .add_enabled(can_save, egui::Button::new("Save"))
+                .on_disabled_hover_text("Save the script first, then name the preset.")
+                .clicked()
+            {
+                self.save_preset();
+            }
+            let exists = self.presets.get(&self.preset_name).is_some();
+            if ui
+                .add_enabled(has_path && exists, egui::Button::new("Delete"))
+                .clicked()
+            {
+                self.delete_preset();
+            }
+
+            let can_compare = !self.presets.presets.is_empty() && self.sweep.is_none();
+            if ui
+                .add_enabled(can_compare, egui::Button::new("Compare presets"))
+                .on_hover_text(
+                    "Evaluate the script for each preset, e.g. to grade a pattern across sizes.",
+                )
+                .clicked()
+            {
+                self.start_sweep();
+            }
+            if self.sweep.is_some() {
+                ui.spinner();
+                ui.ctx().request_repaint();
             }
         });
+        if let Some(err) = &self.presets_error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+
+        if !self.sweep_results.is_empty() {
+            egui::Grid::new("sweep_results")
+                .striped(true)
+                .show(ui, |ui| {
+                    for heading in ["Preset", "Rows", "Stitches", "Yarn"] {
+                        ui.strong(heading);
+                    }
+                    ui.end_row();
+                    for result in &self.sweep_results {
+                        ui.label(&result.name);
+                        match &result.stats {
+                            Ok(stats) => {
+                                ui.label(stats.rows.to_string());
+                                ui.label(stats.stitches.to_string());
+                                ui.label(format!("{:.2}m", stats.yarn.total_length()));
+                            }
+                            Err(err) => {
+                                ui.colored_label(egui::Color32::RED, err.to_string());
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+        }
+    }
+
+    /// The parameters the user has changed, to set when evaluating the script.
+    /// Parameters left at their defaults, and derived parameters, are worked out by the script itself.
+    pub fn changed_parameters(&self) -> HashMap<ImmutableString, Dynamic> {
         self.parameters
-            .retain(|k, _| exports.iter().any(|(ek, _)| k == ek));
+            .iter()
+            .filter(|(name, _)| self.changed.contains(*name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Work out the derived parameters again from the values of the others.
+    fn update_derived(&mut self) {
+        let Some(script) = &self.script else {
+            return;
+        };
+        if let Ok(values) = PatternScript::get_parameter_values(script, &self.changed_parameters())
+        {
+            self.parameters.extend(values);
+        }
     }
 }
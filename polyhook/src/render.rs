@@ -80,7 +80,7 @@ impl Renderer {
         let tex_normal = Texture::from_bytes(device, normal_bytes, "dc_normal");
 
         let model = Model::new(
-            model_from_pattern(&pattern),
+            model_from_pattern(&pattern, &Default::default()),
             device,
             &shader,
             &tex_diffuse,
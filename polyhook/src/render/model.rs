@@ -77,7 +77,7 @@ impl Vertex {
 #[derive(Clone)]
 pub struct ModelData {
     vertices: Vec<Vertex>,
-    indices: Vec<u16>,
+    indices: Vec<u32>,
 }
 
 impl ModelData {
@@ -85,7 +85,7 @@ impl ModelData {
         self.indices.len()
     }
 
-    pub fn new(vertices: Vec<Vertex>, indices: Vec<u16>) -> Self {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
         Self { vertices, indices }
     }
 }
@@ -147,7 +147,7 @@ impl Model {
     pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>) {
         render_pass.set_bind_group(0, &self.buffers.bind_groups.uniform, &[]);
         render_pass.set_bind_group(1, &self.buffers.bind_groups.texture, &[]);
-        render_pass.set_index_buffer(self.buffers.index.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_index_buffer(self.buffers.index.slice(..), wgpu::IndexFormat::Uint32);
         render_pass.set_vertex_buffer(0, self.buffers.vertex.slice(..));
         render_pass.draw_indexed(0..(self.data.num_indices() as u32), 0, 0..1);
     }
@@ -1,24 +1,69 @@
 use egui::{Color32, InnerResponse, Rect, Sense, Stroke, Vec2, Widget};
-use hooklib::parametric::{example_flat, Identifier, Operation, OperationRef, ParametricPattern};
+use hooklib::parametric::{
+    example_flat, BinaryOp, Identifier, Operation, OperationRef, ParametricPattern,
+};
 use std::{
     iter::once,
     time::{Duration, Instant},
 };
 
-const FUNCTIONS: &[&str] = &["chain", "dc", "turn", "new_row"];
+const FUNCTIONS: &[&str] = &[
+    "chain",
+    "sl",
+    "sc",
+    "hdc",
+    "dc",
+    "tr",
+    "dtr",
+    "dec",
+    "skip",
+    "turn",
+    "new_row",
+    "magic_ring",
+    "new_part",
+    "mark",
+    "curr",
+    "row",
+    "into",
+    "ss",
+    "sew",
+    "inc_n",
+    "dec_n",
+    "expect_count",
+    "change_color",
+    "stuffing",
+];
+
+/// The kinds of operation a value can be changed to, as they're named in its menu.
+const VALUE_TYPES: &[(&str, OperationType)] = &[
+    ("Number", OperationType::Literal),
+    ("Decimal", OperationType::Float),
+    ("Variable", OperationType::Variable),
+    ("Call", OperationType::Call),
+    ("Operation", OperationType::Binary),
+    ("List", OperationType::Array),
+    ("Code", OperationType::Raw),
+];
 
 pub struct VisualView {
     cached_identifiers: Vec<Identifier>,
+    /// The stitch functions, followed by the functions defined in the pattern.
+    cached_functions: Vec<Identifier>,
     valid_functions: Vec<Identifier>,
     pattern: ParametricPattern,
+    /// The script the pattern was loaded from, as the pattern writes it, to tell whether it's been edited since.
+    loaded: String,
 }
 
 impl Default for VisualView {
     fn default() -> Self {
+        let pattern = example_flat();
         Self {
             cached_identifiers: Vec::default(),
+            cached_functions: Vec::default(),
             valid_functions: FUNCTIONS.iter().map(|&func| func.into()).collect(),
-            pattern: example_flat(),
+            loaded: pattern.to_script(),
+            pattern,
         }
     }
 }
@@ -31,6 +76,17 @@ impl Widget for &mut VisualView {
                 egui::Frame::group(ui.style())
                     .show(ui, |ui| {
                         self.cached_identifiers = self.pattern.defined_identifiers();
+                        let defined = self.pattern.defined_functions();
+                        self.cached_functions = self
+                            .valid_functions
+                            .iter()
+                            .cloned()
+                            .chain(
+                                defined
+                                    .into_iter()
+                                    .filter(|func| !self.valid_functions.contains(func)),
+                            )
+                            .collect();
                         let r = self.pattern_ui(ui);
                         ui.allocate_space(ui.available_size());
                         r
@@ -59,13 +115,20 @@ impl ShouldShowAdd {
     }
 }
 
-#[allow(dead_code)]
+#[derive(Clone, Copy)]
 enum OperationType {
     Define,
     Literal,
+    Float,
     Variable,
     Call,
+    Binary,
+    Array,
     Repeat,
+    Function,
+    If,
+    For,
+    Raw,
 }
 
 struct AddStep {
@@ -83,6 +146,18 @@ impl VisualView {
         self.pattern.to_script()
     }
 
+    /// Replace the pattern with one converted from a script. Code that can't be edited visually is kept as raw code.
+    pub fn load_script(&mut self, source: &str) {
+        self.pattern = ParametricPattern::from_script(source);
+        self.loaded = self.pattern.to_script();
+    }
+
+    /// The script for the pattern, if it's been edited since it was loaded.
+    pub fn modified_code(&self) -> Option<String> {
+        let code = self.get_code();
+        (code != self.loaded).then_some(code)
+    }
+
     fn pattern_ui(&self, ui: &mut egui::Ui) -> egui::Response {
         self.operation_ui(ui, self.pattern.root().expect("No root node found."))
     }
@@ -154,6 +229,14 @@ impl VisualView {
                         Some(OperationType::Call)
                     } else if ui.button("Repeat").clicked() {
                         Some(OperationType::Repeat)
+                    } else if ui.button("Loop").clicked() {
+                        Some(OperationType::For)
+                    } else if ui.button("If").clicked() {
+                        Some(OperationType::If)
+                    } else if ui.button("Function").clicked() {
+                        Some(OperationType::Function)
+                    } else if ui.button("Code").clicked() {
+                        Some(OperationType::Raw)
                     } else {
                         None
                     }
@@ -213,6 +296,70 @@ impl VisualView {
         }
     }
 
+    /// Create a new operation of the given kind, with placeholder values to be edited.
+    fn new_operation(&self, kind: OperationType) -> OperationRef {
+        let p = &self.pattern;
+        match kind {
+            OperationType::Define => p.define("new_variable", p.literal(0)),
+            OperationType::Literal => p.literal(0),
+            OperationType::Float => p.float(0.0),
+            OperationType::Variable => p.variable("select variable..."),
+            OperationType::Call => p.call("chain", []),
+            OperationType::Binary => p.binary(BinaryOp::Add, p.literal(0), p.literal(0)),
+            OperationType::Array => p.array([]),
+            OperationType::Repeat => p.repeat(p.literal(0), p.seq([])),
+            OperationType::Function => p.function("new_function", [], p.seq([])),
+            OperationType::If => p.if_then(
+                p.binary(BinaryOp::Eq, p.literal(0), p.literal(0)),
+                p.seq([]),
+                None,
+            ),
+            OperationType::For => p.for_in(
+                "i",
+                p.binary(BinaryOp::Range, p.literal(0), p.literal(0)),
+                p.seq([]),
+            ),
+            OperationType::Raw => p.raw(""),
+        }
+    }
+
+    /// Show a value used by an operation, with a menu to change it to another kind of value.
+    fn value_ui(&self, ui: &mut egui::Ui, value: &mut OperationRef) -> egui::Response {
+        ui.horizontal(|ui| {
+            let resp = self.operation_ui(ui, *value);
+            let menu = ui.menu_button("...", |ui| {
+                VALUE_TYPES.iter().find_map(|&(name, kind)| {
+                    ui.button(name).clicked().then(|| {
+                        ui.close_menu();
+                        kind
+                    })
+                })
+            });
+            if let Some(Some(kind)) = menu.inner {
+                *value = self.new_operation(kind);
+            }
+            resp | menu.response
+        })
+        .inner
+    }
+
+    /// Show a list of values, e.g. the arguments of a call, with buttons to add and remove them.
+    fn values_ui(&self, ui: &mut egui::Ui, values: &mut Vec<OperationRef>) {
+        let mut to_remove = None;
+        for (i, value) in values.iter_mut().enumerate() {
+            self.value_ui(ui, value);
+            if ui.small_button("x").clicked() {
+                to_remove = Some(i);
+            }
+        }
+        if let Some(i) = to_remove {
+            values.remove(i);
+        }
+        if ui.small_button("+").clicked() {
+            values.push(self.pattern.literal(0));
+        }
+    }
+
     fn operation_ui(&self, ui: &mut egui::Ui, operation: OperationRef) -> egui::Response {
         match &mut *self.pattern.get_mut(operation).expect("Invalid node index") {
             Operation::Seq(vec) => {
@@ -233,7 +380,10 @@ impl VisualView {
                             InnerResponse::new(add.map(Instruction::AddStep), add_resp)
                         });
                         let after_operations = vec.iter_mut().enumerate().map(|(i, op)| {
+                            // each step takes the whole width, so it can be hovered anywhere
                             let resp = self.operation_ui(ui, *op);
+                            let rect = resp.rect.with_max_x(ui.max_rect().right());
+                            let resp = resp.with_new_rect(rect);
                             let InnerResponse {
                                 inner: add,
                                 response: add_resp,
@@ -261,18 +411,7 @@ impl VisualView {
                     .inner;
 
                 if let Some(Instruction::AddStep(add)) = resp.inner {
-                    let op_to_add = match add.kind {
-                        OperationType::Define => {
-                            self.pattern.define("new_variable", self.pattern.literal(0))
-                        }
-                        OperationType::Literal => self.pattern.literal(0),
-                        OperationType::Variable => self.pattern.variable("select variable..."),
-                        OperationType::Call => self.pattern.call("chain"),
-                        OperationType::Repeat => self
-                            .pattern
-                            .repeat(self.pattern.literal(0), self.pattern.seq([])),
-                    };
-                    vec.insert(add.at, op_to_add);
+                    vec.insert(add.at, self.new_operation(add.kind));
                 } else if let Some(Instruction::RemoveStep(i)) = resp.inner {
                     vec.remove(i);
                 }
@@ -280,13 +419,16 @@ impl VisualView {
             }
             Operation::Define(identifier, operation) => {
                 ui.horizontal(|ui| {
-                    ui.label(format!("define {identifier} as"));
-                    self.operation_ui(ui, *operation);
+                    ui.label("define");
+                    identifier_edit(ui, identifier);
+                    ui.label("as");
+                    self.value_ui(ui, operation);
                     ui.allocate_space([ui.available_width(), 0.0].into());
                 })
                 .response
             }
             Operation::Literal(value) => ui.add(egui::DragValue::new(value)),
+            Operation::Float(value) => ui.add(egui::DragValue::new(value).speed(0.01)),
             Operation::Variable(identifier) => {
                 egui::ComboBox::from_id_salt(operation)
                     .selected_text(identifier.to_string())
@@ -297,38 +439,48 @@ impl VisualView {
                     })
                     .response
             }
-            Operation::Call(identifier) => {
+            Operation::Call(identifier, args) => {
                 ui.horizontal(|ui| {
                     egui::ComboBox::from_id_salt(operation)
                         .selected_text(identifier.to_string())
                         .show_ui(ui, |ui| {
-                            self.valid_functions.iter().for_each(|option| {
+                            self.cached_functions.iter().for_each(|option| {
                                 ui.selectable_value(identifier, option.clone(), option.to_string());
                             });
                         });
-                    ui.allocate_space([ui.available_width(), 0.0].into());
+                    ui.label("(");
+                    self.values_ui(ui, args);
+                    ui.label(")");
+                })
+                .response
+            }
+            Operation::Binary(op, lhs, rhs) => {
+                ui.horizontal(|ui| {
+                    self.value_ui(ui, lhs);
+                    egui::ComboBox::from_id_salt(operation)
+                        .width(40.0)
+                        .selected_text(op.symbol())
+                        .show_ui(ui, |ui| {
+                            BinaryOp::ALL.iter().for_each(|&option| {
+                                ui.selectable_value(op, option, option.symbol());
+                            });
+                        });
+                    self.value_ui(ui, rhs);
+                })
+                .response
+            }
+            Operation::Array(items) => {
+                ui.horizontal(|ui| {
+                    ui.label("[");
+                    self.values_ui(ui, items);
+                    ui.label("]");
                 })
                 .response
             }
             Operation::Repeat(n, op) => {
                 let resp_1 = ui.horizontal(|ui| {
                     ui.label("do")
-                        .union(self.operation_ui(ui, *n))
-                        .union({
-                            let is_literal = self
-                                .pattern
-                                .get(*n)
-                                .is_some_and(|n| matches!(*n, Operation::Literal(_)));
-                            let resp = ui.button(if is_literal { "(L -> V)" } else { "(V -> L)" });
-                            if resp.clicked() {
-                                if is_literal {
-                                    *n = self.pattern.variable("select variable...");
-                                } else {
-                                    *n = self.pattern.literal(0);
-                                }
-                            }
-                            resp
-                        })
+                        .union(self.value_ui(ui, n))
                         .union(ui.label("times:"))
                 });
 
@@ -336,6 +488,95 @@ impl VisualView {
 
                 resp_1.inner | resp_1.response | resp_2.inner | resp_2.response
             }
+            Operation::Function(identifier, params, body) => {
+                let resp_1 = ui.horizontal(|ui| {
+                    ui.label("function");
+                    identifier_edit(ui, identifier);
+                    ui.label("with");
+                    let mut to_remove = None;
+                    for (i, param) in params.iter_mut().enumerate() {
+                        identifier_edit(ui, param);
+                        if ui.small_button("x").clicked() {
+                            to_remove = Some(i);
+                        }
+                    }
+                    if let Some(i) = to_remove {
+                        params.remove(i);
+                    }
+                    if ui.small_button("+").clicked() {
+                        params.push("value".into());
+                    }
+                });
+
+                let resp_2 = ui.indent(0, |ui| self.operation_ui(ui, *body));
+
+                resp_1.response | resp_2.inner | resp_2.response
+            }
+            Operation::If(condition, then, otherwise) => {
+                let resp_1 = ui.horizontal(|ui| ui.label("if").union(self.value_ui(ui, condition)));
+                let resp_2 = ui.indent(0, |ui| self.operation_ui(ui, *then));
+                let resp = resp_1.inner | resp_1.response | resp_2.inner | resp_2.response;
+
+                match otherwise {
+                    Some(body) => {
+                        let resp_3 = ui.horizontal(|ui| {
+                            ui.label("otherwise");
+                            ui.small_button("x").clicked()
+                        });
+                        let resp_4 = ui.indent(0, |ui| self.operation_ui(ui, *body));
+                        if resp_3.inner {
+                            *otherwise = None;
+                        }
+                        resp | resp_3.response | resp_4.inner | resp_4.response
+                    }
+                    None => {
+                        let add = ui.small_button("+ otherwise");
+                        if add.clicked() {
+                            *otherwise = Some(self.pattern.seq([]));
+                        }
+                        resp | add
+                    }
+                }
+            }
+            Operation::For(identifier, values, body) => {
+                let resp_1 = ui.horizontal(|ui| {
+                    ui.label("for each")
+                        .union(identifier_edit(ui, identifier))
+                        .union(ui.label("in"))
+                        .union(self.value_ui(ui, values))
+                });
+
+                let resp_2 = ui.indent(0, |ui| self.operation_ui(ui, *body));
+
+                resp_1.inner | resp_1.response | resp_2.inner | resp_2.response
+            }
+            Operation::Raw(code) if code.contains('\n') => ui.add(
+                egui::TextEdit::multiline(code)
+                    .code_editor()
+                    .desired_rows(1)
+                    .desired_width(f32::INFINITY),
+            ),
+            // a single line of code grows as it's typed, so it can be used as a value
+            Operation::Raw(code) => ui.add(
+                egui::TextEdit::singleline(code)
+                    .code_editor()
+                    .clip_text(false)
+                    .desired_width(60.0),
+            ),
         }
     }
 }
+
+/// Edit the name of a variable, function or parameter.
+fn identifier_edit(ui: &mut egui::Ui, identifier: &mut Identifier) -> egui::Response {
+    let mut name = identifier.to_string();
+    let resp = ui.add(
+        egui::TextEdit::singleline(&mut name)
+            .clip_text(false)
+            .desired_width(60.0),
+    );
+    if resp.changed() {
+        *identifier = name.into();
+    }
+    resp
+}
@@ -5,19 +5,49 @@ use itertools::Itertools;
 use petgraph::{
     algo::dijkstra,
     graph::NodeIndex,
+    unionfind::UnionFind,
     visit::{EdgeRef, IntoNodeReferences},
     Graph, Undirected,
 };
 use rand::prelude::*;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Display;
 use std::ops::{Add, Mul, Sub};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+/// A callback reporting how far through a layout is, as a fraction from 0 to 1.
+/// Returning `false` cancels the layout. It may be called from several threads at once.
+pub type ProgressFn<'a> = dyn Fn(f32) -> bool + Sync + 'a;
+
+/// The error returned when a layout is cancelled by its [`ProgressFn`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The layout was cancelled.")
+    }
+}
+
+impl Error for Cancelled {}
 
 /// Trait for types that can be used as coordinates for SGD.
 pub trait SGDCoords:
-    Add<Self, Output = Self> + Sub<Self, Output = Self> + Mul<f32, Output = Self> + Sized + Copy
+    Add<Self, Output = Self>
+    + Sub<Self, Output = Self>
+    + Mul<f32, Output = Self>
+    + Sized
+    + Copy
+    + Send
+    + Sync
 {
     fn random<R: Rng>(rng: &mut R) -> Self;
     fn length(self) -> f32;
     fn is_nan(self) -> bool;
+    fn to_vec3(self) -> Vec3;
+    fn from_vec3(v: Vec3) -> Self;
 }
 
 impl SGDCoords for Vec2 {
@@ -32,6 +62,14 @@ impl SGDCoords for Vec2 {
     fn is_nan(self) -> bool {
         Vec2::is_nan(self)
     }
+
+    fn to_vec3(self) -> Vec3 {
+        self.extend(0.0)
+    }
+
+    fn from_vec3(v: Vec3) -> Self {
+        v.truncate()
+    }
 }
 
 impl SGDCoords for Vec3 {
@@ -50,6 +88,14 @@ impl SGDCoords for Vec3 {
     fn is_nan(self) -> bool {
         Vec3::is_nan(self)
     }
+
+    fn to_vec3(self) -> Vec3 {
+        self
+    }
+
+    fn from_vec3(v: Vec3) -> Self {
+        v
+    }
 }
 
 /// A single term in the SGD process, created from a shortest-path between two nodes.
@@ -59,13 +105,90 @@ struct Term {
     end: NodeIndex,
     d: f32,
     w: f32,
+    /// Whether the end node is moved as well as the start.
+    /// Terms towards a pivot only move the other node, so the pivot isn't pulled around by every node in the graph.
+    move_end: bool,
+}
+
+/// How the terms used for SGD are chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SgdMode {
+    /// Use the shortest path between every pair of nodes.
+    /// This is the most accurate, but takes quadratic time and memory in the number of nodes.
+    #[default]
+    Exact,
+    /// Use the shortest paths from every node to a number of pivot nodes, plus the edges between neighbours.
+    /// This takes time and memory that grow roughly linearly, for large patterns.
+    Sparse { pivots: usize },
 }
 
 const EPSILON: f32 = 0.01;
 const SGD_ITERS: u32 = 10;
 
+const FDG_ITERS: u32 = 10;
+const STEP_SIZE: f32 = 0.1;
+const ATTRACTIVE_FORCE: f32 = 2.0;
+
+const REPULSION_RADIUS: f32 = 1.0;
+
+const INFLATE_ITERS: u32 = 20;
+/// Scales pressure so that a pressure of 1 is firmly stuffed, without stretching stitches much.
+const PRESSURE_SCALE: f32 = 0.2;
+
+/// Options for the layout performed by [`sgd`] and [`fdg`].
+/// Laying out the same graph with the same options always gives the same result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutOptions {
+    /// The seed for the random initial positions and the order terms are visited in.
+    pub seed: u64,
+    /// How the terms used for SGD are chosen.
+    pub mode: SgdMode,
+    /// The number of iterations of SGD.
+    pub iterations: u32,
+    /// How small the final step size of SGD is, relative to the largest weight.
+    pub epsilon: f32,
+    /// The number of iterations of force-directed layout.
+    pub fdg_iterations: u32,
+    /// How far nodes are moved along the force on them in each iteration of force-directed layout.
+    pub step_size: f32,
+    /// The strength of the force pulling connected nodes towards their edge length.
+    pub attractive_force: f32,
+    /// Apply SGD updates from many threads at once, without locking ("Hogwild").
+    /// This is much faster for large graphs, but threads race on the positions, so the result is no longer deterministic.
+    pub hogwild: bool,
+    /// The pressure pushing closed regions outwards, like stuffing in a finished toy.
+    /// This is used for regions that don't give their own, and zero leaves them as they are.
+    pub pressure: f32,
+    /// The number of iterations of [`inflate`].
+    pub inflate_iterations: u32,
+    /// The strength of the force pushing apart nodes that aren't connected but are closer than `repulsion_radius`,
+    /// which stops parts of the graph passing through each other during force-directed layout. Zero turns it off.
+    pub repulsion: f32,
+    /// How close nodes that aren't connected can get before they're pushed apart.
+    pub repulsion_radius: f32,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            mode: SgdMode::Exact,
+            iterations: SGD_ITERS,
+            epsilon: EPSILON,
+            fdg_iterations: FDG_ITERS,
+            step_size: STEP_SIZE,
+            attractive_force: ATTRACTIVE_FORCE,
+            hogwild: false,
+            pressure: 0.0,
+            inflate_iterations: INFLATE_ITERS,
+            repulsion: 0.0,
+            repulsion_radius: REPULSION_RADIUS,
+        }
+    }
+}
+
 /// Produces the annealing schedule used for SGD.
-fn schedule(terms: &[Term], t_max: u32) -> Vec<f32> {
+fn schedule(terms: &[Term], t_max: u32, epsilon: f32) -> Vec<f32> {
     let w_min = terms
         .iter()
         .min_by(|a, b| a.w.partial_cmp(&b.w).expect("tried to compare NaN"))
@@ -78,7 +201,7 @@ fn schedule(terms: &[Term], t_max: u32) -> Vec<f32> {
         .w;
 
     let eta_max = 1.0 / w_min;
-    let eta_min = EPSILON / w_max;
+    let eta_min = epsilon / w_max;
 
     let lambda = f32::ln(eta_max / eta_min) / ((t_max as f32) - 1.0);
 
@@ -87,13 +210,287 @@ fn schedule(terms: &[Term], t_max: u32) -> Vec<f32> {
         .collect::<Vec<_>>()
 }
 
+/// Find the shortest paths from a node to every node it's connected to.
+/// They're sorted so that the terms don't depend on the order of the hash map.
+fn shortest_paths<C: Sync>(
+    graph: &Graph<C, f32, Undirected>,
+    node: NodeIndex,
+) -> Vec<(NodeIndex, f32)> {
+    dijkstra::dijkstra(graph, node, None, |e| *e.weight())
+        .into_iter()
+        .sorted_unstable_by_key(|(end, _)| *end)
+        .collect()
+}
+
+/// Create a term for every pair of connected nodes.
+/// Progress is reported as the fraction of nodes whose shortest paths have been found.
+fn exact_terms<C: Sync>(
+    graph: &Graph<C, f32, Undirected>,
+    progress: &ProgressFn<'_>,
+) -> Result<Vec<Term>, Cancelled> {
+    // ignore the last node because it'll be covered by all the others
+    let sources = graph.node_count().saturating_sub(1);
+    let done = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+
+    let terms = (0..sources)
+        .into_par_iter()
+        .map(NodeIndex::new)
+        .map(|node| {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            // find the shortest path from each node to each other node
+            let terms = shortest_paths(graph, node)
+                .into_iter()
+                .filter(|(end, _)| node < *end) // and only count the unique paths
+                .map(|(end, cost)| Term {
+                    start: node,
+                    end,
+                    d: cost,
+                    w: f32::powi(cost, -2), // the weight w of a term is the inverse square cost.
+                    move_end: true,
+                })
+                .collect::<Vec<_>>();
+
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            if !progress(done as f32 / sources as f32) {
+                cancelled.store(true, Ordering::Relaxed);
+                return None;
+            }
+            Some(terms)
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Cancelled)?;
+
+    Ok(terms.into_iter().flatten().collect())
+}
+
+/// Create terms between every node and a set of pivots, plus between each pair of neighbours.
+///
+/// Pivots are chosen at random, plus a node in each connected component without one so that every node has a pivot.
+/// Each node's term to a pivot stands in for the terms to the nodes near that pivot, so it's weighted by how many of them there are.
+/// This is the sparse approximation from Zheng, Pawar & Goodman, "Graph Drawing by Stochastic Gradient Descent".
+/// Progress is reported as the fraction of pivots whose shortest paths have been found.
+fn sparse_terms<C: Sync, R: Rng>(
+    graph: &Graph<C, f32, Undirected>,
+    pivots: usize,
+    rng: &mut R,
+    progress: &ProgressFn<'_>,
+) -> Result<Vec<Term>, Cancelled> {
+    let n = graph.node_count();
+    if n < 2 {
+        return Ok(Vec::new());
+    }
+
+    // every pivot is known up front, so their shortest paths can be found in parallel
+    let mut pivots = rand::seq::index::sample(rng, n, pivots.clamp(1, n))
+        .into_iter()
+        .sorted_unstable()
+        .map(NodeIndex::new)
+        .collect::<Vec<_>>();
+    let mut components = UnionFind::new(n);
+    for e in graph.edge_references() {
+        components.union(e.source().index(), e.target().index());
+    }
+    let mut covered = pivots
+        .iter()
+        .map(|pivot| components.find(pivot.index()))
+        .collect::<HashSet<_>>();
+    for node in 0..n {
+        if covered.insert(components.find(node)) {
+            pivots.push(NodeIndex::new(node));
+        }
+    }
+
+    // the distance from each pivot to every node
+    let done = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    let pivot_distances = pivots
+        .par_iter()
+        .map(|&pivot| {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            let mut distances = vec![f32::INFINITY; n];
+            for (node, cost) in shortest_paths(graph, pivot) {
+                distances[node.index()] = cost;
+            }
+
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            if !progress(done as f32 / pivots.len() as f32) {
+                cancelled.store(true, Ordering::Relaxed);
+                return None;
+            }
+            Some((pivot, distances))
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Cancelled)?;
+
+    // the distance from each node to its nearest pivot, and which pivot that is
+    let nearest = (0..n)
+        .map(|node| {
+            pivot_distances
+                .iter()
+                .enumerate()
+                .map(|(p, (_, distances))| (distances[node], p))
+                .filter(|(cost, _)| cost.is_finite())
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap_or((f32::INFINITY, usize::MAX))
+        })
+        .collect::<Vec<_>>();
+
+    // the sorted distances from each pivot to the nodes in its region, i.e. the nodes closer to it than any other pivot
+    let mut regions = vec![Vec::new(); pivot_distances.len()];
+    for &(cost, p) in &nearest {
+        if p != usize::MAX {
+            regions[p].push(cost);
+        }
+    }
+    regions
+        .iter_mut()
+        .for_each(|region| region.sort_unstable_by(f32::total_cmp));
+
+    let neighbour_terms = graph
+        .edge_references()
+        .filter(|e| e.source() != e.target())
+        .map(|e| {
+            (
+                e.source().min(e.target()),
+                e.source().max(e.target()),
+                *e.weight(),
+            )
+        })
+        .sorted_unstable_by_key(|(start, end, _)| (*start, *end))
+        .dedup_by(|a, b| a.0 == b.0 && a.1 == b.1)
+        .map(|(start, end, d)| Term {
+            start,
+            end,
+            d,
+            w: f32::powi(d, -2),
+            move_end: true,
+        });
+
+    let pivot_terms =
+        pivot_distances
+            .par_iter()
+            .zip(&regions)
+            .flat_map_iter(|((pivot, distances), region)| {
+                let pivot = *pivot;
+                graph.node_indices().filter_map(move |node| {
+                    let d = distances[node.index()];
+                    if node == pivot || !d.is_finite() || graph.contains_edge(node, pivot) {
+                        return None;
+                    }
+                    // the number of nodes in the region that are closer to the pivot than halfway to this node
+                    let s = region.partition_point(|&r| r <= d / 2.0).max(1) as f32;
+                    Some(Term {
+                        start: node,
+                        end: pivot,
+                        d,
+                        w: s * f32::powi(d, -2),
+                        move_end: false,
+                    })
+                })
+            });
+
+    let mut terms = neighbour_terms.collect::<Vec<_>>();
+    terms.par_extend(pivot_terms);
+    Ok(terms)
+}
+
+/// Calculate the stress of a layout, i.e. how far the distances between nodes are from their shortest paths.
+/// This is the sum of `(|p_i - p_j| - d_ij)^2 / d_ij^2` over every pair of connected nodes, so it's what SGD minimises.
+/// It takes quadratic time, so it's only for comparing layouts of smaller graphs.
+pub fn stress<C: SGDCoords>(graph: &Graph<C, f32, Undirected>) -> f32 {
+    exact_terms(graph, &|_| true)
+        .unwrap_or_default()
+        .iter()
+        .map(|term| {
+            let mag = (graph[term.start] - graph[term.end]).length();
+            term.w * f32::powi(mag - term.d, 2)
+        })
+        .sum()
+}
+
 /// Performs SGD on the given graph, converting all nodes to the given [`SGDCoords`] type, and all edges into their length given by [`Into<f32>`].
-pub fn sgd<C, N, E>(g: &Graph<N, E>) -> Graph<C, f32, Undirected>
+pub fn sgd<C, N, E>(g: &Graph<N, E>, options: &LayoutOptions) -> Graph<C, f32, Undirected>
 where
     C: SGDCoords,
     E: Into<f32> + Clone,
 {
-    let mut rng = SmallRng::from_rng(thread_rng()).expect("Couldn't create RNG");
+    sgd_warm(g, options, &[])
+}
+
+/// Place every node without an initial position next to its neighbours that have one.
+/// Nodes that aren't connected to any placed nodes keep their random position.
+fn place_from_neighbours<C: SGDCoords, R: Rng>(
+    graph: &mut Graph<C, f32, Undirected>,
+    initial: &[Option<C>],
+    rng: &mut R,
+) {
+    let mut placed = graph
+        .node_indices()
+        .map(|node| initial.get(node.index()).copied().flatten())
+        .collect::<Vec<_>>();
+
+    // keep going until no more nodes can be placed, so that new rows grow out from the old ones
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in graph.node_indices() {
+            if placed[node.index()].is_some() {
+                continue;
+            }
+            let neighbours = graph
+                .neighbors(node)
+                .filter_map(|n| placed[n.index()])
+                .collect::<Vec<_>>();
+            if let Some((&first, rest)) = neighbours.split_first() {
+                let sum = rest.iter().fold(first, |sum, &p| sum + p);
+                let jitter = (C::random(rng) - C::random(rng)) * 0.1;
+                placed[node.index()] = Some(sum * (1.0 / neighbours.len() as f32) + jitter);
+                changed = true;
+            }
+        }
+    }
+
+    for (node, p) in graph.node_indices().zip(placed) {
+        if let Some(p) = p {
+            graph[node] = p;
+        }
+    }
+}
+
+/// Performs SGD on the given graph like [`sgd`], starting from the given positions rather than random ones.
+/// `initial` gives the starting position of each node by index, e.g. from the layout of an earlier version of the same pattern.
+/// Nodes without a position are placed next to their neighbours.
+/// Since the layout is mostly there already, only the smaller steps of the annealing schedule are used.
+pub fn sgd_warm<C, N, E>(
+    g: &Graph<N, E>,
+    options: &LayoutOptions,
+    initial: &[Option<C>],
+) -> Graph<C, f32, Undirected>
+where
+    C: SGDCoords,
+    E: Into<f32> + Clone,
+{
+    sgd_with_progress(g, options, initial, &|_| true).expect("Layout can't be cancelled")
+}
+
+/// Performs SGD on the given graph like [`sgd_warm`], reporting progress as it goes.
+/// Finding the shortest paths makes up the first half of the progress, and the SGD iterations the second half.
+pub fn sgd_with_progress<C, N, E>(
+    g: &Graph<N, E>,
+    options: &LayoutOptions,
+    initial: &[Option<C>],
+    progress: &ProgressFn<'_>,
+) -> Result<Graph<C, f32, Undirected>, Cancelled>
+where
+    C: SGDCoords,
+    E: Into<f32> + Clone,
+{
+    let mut rng = SmallRng::seed_from_u64(options.seed);
 
     // turn a crochet graph into pure vertices and edges
     let mut graph = g
@@ -103,29 +500,24 @@ where
         )
         .into_edge_type::<Undirected>();
 
-    let nodes = graph.node_indices().collect::<Vec<_>>();
-    let terms = nodes
-        .iter()
-        .take(nodes.len() - 1) // ignore the last node because it'll be covered by all the others
-        .flat_map(|node| {
-            // find the shortest path from each node to each other node
-            dijkstra::dijkstra(&graph, *node, None, |e| *e.weight())
-                .into_iter()
-                .map(|(end, cost)| (node, end, cost))
-                .collect::<Vec<_>>()
-        })
-        .filter(|(start, end, _)| *start < end) // and only count the unique paths
-        .map(|(&start, end, cost)| Term {
-            start,
-            end,
-            d: cost,
-            w: f32::powi(cost, -2), // the weight w of a term is the inverse square cost.
-        })
-        .collect::<Vec<_>>();
+    let warm = initial.iter().any(Option::is_some);
+    if warm {
+        place_from_neighbours(&mut graph, initial, &mut rng);
+    }
+
+    let terms = match options.mode {
+        SgdMode::Exact => exact_terms(&graph, &|fraction| progress(fraction * 0.5))?,
+        SgdMode::Sparse { pivots } => sparse_terms(&graph, pivots, &mut rng, &|fraction| {
+            progress(fraction * 0.5)
+        })?,
+    };
+    if !progress(0.5) {
+        return Err(Cancelled);
+    }
 
     if terms.is_empty() {
         eprintln!("No terms found in the graph! Was there more than one node?");
-        return graph;
+        return Ok(graph);
     }
 
     // shuffle the terms twice and alternate between both shuffles
@@ -135,55 +527,223 @@ where
     terms_order_2.shuffle(&mut rng);
     let mut terms_orders = { std::iter::repeat([&terms_order_1, &terms_order_2]).flatten() };
 
-    let etas: Vec<f32> = schedule(&terms, SGD_ITERS);
-    for eta in etas {
+    let mut etas: Vec<f32> = schedule(&terms, options.iterations, options.epsilon);
+    if warm {
+        // the large steps at the start of the schedule would scramble the initial layout
+        etas.drain(..etas.len() / 2);
+    }
+    let iteration_progress = |i: usize| progress(0.5 + 0.5 * (i + 1) as f32 / etas.len() as f32);
+    if options.hogwild {
+        hogwild(&mut graph, &terms, &etas, terms_orders, iteration_progress)?;
+        return Ok(graph);
+    }
+
+    for (i, &eta) in etas.iter().enumerate() {
         let term_idxs = terms_orders.next().unwrap();
         for &term_idx in term_idxs.iter() {
             let term = &terms[term_idx];
-            let mu = f32::min(eta * term.w, 1.0); // limit the step size to at most 1.
-            let p_i: C = graph[term.start];
-            let p_j: C = graph[term.end];
+            let (p_i, p_j) = step(term, eta, graph[term.start], graph[term.end]);
+            graph[term.start] = p_i;
+            graph[term.end] = p_j;
+        }
+        if !iteration_progress(i) {
+            return Err(Cancelled);
+        }
+    }
 
-            let d: C = p_i - p_j;
-            let mag = d.length();
+    Ok(graph)
+}
+
+/// Move the two nodes of a term towards their ideal distance, returning their new positions.
+fn step<C: SGDCoords>(term: &Term, eta: f32, p_i: C, p_j: C) -> (C, C) {
+    let mu = f32::min(eta * term.w, 1.0); // limit the step size to at most 1.
 
-            let r = (mu * (mag - term.d)) / (2.0 * mag);
-            let rv = d * r;
+    let d: C = p_i - p_j;
+    let mag = d.length();
 
-            graph[term.start] = p_i - rv;
-            graph[term.end] = p_j + rv;
+    if term.move_end {
+        // move both nodes half of the way
+        let r = (mu * (mag - term.d)) / (2.0 * mag);
+        let rv = d * r;
+
+        (p_i - rv, p_j + rv)
+    } else {
+        // only move the start node, so it has to go the whole way
+        let r = (mu * (mag - term.d)) / mag;
+        (p_i - d * r, p_j)
+    }
+}
+
+/// Apply the SGD updates for each term in parallel, letting threads overwrite each other's updates.
+/// Each term only touches two nodes, so in a large graph collisions are rare and barely affect the result.
+fn hogwild<'a, C: SGDCoords>(
+    graph: &mut Graph<C, f32, Undirected>,
+    terms: &[Term],
+    etas: &[f32],
+    mut terms_orders: impl Iterator<Item = &'a Vec<usize>>,
+    iteration_progress: impl Fn(usize) -> bool,
+) -> Result<(), Cancelled> {
+    // positions are stored as atomic bits so that racing threads don't cause undefined behaviour
+    let positions = graph
+        .node_weights()
+        .map(|p| p.to_vec3().to_array().map(|f| AtomicU32::new(f.to_bits())))
+        .collect::<Vec<_>>();
+    let load = |node: NodeIndex| {
+        C::from_vec3(Vec3::from_array(
+            positions[node.index()]
+                .each_ref()
+                .map(|f| f32::from_bits(f.load(Ordering::Relaxed))),
+        ))
+    };
+    let store = |node: NodeIndex, p: C| {
+        for (f, v) in positions[node.index()].iter().zip(p.to_vec3().to_array()) {
+            f.store(v.to_bits(), Ordering::Relaxed);
         }
+    };
+
+    for (i, &eta) in etas.iter().enumerate() {
+        let term_idxs = terms_orders.next().unwrap();
+        term_idxs.par_iter().for_each(|&term_idx| {
+            let term = &terms[term_idx];
+            let (p_i, p_j) = step(term, eta, load(term.start), load(term.end));
+            store(term.start, p_i);
+            if term.move_end {
+                store(term.end, p_j);
+            }
+        });
+        if !iteration_progress(i) {
+            return Err(Cancelled);
+        }
+    }
+
+    for node in graph.node_indices() {
+        graph[node] = load(node);
     }
+    Ok(())
+}
 
+/// The attractive force on a node, pulling it and its neighbours towards the lengths of the edges between them.
+fn spring_force(
+    graph: &Graph<Vec3, f32, Undirected>,
+    n1: NodeIndex,
+    options: &LayoutOptions,
+) -> Vec3 {
+    let p1 = graph[n1];
     graph
+        .edges(n1)
+        .map(|e| {
+            let n2 = if e.source() == n1 {
+                e.target()
+            } else {
+                e.source()
+            };
+            let d = graph[n2] - p1;
+            let f = options.attractive_force * f32::log10(d.length() / e.weight());
+            f * d.normalize()
+        })
+        .sum()
 }
 
-const FDG_ITERS: u32 = 10;
-const STEP_SIZE: f32 = 0.1;
-const ATTRACTIVE_FORCE: f32 = 2.0;
+/// Buckets nodes into a grid of cubes, to quickly find the nodes near a point.
+struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<[i32; 3], Vec<NodeIndex>>,
+}
+
+impl SpatialHash {
+    fn new(graph: &Graph<Vec3, f32, Undirected>, cell_size: f32) -> Self {
+        let mut cells = HashMap::<_, Vec<_>>::new();
+        for (node, p) in graph.node_references() {
+            cells
+                .entry(Self::cell(*p, cell_size))
+                .or_default()
+                .push(node);
+        }
+        Self { cell_size, cells }
+    }
+
+    fn cell(p: Vec3, cell_size: f32) -> [i32; 3] {
+        (p / cell_size).floor().as_ivec3().to_array()
+    }
+
+    /// Iterate over the nodes in the cell containing a point and the cells around it,
+    /// i.e. every node within the cell size of the point and some further away.
+    fn near(&self, p: Vec3) -> impl Iterator<Item = NodeIndex> + '_ {
+        let [x, y, z] = Self::cell(p, self.cell_size);
+        (-1..=1)
+            .cartesian_product(-1..=1)
+            .cartesian_product(-1..=1)
+            .filter_map(move |((dx, dy), dz)| self.cells.get(&[x + dx, y + dy, z + dz]))
+            .flatten()
+            .copied()
+    }
+}
+
+/// The repulsive force on a node, pushing it away from nearby nodes it isn't connected to.
+fn repulsive_force(
+    graph: &Graph<Vec3, f32, Undirected>,
+    hash: &SpatialHash,
+    n1: NodeIndex,
+    options: &LayoutOptions,
+) -> Vec3 {
+    let p1 = graph[n1];
+    hash.near(p1)
+        .filter(|n2| *n2 != n1 && !graph.contains_edge(n1, *n2))
+        .map(|n2| {
+            let d = p1 - graph[n2];
+            let overlap = 1.0 - d.length() / options.repulsion_radius;
+            if overlap > 0.0 {
+                options.repulsion * overlap * d.normalize_or_zero()
+            } else {
+                Vec3::ZERO
+            }
+        })
+        .sum()
+}
+
+/// The total force on a node from its edges, and from nearby nodes if repulsion is turned on.
+fn layout_force(
+    graph: &Graph<Vec3, f32, Undirected>,
+    hash: Option<&SpatialHash>,
+    n: NodeIndex,
+    options: &LayoutOptions,
+) -> Vec3 {
+    spring_force(graph, n, options)
+        + hash.map_or(Vec3::ZERO, |hash| repulsive_force(graph, hash, n, options))
+}
+
+/// Build a spatial hash for finding nearby nodes, if repulsion is turned on.
+fn repulsion_hash(
+    graph: &Graph<Vec3, f32, Undirected>,
+    options: &LayoutOptions,
+) -> Option<SpatialHash> {
+    (options.repulsion > 0.0).then(|| SpatialHash::new(graph, options.repulsion_radius))
+}
 
 /// Perform force-directed graph layout on a graph.
-/// Uses the Tutte approach - attractive forces and no repulsive forces.
-pub fn fdg(g: &mut Graph<Vec3, f32, Undirected>) {
-    for _ in 1..=FDG_ITERS {
-        let new_pos = g
-            .node_references()
-            .map(|(n1, p1)| {
-                let force: Vec3 = g
-                    .edges(n1)
-                    .map(|e| {
-                        let n2 = if e.source() == n1 {
-                            e.target()
-                        } else {
-                            e.source()
-                        };
-                        let p2 = g.node_weight(n2).unwrap();
-                        let d = p2 - p1;
-                        let f = ATTRACTIVE_FORCE * f32::log10(d.length() / e.weight());
-                        f * d.normalize()
-                    })
-                    .sum();
-                (n1, g.node_weight(n1).unwrap() + (STEP_SIZE * force))
+/// Uses the Tutte approach - attractive forces and no repulsive forces,
+/// unless [`LayoutOptions::repulsion`] is set to keep nodes that aren't connected apart.
+pub fn fdg(g: &mut Graph<Vec3, f32, Undirected>, options: &LayoutOptions) {
+    fdg_with_progress(g, options, &|_| true).expect("Layout can't be cancelled")
+}
+
+/// Perform force-directed graph layout on a graph like [`fdg`], reporting progress after each iteration.
+pub fn fdg_with_progress(
+    g: &mut Graph<Vec3, f32, Undirected>,
+    options: &LayoutOptions,
+    progress: &ProgressFn<'_>,
+) -> Result<(), Cancelled> {
+    for i in 1..=options.fdg_iterations {
+        let graph = &*g;
+        let hash = repulsion_hash(graph, options);
+        let new_pos = (0..graph.node_count())
+            .into_par_iter()
+            .map(NodeIndex::new)
+            .map(|n1| {
+                (
+                    n1,
+                    graph[n1] + options.step_size * layout_force(graph, hash.as_ref(), n1, options),
+                )
             })
             .collect::<Vec<_>>();
 
@@ -191,7 +751,105 @@ pub fn fdg(g: &mut Graph<Vec3, f32, Undirected>) {
             let w = g.node_weight_mut(n).unwrap();
             *w = p;
         });
+
+        if !progress(i as f32 / options.fdg_iterations as f32) {
+            return Err(Cancelled);
+        }
     }
+
+    Ok(())
+}
+
+/// A closed region of a graph, like a stuffed part of a pattern, to be inflated by [`inflate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    /// The nodes making up the surface of the region.
+    pub nodes: Vec<NodeIndex>,
+    /// The pressure pushing the region outwards, or `None` to use [`LayoutOptions::pressure`].
+    pub pressure: Option<f32>,
+}
+
+/// Inflate closed regions of a laid out graph, as if they were stuffed.
+/// Each node is pushed away from the centre of its region, while the edges pull it back towards their lengths,
+/// so crumpled or concave regions fill out without stretching the graph much.
+pub fn inflate(g: &mut Graph<Vec3, f32, Undirected>, regions: &[Region], options: &LayoutOptions) {
+    inflate_with_progress(g, regions, options, &|_| true).expect("Layout can't be cancelled")
+}
+
+/// Inflate closed regions of a graph like [`inflate`], reporting progress after each iteration.
+pub fn inflate_with_progress(
+    g: &mut Graph<Vec3, f32, Undirected>,
+    regions: &[Region],
+    options: &LayoutOptions,
+    progress: &ProgressFn<'_>,
+) -> Result<(), Cancelled> {
+    let regions = regions
+        .iter()
+        .map(|region| (region, region.pressure.unwrap_or(options.pressure)))
+        .filter(|(region, pressure)| *pressure != 0.0 && !region.nodes.is_empty())
+        .collect::<Vec<_>>();
+    if regions.is_empty() {
+        return Ok(());
+    }
+
+    // the region each node is in, if any; a node in several regions is pushed by the last one
+    let mut node_regions = vec![None; g.node_count()];
+    for (i, (region, _)) in regions.iter().enumerate() {
+        for node in &region.nodes {
+            node_regions[node.index()] = Some(i);
+        }
+    }
+
+    for i in 1..=options.inflate_iterations {
+        let graph = &*g;
+        let centres = regions
+            .iter()
+            .map(|(region, _)| {
+                region.nodes.iter().map(|n| graph[*n]).sum::<Vec3>() / region.nodes.len() as f32
+            })
+            .collect::<Vec<_>>();
+
+        let hash = repulsion_hash(graph, options);
+        let new_pos = (0..graph.node_count())
+            .into_par_iter()
+            .map(NodeIndex::new)
+            .map(|n| {
+                let pressure = node_regions[n.index()].map_or(Vec3::ZERO, |region| {
+                    PRESSURE_SCALE
+                        * regions[region].1
+                        * (graph[n] - centres[region]).normalize_or_zero()
+                });
+                graph[n]
+                    + options.step_size
+                        * (layout_force(graph, hash.as_ref(), n, options) + pressure)
+            })
+            .collect::<Vec<_>>();
+
+        g.node_weights_mut().zip(new_pos).for_each(|(w, p)| *w = p);
+
+        if !progress(i as f32 / options.inflate_iterations as f32) {
+            return Err(Cancelled);
+        }
+    }
+
+    Ok(())
+}
+
+/// Scale a laid out graph so its edges are the right length on average, returning the scale applied.
+/// Shortest paths are longer than straight lines across a curved or sheared surface, so SGD tends to spread
+/// a layout out a little; this keeps its overall size true to the edge lengths.
+pub fn rescale<C: SGDCoords>(g: &mut Graph<C, f32, Undirected>) -> f32 {
+    let (rest, actual) = g
+        .edge_references()
+        .map(|e| (*e.weight(), (g[e.target()] - g[e.source()]).length()))
+        .fold((0.0, 0.0), |(rest, actual), (r, a)| (rest + r, actual + a));
+    if actual <= 0.0 {
+        return 1.0;
+    }
+
+    let scale = rest / actual;
+    g.node_weights_mut().for_each(|p| *p = *p * scale);
+    scale
 }
 
 /// Normalize a graph to be roughly in the same position each time, regardless of initial random state.
@@ -238,18 +896,260 @@ mod tests {
     fn test_sgd() {
         let pattern = test_pattern_sphere().unwrap();
 
-        let graph = sgd::<Vec3, _, _>(&*pattern.graph());
+        let graph = sgd::<Vec3, _, _>(&*pattern.graph(), &LayoutOptions::default());
 
         for w in graph.node_weights() {
             println!("{}", w);
         }
     }
 
+    #[test]
+    fn test_inflate() {
+        // A sphere laid out from its graph alone is a bit crumpled; stuffing it should fill it out
+        // without stretching its stitches much.
+        let pattern = test_pattern_sphere().unwrap();
+        let graph = pattern.triangulated_graph();
+        let options = LayoutOptions {
+            pressure: 1.0,
+            ..Default::default()
+        };
+        let mut layout = sgd::<Vec3, _, _>(&graph, &options);
+        fdg(&mut layout, &options);
+
+        // how close the nearest node is to the centre, relative to the average, which is 1 for a perfect sphere
+        let roundness = |layout: &Graph<Vec3, f32, Undirected>| {
+            let centre = layout.node_weights().sum::<Vec3>() / layout.node_count() as f32;
+            let distances = layout
+                .node_weights()
+                .map(|p| p.distance(centre))
+                .collect::<Vec<_>>();
+            let mean = distances.iter().sum::<f32>() / distances.len() as f32;
+            distances.into_iter().fold(f32::MAX, f32::min) / mean
+        };
+        // the average relative difference of each edge from its length, ignoring slip stitches and seams
+        let strain = |layout: &Graph<Vec3, f32, Undirected>| {
+            let edges = layout
+                .edge_references()
+                .filter(|e| *e.weight() > 0.5)
+                .collect::<Vec<_>>();
+            edges
+                .iter()
+                .map(|e| (layout[e.source()].distance(layout[e.target()]) / e.weight() - 1.0).abs())
+                .sum::<f32>()
+                / edges.len() as f32
+        };
+        let before = (roundness(&layout), strain(&layout));
+
+        let region = Region {
+            nodes: layout.node_indices().collect(),
+            pressure: None,
+        };
+        inflate(&mut layout, &[region], &options);
+        let after = (roundness(&layout), strain(&layout));
+
+        assert!(after.0 > before.0);
+        assert!(after.1 < before.1 * 1.5);
+    }
+
+    #[test]
+    fn test_repulsion() {
+        // two separate edges lying almost on top of each other
+        let mut graph = Graph::<Vec3, f32, Undirected>::new_undirected();
+        let a = graph.add_node(Vec3::new(-0.5, 0.0, 0.0));
+        let b = graph.add_node(Vec3::new(0.5, 0.0, 0.0));
+        let c = graph.add_node(Vec3::new(-0.5, 0.0, 0.1));
+        let d = graph.add_node(Vec3::new(0.5, 0.0, 0.1));
+        graph.add_edge(a, b, 1.0);
+        graph.add_edge(c, d, 1.0);
+        let gap = |graph: &Graph<Vec3, f32, Undirected>| {
+            ((graph[a] + graph[b]) / 2.0).distance((graph[c] + graph[d]) / 2.0)
+        };
+
+        let mut attractive = graph.clone();
+        fdg(&mut attractive, &LayoutOptions::default());
+        assert!(gap(&attractive) < 0.2);
+
+        let options = LayoutOptions {
+            repulsion: 1.0,
+            ..Default::default()
+        };
+        fdg(&mut graph, &options);
+        assert!(gap(&graph) > 0.5);
+        assert!((graph[a].distance(graph[b]) - 1.0).abs() < 0.2);
+    }
+
     #[test]
     fn test_sgd_size() {
         for i in (5..=30).step_by(5) {
             let pattern = test_pattern_flat(i).unwrap();
-            let _ = sgd::<Vec3, _, _>(&pattern.triangulated_graph());
+            let _ = sgd::<Vec3, _, _>(&pattern.triangulated_graph(), &LayoutOptions::default());
+        }
+    }
+
+    #[test]
+    fn test_sgd_seed() {
+        let pattern = test_pattern_flat(10).unwrap();
+        let graph = pattern.triangulated_graph();
+        let layout = |seed| {
+            let options = LayoutOptions {
+                seed,
+                ..Default::default()
+            };
+            let mut layout = sgd::<Vec3, _, _>(&graph, &options);
+            fdg(&mut layout, &options);
+            layout
+                .node_weights()
+                .flat_map(|p| p.to_array().map(f32::to_bits))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(layout(1), layout(1));
+        assert_ne!(layout(1), layout(2));
+    }
+
+    /// Lay out a graph with both the exact and sparse solvers, returning the stress of each.
+    fn compare_stress<N, E: Into<f32> + Clone>(graph: &Graph<N, E>) -> (f32, f32) {
+        let exact = LayoutOptions::default();
+        let sparse = LayoutOptions {
+            mode: SgdMode::Sparse { pivots: 30 },
+            ..Default::default()
+        };
+        (
+            stress(&sgd::<Vec3, _, _>(graph, &exact)),
+            stress(&sgd::<Vec3, _, _>(graph, &sparse)),
+        )
+    }
+
+    #[test]
+    fn test_sparse_stress() {
+        // The sparse approximation shouldn't be much worse than the exact solver.
+        for pattern in [
+            test_pattern_flat(20).unwrap(),
+            test_pattern_sphere().unwrap(),
+        ] {
+            let (exact, sparse) = compare_stress(&pattern.triangulated_graph());
+            assert!(sparse <= exact * 1.5, "exact: {exact}, sparse: {sparse}");
+        }
+    }
+
+    #[test]
+    #[ignore = "Compares the exact and sparse solvers on every test pattern, takes a minute to run."]
+    fn test_analyze_sparse_stress() {
+        let patterns = [
+            test_pattern_flat(20).unwrap(),
+            test_pattern_sphere().unwrap(),
+            test_pattern_spiral_rounds().unwrap(),
+            test_pattern_joined_rounds().unwrap(),
+        ];
+        for pattern in patterns {
+            let graph = pattern.triangulated_graph();
+            let (exact, sparse) = compare_stress(&graph);
+            println!(
+                "{} nodes: exact stress {exact}, sparse stress {sparse}",
+                graph.node_count()
+            );
         }
     }
+
+    #[test]
+    fn test_sparse_size() {
+        // Large enough that the exact solver would need hundreds of millions of terms.
+        let pattern = test_pattern_flat(120).unwrap();
+        let options = LayoutOptions {
+            mode: SgdMode::Sparse { pivots: 20 },
+            ..Default::default()
+        };
+        let graph = sgd::<Vec3, _, _>(&pattern.triangulated_graph(), &options);
+        assert!(graph.node_weights().all(|p| !p.is_nan()));
+    }
+
+    #[test]
+    fn test_sgd_warm() {
+        let graph = test_pattern_sphere().unwrap().triangulated_graph();
+        let options = LayoutOptions::default();
+        let cold = sgd::<Vec3, _, _>(&graph, &options);
+
+        // start from the finished layout, except for a few nodes that have to be placed again
+        let initial = cold
+            .node_weights()
+            .enumerate()
+            .map(|(i, &p)| (i % 10 != 0).then_some(p))
+            .collect::<Vec<_>>();
+        let warm = sgd_warm(&graph, &options, &initial);
+
+        assert!(stress(&warm) <= stress(&cold) * 1.1);
+        let moved = cold
+            .node_weights()
+            .zip(warm.node_weights())
+            .map(|(a, b)| (*a - *b).length())
+            .sum::<f32>()
+            / cold.node_count() as f32;
+        assert!(moved < 0.5, "nodes moved {moved} on average");
+    }
+
+    #[test]
+    fn test_sgd_cancel() {
+        let graph = test_pattern_flat(10).unwrap().triangulated_graph();
+        let options = LayoutOptions::default();
+
+        let reported = std::sync::Mutex::new(Vec::new());
+        let result = sgd_with_progress::<Vec3, _, _>(&graph, &options, &[], &|fraction| {
+            reported.lock().unwrap().push(fraction);
+            true
+        });
+        assert!(result.is_ok());
+        let reported = reported.into_inner().unwrap();
+        assert_eq!(reported.last(), Some(&1.0));
+        assert!(reported.iter().all(|f| (0.0..=1.0).contains(f)));
+
+        // cancel once the shortest paths have been found
+        let result =
+            sgd_with_progress::<Vec3, _, _>(&graph, &options, &[], &|fraction| fraction < 0.5);
+        assert_eq!(result.err(), Some(Cancelled));
+
+        let mut layout = sgd::<Vec3, _, _>(&graph, &options);
+        assert_eq!(
+            fdg_with_progress(&mut layout, &options, &|_| false),
+            Err(Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_sparse_cancel() {
+        let graph = test_pattern_flat(10).unwrap().triangulated_graph();
+        let options = LayoutOptions {
+            mode: SgdMode::Sparse { pivots: 10 },
+            ..Default::default()
+        };
+
+        // progress is reported for each pivot while the shortest paths are found
+        let reported = std::sync::Mutex::new(Vec::new());
+        let result = sgd_with_progress::<Vec3, _, _>(&graph, &options, &[], &|fraction| {
+            reported.lock().unwrap().push(fraction);
+            true
+        });
+        assert!(result.is_ok());
+        let reported = reported.into_inner().unwrap();
+        assert!(reported.iter().filter(|f| **f < 0.5).count() >= 9);
+
+        let result =
+            sgd_with_progress::<Vec3, _, _>(&graph, &options, &[], &|fraction| fraction < 0.25);
+        assert_eq!(result.err(), Some(Cancelled));
+    }
+
+    #[test]
+    fn test_hogwild() {
+        let pattern = test_pattern_flat(20).unwrap();
+        let graph = pattern.triangulated_graph();
+        let sequential = stress(&sgd::<Vec3, _, _>(&graph, &LayoutOptions::default()));
+        let options = LayoutOptions {
+            hogwild: true,
+            ..Default::default()
+        };
+        let hogwild = stress(&sgd::<Vec3, _, _>(&graph, &options));
+        assert!(
+            hogwild <= sequential * 1.5,
+            "sequential: {sequential}, hogwild: {hogwild}"
+        );
+    }
 }